@@ -18,22 +18,32 @@ extern crate pbr;
 extern crate point_viewer;
 extern crate scoped_pool;
 extern crate prost;
+extern crate rand;
 
 use pbr::ProgressBar;
 use point_viewer::{InternalIterator, Point};
+use point_viewer::compression;
+use point_viewer::duplicates::{MergeMode, merge_duplicates};
+use point_viewer::e57::E57Iterator;
 use point_viewer::errors::*;
-use point_viewer::math::{Cube, Cuboid, CuboidLike};
+use point_viewer::math::{Cube, Cuboid, CuboidLike, Matrix, Matrix4f, Vector3f};
 use point_viewer::octree;
-use point_viewer::ply::PlyIterator;
+use point_viewer::outliers::OutlierRemoval;
+use point_viewer::pcd::PcdIterator;
+use point_viewer::pipeline::{Crop, EveryNth, Filter, MaxDistance, Pipeline, RandomSubsample,
+                             RemapColors, Stage, Transform};
+use point_viewer::ply::{Mesh, PlyIterator};
 use point_viewer::proto;
-use point_viewer::pts::PtsIterator;
+use point_viewer::pts::{Columns, PtsIterator, SkippedLines, TextFormat};
 use prost::Message;
 use scoped_pool::{Pool, Scope};
 use std::collections::HashSet;
 use std::fs::{self, File};
-use std::io::{Stdout, Write};
+use std::io::{self, Stdout, Write};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::process;
+use std::sync::{Arc, Mutex, mpsc};
 
 const UPDATE_COUNT: i64 = 100000;
 const MAX_POINTS_PER_NODE: i64 = 100000;
@@ -48,7 +58,7 @@ fn split<P>(
     resolution: f64,
     node: &octree::Node,
     stream: P,
-) -> Vec<SplittedNode>
+) -> Result<Vec<SplittedNode>>
     where P: InternalIterator
 {
     let mut children: Vec<Option<octree::NodeWriter>> =
@@ -85,7 +95,7 @@ fn split<P>(
             }
             children[array_index].as_mut().unwrap().write(&p);
         }
-    );
+    )?;
 
     // Remove the node file on disk by reopening the node and immediately dropping it again without
     // writing a point. This only saves some disk space during processing - all nodes will be
@@ -107,7 +117,7 @@ fn split<P>(
             }
         );
     }
-    rv
+    Ok(rv)
 }
 
 fn should_split_node(node: &SplittedNode, resolution: f64) -> bool {
@@ -129,6 +139,65 @@ fn should_split_node(node: &SplittedNode, resolution: f64) -> bool {
     true
 }
 
+/// Merges the duplicate points in 'node' on the resolution grid starting at 'origin' and rewrites
+/// it if any were found. Returns the number of points that were removed.
+fn deduplicate_node(
+    output_directory: &Path,
+    node: &octree::Node,
+    origin: &Vector3f,
+    resolution: f64,
+    mode: MergeMode,
+) -> Result<i64> {
+    let stream = octree::NodeIterator::from_disk(output_directory, &node.id)?;
+    let num_points = stream.size_hint().unwrap() as i64;
+    let points = merge_duplicates(stream, origin, resolution, mode)?;
+    if points.len() as i64 == num_points {
+        return Ok(0);
+    }
+    let mut writer = octree::NodeWriter::new(output_directory, node, resolution);
+    for p in &points {
+        writer.write(p);
+    }
+    Ok(num_points - writer.num_written())
+}
+
+/// Merges the duplicate points in all 'leaf_nodes' of the octree spanning 'root_cube'. All leaves
+/// use the same grid, which starts at the corner of the root. Returns the number of points that
+/// were removed.
+fn deduplicate_leaves(
+    pool: &Pool,
+    output_directory: &Path,
+    resolution: f64,
+    root_cube: &Cube,
+    leaf_nodes: &[octree::Node],
+    mode: MergeMode,
+) -> Result<i64> {
+    let origin = root_cube.min();
+    let num_removed = AtomicUsize::new(0);
+    let errors = Mutex::new(Vec::new());
+    pool.scoped(
+        |scope| for leaf in leaf_nodes {
+            let origin = &origin;
+            let num_removed = &num_removed;
+            let errors = &errors;
+            scope.execute(
+                move || match deduplicate_node(output_directory, leaf, origin, resolution, mode) {
+                    Ok(num_merged) => {
+                        num_removed.fetch_add(num_merged as usize, Ordering::Relaxed);
+                    }
+                    Err(err) => errors.lock().unwrap().push(err),
+                },
+            );
+        }
+    );
+    match errors.into_inner().unwrap().into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(num_removed.into_inner() as i64),
+    }
+}
+
+/// Splits 'splitted_node' and recurses into the children that are still too big. Errors of the
+/// recursion are pushed to 'errors', since it runs on the pool.
 fn split_node<'a, 'b: 'a, P>(
     scope: &Scope<'a>,
     output_directory: &'b Path,
@@ -136,9 +205,11 @@ fn split_node<'a, 'b: 'a, P>(
     splitted_node: SplittedNode,
     stream: P,
     leaf_nodes_sender: mpsc::Sender<octree::Node>,
-) where P: InternalIterator
+    errors: &'b Mutex<Vec<Error>>,
+) -> Result<()>
+    where P: InternalIterator
 {
-    let children = split(output_directory, resolution, &splitted_node.node, stream);
+    let children = split(output_directory, resolution, &splitted_node.node, stream)?;
     let (leaf_nodes, split_nodes): (Vec<_>, Vec<_>) =
         children
             .into_iter()
@@ -148,16 +219,23 @@ fn split_node<'a, 'b: 'a, P>(
         let leaf_nodes_sender_clone = leaf_nodes_sender.clone();
         scope.recurse(
             move |scope| {
-                let stream = octree::NodeIterator::from_disk(output_directory, &child.node.id)
-                    .unwrap();
-                split_node(
-                    scope,
-                    output_directory,
-                    resolution,
-                    child,
-                    stream,
-                    leaf_nodes_sender_clone,
-                );
+                let result = octree::NodeIterator::from_disk(output_directory, &child.node.id)
+                    .and_then(
+                        |stream| {
+                            split_node(
+                                scope,
+                                output_directory,
+                                resolution,
+                                child,
+                                stream,
+                                leaf_nodes_sender_clone,
+                                errors,
+                            )
+                        }
+                    );
+                if let Err(err) = result {
+                    errors.lock().unwrap().push(err);
+                }
             }
         );
     }
@@ -165,6 +243,7 @@ fn split_node<'a, 'b: 'a, P>(
     for splitted_node in leaf_nodes {
         leaf_nodes_sender.send(splitted_node.node).unwrap();
     }
+    Ok(())
 }
 
 fn subsample_children_into(
@@ -185,7 +264,7 @@ fn subsample_children_into(
         // We read all points into memory, because the new node writer will rewrite this child's
         // file(s).
         let mut points = Vec::with_capacity(node_iterator.size_hint().unwrap());
-        node_iterator.for_each(|p| points.push((*p).clone()));
+        node_iterator.for_each(|p| points.push((*p).clone()))?;
 
         let mut child_writer = octree::NodeWriter::new(output_directory, &child, resolution);
         for (idx, p) in points.into_iter().enumerate() {
@@ -199,15 +278,295 @@ fn subsample_children_into(
     Ok(())
 }
 
+/// Reads all points of the node 'id' into memory.
+fn read_points(output_directory: &Path, id: &octree::NodeId) -> Result<Vec<Point>> {
+    octree::NodeIterator::from_disk(output_directory, id)?
+        .into_iter()
+        .collect()
+}
+
+/// Finds the leaves around a leaf by their node ids, without looking at all leaves.
+struct LeafNeighbours {
+    root_cube: Cube,
+    leaf_ids: HashSet<octree::NodeId>,
+    // The ancestors of all leaves, i.e. the nodes that were split.
+    split_ids: HashSet<octree::NodeId>,
+}
+
+impl LeafNeighbours {
+    fn new(root_cube: Cube, leaf_nodes: &[octree::Node]) -> Self {
+        let mut split_ids = HashSet::new();
+        for leaf in leaf_nodes {
+            let mut ancestor = leaf.parent();
+            while let Some(node) = ancestor {
+                if !split_ids.insert(node.id) {
+                    break;
+                }
+                ancestor = node.parent();
+            }
+        }
+        LeafNeighbours {
+            root_cube: root_cube,
+            leaf_ids: leaf_nodes.iter().map(|n| n.id).collect(),
+            split_ids: split_ids,
+        }
+    }
+
+    /// Returns the node on 'level' that contains 'p', or None if 'p' is outside of the octree.
+    fn node_containing(&self, level: usize, p: &Vector3f) -> Option<octree::Node> {
+        if !self.root_cube.contains(p) {
+            return None;
+        }
+        let mut node = octree::Node::root_with_bounding_cube(self.root_cube.clone());
+        for _ in 0..level {
+            let child_index = node.get_child_id_containing_point(p);
+            node = node.get_child(child_index);
+        }
+        Some(node)
+    }
+
+    /// Returns the leaves other than 'leaf' that intersect 'halo_box'. 'halo_box' must not reach
+    /// further than the nodes of the same size next to 'leaf'.
+    fn find(&self, leaf: &octree::Node, halo_box: &Cuboid) -> Vec<octree::Node> {
+        let mut neighbours = Vec::new();
+        let mut seen = HashSet::new();
+        let edge_length = leaf.bounding_cube.edge_length();
+        let center = leaf.bounding_cube.center();
+        for dx in -1..2 {
+            for dy in -1..2 {
+                for dz in -1..2 {
+                    if (dx, dy, dz) == (0, 0, 0) {
+                        continue;
+                    }
+                    let offset = Vector3f::new(dx as f32, dy as f32, dz as f32) * edge_length;
+                    if let Some(node) = self.node_containing(leaf.level(), &(center + offset)) {
+                        self.collect_leaves(node, halo_box, &mut seen, &mut neighbours);
+                    }
+                }
+            }
+        }
+        neighbours
+    }
+
+    /// Adds the leaf that is or contains 'node', or the leaves below 'node' that intersect
+    /// 'halo_box'.
+    fn collect_leaves(
+        &self,
+        node: octree::Node,
+        halo_box: &Cuboid,
+        seen: &mut HashSet<octree::NodeId>,
+        leaves: &mut Vec<octree::Node>,
+    ) {
+        if self.split_ids.contains(&node.id) {
+            for i in 0..8 {
+                let child = node.get_child(octree::ChildIndex::from_u8(i));
+                if halo_box.intersects(&child.bounding_cube) {
+                    self.collect_leaves(child, halo_box, seen, leaves);
+                }
+            }
+            return;
+        }
+        // The node is a leaf, part of a bigger leaf or empty.
+        let mut ancestor = Some(node);
+        while let Some(node) = ancestor {
+            if self.leaf_ids.contains(&node.id) {
+                if seen.insert(node.id) {
+                    leaves.push(node);
+                }
+                return;
+            }
+            if self.split_ids.contains(&node.id) {
+                return;
+            }
+            ancestor = node.parent();
+        }
+    }
+}
+
+/// Finds the outliers of 'leaf', see 'remove_outliers'.
+fn find_outliers(
+    output_directory: &Path,
+    leaf: &octree::Node,
+    neighbours: &LeafNeighbours,
+    outlier_removal: &OutlierRemoval,
+) -> Result<Vec<usize>> {
+    let halo_size = leaf.bounding_cube.edge_length() / 8.;
+    let halo_offset = Vector3f::new(halo_size, halo_size, halo_size);
+    let halo_box = Cuboid::from_min_max(
+        leaf.bounding_cube.min() - halo_offset,
+        leaf.bounding_cube.max() + halo_offset,
+    );
+    let points = read_points(output_directory, &leaf.id)?;
+    let mut halo = Vec::new();
+    for other in neighbours.find(leaf, &halo_box) {
+        octree::NodeIterator::from_disk(output_directory, &other.id)?
+            .for_each(
+                |p| if halo_box.contains(&p.position) {
+                    halo.push(p.clone());
+                }
+            )?;
+    }
+    Ok(outlier_removal.find_outliers(&points, &halo))
+}
+
+/// Removes statistical outliers from all 'leaf_nodes' of the octree spanning 'root_cube'. Points
+/// in neighbouring leaves that are close to the border of a leaf are considered as neighbours too.
+/// Returns the number of points that were removed.
+fn remove_outliers(
+    pool: &Pool,
+    output_directory: &Path,
+    resolution: f64,
+    root_cube: &Cube,
+    leaf_nodes: &[octree::Node],
+    outlier_removal: &OutlierRemoval,
+) -> Result<i64> {
+    let neighbours = LeafNeighbours::new(root_cube.clone(), leaf_nodes);
+    // We first find all outliers and only then rewrite the nodes, so that the halo is always read
+    // from unmodified nodes.
+    let (outliers_sender, outliers_receiver) = mpsc::channel();
+    pool.scoped(
+        |scope| for (leaf_index, leaf) in leaf_nodes.iter().enumerate() {
+            let outliers_sender = outliers_sender.clone();
+            let neighbours = &neighbours;
+            scope.execute(
+                move || {
+                    let result =
+                        find_outliers(output_directory, leaf, neighbours, outlier_removal);
+                    outliers_sender.send((leaf_index, result)).unwrap();
+                }
+            );
+        }
+    );
+    drop(outliers_sender);
+
+    let mut leaves_with_outliers = Vec::new();
+    for (leaf_index, result) in outliers_receiver {
+        let outliers = result?;
+        if !outliers.is_empty() {
+            leaves_with_outliers.push((leaf_index, outliers));
+        }
+    }
+    let num_removed = leaves_with_outliers
+        .iter()
+        .map(|&(_, ref outliers)| outliers.len() as i64)
+        .sum();
+
+    let errors = Mutex::new(Vec::new());
+    pool.scoped(
+        |scope| for &(leaf_index, ref outliers) in &leaves_with_outliers {
+            let errors = &errors;
+            scope.execute(
+                move || {
+                    let leaf = &leaf_nodes[leaf_index];
+                    let points = match read_points(output_directory, &leaf.id) {
+                        Ok(points) => points,
+                        Err(err) => {
+                            errors.lock().unwrap().push(err);
+                            return;
+                        }
+                    };
+                    // 'outliers' is sorted, so we can walk it alongside the points.
+                    let mut outliers = outliers.iter().peekable();
+                    let mut writer = octree::NodeWriter::new(output_directory, leaf, resolution);
+                    for (index, p) in points.iter().enumerate() {
+                        if outliers.peek() == Some(&&index) {
+                            outliers.next();
+                            continue;
+                        }
+                        writer.write(p);
+                    }
+                }
+            );
+        }
+    );
+    match errors.into_inner().unwrap().into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(num_removed),
+    }
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum InputFormat {
+    Ply,
+    Pts,
+    Xyz,
+    Csv,
+    E57,
+    Pcd,
+}
+
+impl InputFormat {
+    /// True for the formats with one point per line of text.
+    fn is_text(&self) -> bool {
+        match *self {
+            InputFormat::Pts | InputFormat::Xyz | InputFormat::Csv => true,
+            InputFormat::Ply | InputFormat::E57 | InputFormat::Pcd => false,
+        }
+    }
+
+    fn from_str(format: &str) -> Option<Self> {
+        match format {
+            "ply" => Some(InputFormat::Ply),
+            "pts" => Some(InputFormat::Pts),
+            "xyz" => Some(InputFormat::Xyz),
+            "csv" => Some(InputFormat::Csv),
+            "e57" => Some(InputFormat::E57),
+            "pcd" => Some(InputFormat::Pcd),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
-enum InputFile {
-    Ply(PathBuf),
-    Pts(PathBuf),
+struct InputFile {
+    filename: PathBuf,
+    format: InputFormat,
+}
+
+/// Counts the points that were inside and outside of the bounding box given by the user.
+#[derive(Debug,Default)]
+struct BoundsCheckCounts {
+    num_inside: AtomicUsize,
+    num_outside: AtomicUsize,
+    // Reported if the bounding box is strict.
+    first_outside: Mutex<Option<Vector3f>>,
+}
+
+/// Checks that points are inside the bounding box given by the user, which is used instead of
+/// determining it from the input. Points outside are dropped and counted.
+struct BoundsCheck {
+    bounding_box: Cuboid,
+    counts: Arc<BoundsCheckCounts>,
+}
+
+impl Stage for BoundsCheck {
+    fn process(&mut self, point: &mut Point) -> bool {
+        if self.bounding_box.contains(&point.position) {
+            self.counts.num_inside.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        if self.counts.num_outside.fetch_add(1, Ordering::Relaxed) == 0 {
+            *self.counts.first_outside.lock().unwrap() = Some(point.position);
+        }
+        false
+    }
+}
+
+/// Copies everything from stdin into 'path', so that the input can be read more than once.
+fn spool_stdin(path: &Path) -> Result<u64> {
+    let mut file = File::create(path)
+        .chain_err(|| format!("Could not create {}.", path.display()))?;
+    let stdin = io::stdin();
+    let num_bytes = io::copy(&mut stdin.lock(), &mut file)?;
+    Ok(num_bytes)
 }
 
 enum InputFileIterator {
     Ply(PlyIterator),
     Pts(PtsIterator),
+    E57(E57Iterator),
+    Pcd(PcdIterator),
+    Points(Vec<Point>),
 }
 
 impl InternalIterator for InputFileIterator {
@@ -215,35 +574,159 @@ impl InternalIterator for InputFileIterator {
         match *self {
             InputFileIterator::Ply(ref p) => p.size_hint(),
             InputFileIterator::Pts(ref p) => p.size_hint(),
+            InputFileIterator::E57(ref p) => p.size_hint(),
+            InputFileIterator::Pcd(ref p) => p.size_hint(),
+            InputFileIterator::Points(ref p) => p.size_hint(),
         }
     }
 
-    fn for_each<F: FnMut(&Point)>(self, f: F) {
+    fn for_each<F: FnMut(&Point)>(self, f: F) -> Result<()> {
         match self {
             InputFileIterator::Ply(p) => p.for_each(f),
             InputFileIterator::Pts(p) => p.for_each(f),
+            InputFileIterator::E57(p) => p.for_each(f),
+            InputFileIterator::Pcd(p) => p.for_each(f),
+            InputFileIterator::Points(p) => p.for_each(f),
         }
     }
 }
 
-fn make_stream(input: &InputFile) -> (InputFileIterator, Option<pbr::ProgressBar<Stdout>>) {
-    let stream = match *input {
-        InputFile::Ply(ref filename) => InputFileIterator::Ply(PlyIterator::new(filename).unwrap()),
-        InputFile::Pts(ref filename) => InputFileIterator::Pts(PtsIterator::new(filename)),
+/// Options that decide how the input is read and which points end up in the octree.
+#[derive(Debug)]
+struct InputOptions {
+    skip_invalid_records: bool,
+    // Maximum distance from the origin of the input, i.e. usually the scanner.
+    max_distance: Option<f32>,
+    transform: Option<Matrix4f>,
+    // Applied after 'transform'.
+    crop: Option<Cuboid>,
+    every_nth: Option<usize>,
+    keep_fraction: Option<f32>,
+    color_gain: Option<f32>,
+    // Points with exactly this color are dropped, before 'color_gain' is applied.
+    drop_color: Option<(u8, u8, u8)>,
+    // Overrides of the layout of text inputs.
+    text_columns: Option<Columns>,
+    text_delimiter: Option<char>,
+    text_header_lines: Option<usize>,
+    default_color: Option<(u8, u8, u8)>,
+    // Spacing of the points sampled on the faces of PLY meshes.
+    sample_mesh: Option<f32>,
+}
+
+// The points of the input, a progress bar if their number is known and, for text inputs, the
+// counts of the lines that were skipped while reading.
+type InputStream = (Pipeline<InputFileIterator>,
+                    Option<pbr::ProgressBar<Stdout>>,
+                    Option<Arc<SkippedLines>>);
+
+fn make_stream(input: &InputFile, options: &InputOptions) -> Result<InputStream> {
+    let is_stdin = input.filename == Path::new("-");
+    let mut skipped_lines = None;
+    let stream = match input.format {
+        InputFormat::Ply if options.sample_mesh.is_some() => {
+            let mesh = if is_stdin {
+                Mesh::from_reader(Box::new(io::stdin()), "<stdin>")?
+            } else {
+                Mesh::from_file(&input.filename)?
+            };
+            InputFileIterator::Points(mesh.sample_points(options.sample_mesh.unwrap()))
+        }
+        InputFormat::Ply if is_stdin => {
+            InputFileIterator::Ply(PlyIterator::from_reader(Box::new(io::stdin()), "<stdin>")?)
+        }
+        InputFormat::Ply => InputFileIterator::Ply(PlyIterator::new(&input.filename)?),
+        InputFormat::Pcd if is_stdin => {
+            InputFileIterator::Pcd(PcdIterator::from_reader(Box::new(io::stdin()), "<stdin>")?)
+        }
+        InputFormat::Pcd => InputFileIterator::Pcd(PcdIterator::new(&input.filename)?),
+        InputFormat::E57 => {
+            if is_stdin || compression::Compression::from_path(&input.filename) !=
+                           compression::Compression::None {
+                return Err(
+                    ErrorKind::InvalidInput(
+                        "E57 files can only be read from uncompressed files.".to_string(),
+                    )
+                            .into()
+                );
+            }
+            InputFileIterator::E57(E57Iterator::new(&input.filename)?)
+        }
+        InputFormat::Pts | InputFormat::Xyz | InputFormat::Csv => {
+            let mut text_format = match input.format {
+                InputFormat::Pts => TextFormat::pts(),
+                InputFormat::Csv => {
+                    TextFormat {
+                        delimiter: Some(','),
+                        ..TextFormat::xyz()
+                    }
+                }
+                _ => TextFormat::xyz(),
+            };
+            if let Some(ref columns) = options.text_columns {
+                text_format.columns = columns.clone();
+                // The number of values is given by the columns now, count lines are still
+                // skipped.
+                text_format.num_values.clear();
+            }
+            if let Some(delimiter) = options.text_delimiter {
+                text_format.delimiter = Some(delimiter);
+            }
+            if let Some(num_header_lines) = options.text_header_lines {
+                text_format.num_header_lines = num_header_lines;
+            }
+            if let Some(default_color) = options.default_color {
+                text_format.default_color = default_color;
+            }
+            let pts = if is_stdin {
+                PtsIterator::from_reader(Box::new(io::stdin()), "<stdin>")
+            } else {
+                PtsIterator::new(&input.filename)?
+            };
+            let mut pts = pts.format(text_format);
+            if options.skip_invalid_records {
+                pts = pts.skip_invalid_lines();
+            }
+            skipped_lines = Some(pts.skipped_lines());
+            InputFileIterator::Pts(pts)
+        }
     };
 
+    let mut stream = Pipeline::new(stream);
+    if let Some(max_distance) = options.max_distance {
+        stream = stream.add_stage(MaxDistance::new(Vector3f::new(0., 0., 0.), max_distance));
+    }
+    if let Some(matrix) = options.transform {
+        stream = stream.add_stage(Transform::new(matrix));
+    }
+    if let Some(ref crop) = options.crop {
+        stream = stream.add_stage(Crop::new(crop.clone()));
+    }
+    if let Some(n) = options.every_nth {
+        stream = stream.add_stage(EveryNth::new(n));
+    }
+    if let Some(fraction) = options.keep_fraction {
+        stream = stream.add_stage(RandomSubsample::new(fraction));
+    }
+    if let Some((r, g, b)) = options.drop_color {
+        stream = stream.add_stage(Filter::new(move |p: &Point| (p.r, p.g, p.b) != (r, g, b)));
+    }
+    if let Some(gain) = options.color_gain {
+        stream = stream.add_stage(RemapColors::with_gain(gain));
+    }
+
     let progress_bar = match stream.size_hint() {
         Some(size) => Some(ProgressBar::new(size as u64)),
         None => None,
     };
-    (stream, progress_bar)
+    Ok((stream, progress_bar, skipped_lines))
 }
 
 /// Returns the bounding_cube and the number of the points in 'input'.
-fn find_bounding_cube(input: &InputFile) -> (Cube, i64) {
+fn find_bounding_cube(input: &InputFile, options: &InputOptions) -> Result<(Cube, i64)> {
     let mut num_points = 0i64;
     let mut bounding_cube = Cuboid::new();
-    let (stream, mut progress_bar) = make_stream(input);
+    let (stream, mut progress_bar, _) = make_stream(input, options)?;
     progress_bar
         .as_mut()
         .map(|pb| pb.message("Determining bounding box: "));
@@ -256,9 +739,213 @@ fn find_bounding_cube(input: &InputFile) -> (Cube, i64) {
                 progress_bar.as_mut().map(|pb| pb.add(UPDATE_COUNT as u64));
             }
         }
-    );
+    )?;
     progress_bar.map(|mut f| f.finish());
-    (bounding_cube.to_cube(), num_points)
+    Ok((bounding_cube.to_cube(), num_points))
+}
+
+/// Parses a comma separated list of exactly 'len' floats given for the argument 'name'.
+fn parse_floats(value: &str, name: &str, len: usize) -> Vec<f32> {
+    let values: Vec<f32> = value
+        .split(',')
+        .map(
+            |s| {
+                s.trim()
+                    .parse::<f32>()
+                    .expect(&format!("{} could not be parsed as floats.", name))
+            }
+        )
+        .collect();
+    if values.len() != len {
+        panic!("{} needs exactly {} values, got {}.", name, len, values.len());
+    }
+    values
+}
+
+/// Parses a color given as 'r,g,b' for the argument 'name'.
+fn parse_color(value: &str, name: &str) -> (u8, u8, u8) {
+    let c: Vec<u8> = value
+        .split(',')
+        .map(
+            |s| {
+                s.trim()
+                    .parse::<u8>()
+                    .expect(&format!("{} could not be parsed as colors.", name))
+            }
+        )
+        .collect();
+    if c.len() != 3 {
+        panic!("{} needs exactly 3 values, got {}.", name, c.len());
+    }
+    (c[0], c[1], c[2])
+}
+
+/// Parses a box given as 'min_x,min_y,min_z,max_x,max_y,max_z' for the argument 'name'.
+fn parse_cuboid(value: &str, name: &str) -> Result<Cuboid> {
+    let v = parse_floats(value, name, 6);
+    let min = Vector3f::new(v[0], v[1], v[2]);
+    let max = Vector3f::new(v[3], v[4], v[5]);
+    if !(min.x <= max.x && min.y <= max.y && min.z <= max.z) {
+        return Err(
+            ErrorKind::InvalidInput(
+                format!("The min of --{} must not be larger than its max.", name),
+            )
+                    .into()
+        );
+    }
+    Ok(Cuboid::from_min_max(min, max))
+}
+
+impl InputOptions {
+    fn from_matches(matches: &clap::ArgMatches) -> Result<Self> {
+        let crop = match matches.value_of("crop") {
+            Some(value) => Some(parse_cuboid(value, "crop")?),
+            None => None,
+        };
+        let text_columns = match matches.value_of("columns") {
+            Some(value) => Some(value.parse::<Columns>().chain_err(|| "Invalid --columns.")?),
+            None => None,
+        };
+        Ok(
+            InputOptions {
+                skip_invalid_records: matches.is_present("skip_invalid_records"),
+                max_distance: matches
+                    .value_of("max_distance")
+                    .map(|v| v.parse::<f32>().expect("max_distance could not be parsed as float.")),
+                transform: matches
+                    .value_of("transform")
+                    .map(
+                        |v| {
+                            let e = parse_floats(v, "transform", 16);
+                            // The matrix is given row major, cgmath expects column major.
+                            Matrix4f::new(
+                                e[0],
+                                e[1],
+                                e[2],
+                                e[3],
+                                e[4],
+                                e[5],
+                                e[6],
+                                e[7],
+                                e[8],
+                                e[9],
+                                e[10],
+                                e[11],
+                                e[12],
+                                e[13],
+                                e[14],
+                                e[15],
+                            )
+                                    .transpose()
+                        }
+                    ),
+                crop: crop,
+                every_nth: matches
+                    .value_of("every_nth")
+                    .map(
+                        |v| {
+                            v.parse::<usize>()
+                                .expect("every_nth could not be parsed as integer.")
+                        }
+                    ),
+                keep_fraction: matches
+                    .value_of("keep_fraction")
+                    .map(
+                        |v| {
+                            v.parse::<f32>()
+                                .expect("keep_fraction could not be parsed as float.")
+                        }
+                    ),
+                color_gain: matches
+                    .value_of("color_gain")
+                    .map(|v| v.parse::<f32>().expect("color_gain could not be parsed as float.")),
+                text_columns: text_columns,
+                text_delimiter: matches
+                    .value_of("delimiter")
+                    .map(
+                        |v| match v {
+                            "tab" => '\t',
+                            "space" => ' ',
+                            _ if v.chars().count() == 1 => v.chars().next().unwrap(),
+                            _ => panic!("delimiter must be a single character, 'tab' or 'space'."),
+                        }
+                    ),
+                text_header_lines: matches
+                    .value_of("header_lines")
+                    .map(
+                        |v| {
+                            v.parse::<usize>()
+                                .expect("header_lines could not be parsed as integer.")
+                        }
+                    ),
+                default_color: matches
+                    .value_of("default_color")
+                    .map(|v| parse_color(v, "default_color")),
+                drop_color: matches
+                    .value_of("drop_color")
+                    .map(|v| parse_color(v, "drop_color")),
+                sample_mesh: matches
+                    .value_of("sample_mesh")
+                    .map(
+                        |v| {
+                            let spacing = v.parse::<f32>()
+                                .expect("sample_mesh could not be parsed as float.");
+                            if spacing <= 0. {
+                                panic!("sample_mesh must be positive.");
+                            }
+                            spacing
+                        }
+                    ),
+            }
+        )
+    }
+}
+
+fn print_skipped_lines(input: &InputFile, skipped_lines: &SkippedLines) {
+    if skipped_lines.num_ignored() > 0 {
+        println!(
+            "Skipped {} lines with an unexpected number of values in {}.",
+            skipped_lines.num_ignored(),
+            input.filename.display()
+        );
+    }
+    if skipped_lines.num_invalid() > 0 {
+        println!(
+            "Skipped {} invalid lines in {}.",
+            skipped_lines.num_invalid(),
+            input.filename.display()
+        );
+    }
+}
+
+/// Returns the first of the 'errors' collected on the pool, if any.
+fn first_error(errors: Mutex<Vec<Error>>) -> Result<()> {
+    match errors.into_inner().unwrap().into_iter().next() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Removes 'meta.pb' and the files of 'nodes' from 'directory', ignoring files that are gone.
+fn remove_octree_files(directory: &Path, nodes: &[octree::Node]) {
+    let _ = fs::remove_file(directory.join("meta.pb"));
+    for node in nodes {
+        let stem = node.id.get_stem(directory);
+        for extension in &[octree::POSITION_EXT, octree::COLOR_EXT, octree::META_EXT] {
+            let _ = fs::remove_file(stem.with_extension(extension));
+        }
+    }
+}
+
+/// Removes the file at 'path' when it goes out of scope, also if building fails.
+struct TemporaryFile {
+    path: PathBuf,
+}
+
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
 }
 
 fn main() {
@@ -277,14 +964,150 @@ fn main() {
                     )
                     .long("resolution")
                     .default_value("0.001"),
+                clap::Arg::with_name("skip_invalid_records")
+                    .help(
+                        "Skip lines of PTS, XYZ and CSV input files that cannot be parsed \
+                           instead of aborting. The number of skipped lines is reported. Other \
+                           formats are rejected with this flag."
+                    )
+                    .long("skip_invalid_records"),
+                clap::Arg::with_name("columns")
+                    .help(
+                        "Layout of the values of text input files as comma separated list of \
+                           'x', 'y', 'z', 'r', 'g', 'b' and '_' for ignored values, e.g. \
+                           'x,y,z,_,r,g,b'."
+                    )
+                    .long("columns")
+                    .takes_value(true),
+                clap::Arg::with_name("delimiter")
+                    .help(
+                        "Character separating the values of text input files, or 'tab' or \
+                           'space'. By default, any number of spaces, tabs or commas."
+                    )
+                    .long("delimiter")
+                    .takes_value(true),
+                clap::Arg::with_name("header_lines")
+                    .help("Number of lines to ignore at the beginning of text input files.")
+                    .long("header_lines")
+                    .takes_value(true),
+                clap::Arg::with_name("default_color")
+                    .help("Color as r,g,b for points of text input files without colors.")
+                    .long("default_color")
+                    .takes_value(true),
+                clap::Arg::with_name("sample_mesh")
+                    .help(
+                        "Add points sampled randomly on the faces of PLY meshes, on average one \
+                           per this spacing squared, to densify sparse meshes."
+                    )
+                    .long("sample_mesh")
+                    .takes_value(true),
+                clap::Arg::with_name("max_distance")
+                    .help(
+                        "Drop points that are further than this away from the origin of the \
+                           input, i.e. the scanner. Applied before --transform."
+                    )
+                    .long("max_distance")
+                    .takes_value(true),
+                clap::Arg::with_name("transform")
+                    .help(
+                        "Transform all points by this 4x4 matrix, given as 16 comma separated \
+                           values in row major order, e.g. the pose of the scanner."
+                    )
+                    .long("transform")
+                    .takes_value(true),
+                clap::Arg::with_name("crop")
+                    .help(
+                        "Only keep points inside this box, given as \
+                           'min_x,min_y,min_z,max_x,max_y,max_z'. Applied after --transform."
+                    )
+                    .long("crop")
+                    .takes_value(true),
+                clap::Arg::with_name("every_nth")
+                    .help("Only keep every n-th point.")
+                    .long("every_nth")
+                    .takes_value(true),
+                clap::Arg::with_name("keep_fraction")
+                    .help("Only keep a random subset of this fraction of the points, e.g. 0.1.")
+                    .long("keep_fraction")
+                    .takes_value(true),
+                clap::Arg::with_name("drop_color")
+                    .help(
+                        "Drop points with exactly this color given as r,g,b, e.g. '0,0,0' for \
+                           points the scanner could not color."
+                    )
+                    .long("drop_color")
+                    .takes_value(true),
+                clap::Arg::with_name("color_gain")
+                    .help("Multiply all colors by this factor, e.g. to brighten dark scans.")
+                    .long("color_gain")
+                    .takes_value(true),
+                clap::Arg::with_name("remove_outliers")
+                    .help(
+                        "Remove points whose mean distance to their nearest neighbours is much \
+                           larger than for the other points in the same leaf node."
+                    )
+                    .long("remove_outliers"),
+                clap::Arg::with_name("outlier_neighbours")
+                    .help("Number of nearest neighbours considered by --remove_outliers.")
+                    .long("outlier_neighbours")
+                    .default_value("8"),
+                clap::Arg::with_name("outlier_std_ratio")
+                    .help(
+                        "Points whose mean neighbour distance is more than this many standard \
+                           deviations above the mean are removed by --remove_outliers."
+                    )
+                    .long("outlier_std_ratio")
+                    .default_value("2"),
+                clap::Arg::with_name("merge_duplicates")
+                    .help(
+                        "Merge points that fall into the same cell of the resolution grid, \
+                           either by averaging them or by keeping the first one."
+                    )
+                    .long("merge_duplicates")
+                    .takes_value(true)
+                    .possible_values(&["average", "first"]),
+                clap::Arg::with_name("bounding_box")
+                    .help(
+                        "Use this box, given as min_x,min_y,min_z,max_x,max_y,max_z, as extent \
+                           of the octree instead of reading the input twice to determine it. \
+                           Points outside of it are dropped."
+                    )
+                    .long("bounding_box")
+                    .takes_value(true),
+                clap::Arg::with_name("strict_bounding_box")
+                    .help("Fail instead of dropping points outside of --bounding_box.")
+                    .long("strict_bounding_box")
+                    .requires("bounding_box"),
+                clap::Arg::with_name("input_format")
+                    .help(
+                        "Format of the input. Defaults to the extension of the input file and \
+                           is required when reading from stdin."
+                    )
+                    .long("input_format")
+                    .takes_value(true)
+                    .possible_values(&["ply", "pts", "xyz", "csv", "e57", "pcd"]),
                 clap::Arg::with_name("input")
-                    .help("PLY/PTS file to parse for the points.")
+                    .help(
+                        "PLY/PTS/XYZ/CSV/E57/PCD file to parse for the points, or '-' to read \
+                           from stdin. PLY files have to be binary little endian. Files ending \
+                           in .gz or .zst are decompressed on the fly."
+                    )
                     .index(1)
                     .required(true),
             ]
         )
         .get_matches();
 
+    if let Err(err) = build_octree(&matches) {
+        println!("Could not build octree: {}", err);
+        for cause in err.iter().skip(1) {
+            println!("Caused by: {}", cause);
+        }
+        process::exit(1);
+    }
+}
+
+fn build_octree(matches: &clap::ArgMatches) -> Result<()> {
     let output_directory = &PathBuf::from(matches.value_of("output_directory").unwrap());
     let resolution = matches
         .value_of("resolution")
@@ -292,19 +1115,104 @@ fn main() {
         .parse::<f64>()
         .expect("resolution could not be parsed as float.");
 
-    let input = {
+    // Ignore errors, maybe directory is already there.
+    let _ = fs::create_dir(output_directory);
+
+    let mut input = {
         let filename = PathBuf::from(matches.value_of("input").unwrap());
-        match filename.extension().and_then(|s| s.to_str()) {
-            Some("ply") => InputFile::Ply(filename.clone()),
-            Some("pts") => InputFile::Pts(filename.clone()),
-            other => panic!("Unknown input file format: {:?}", other),
+        let format = match matches.value_of("input_format") {
+            Some(format) => InputFormat::from_str(format).unwrap(),
+            None if filename == Path::new("-") => {
+                panic!("--input_format is required when reading from stdin.")
+            }
+            None => {
+                // Compressed files are detected by the extension before the compression one.
+                match compression::uncompressed_path(&filename)
+                          .extension()
+                          .and_then(|s| s.to_str()) {
+                    Some(extension) => {
+                        InputFormat::from_str(extension)
+                            .expect(&format!("Unknown input file format: {}", extension))
+                    }
+                    None => panic!("Unknown input file format: {}", filename.display()),
+                }
+            }
+        };
+        InputFile {
+            filename: filename,
+            format: format,
         }
     };
+    if matches.is_present("skip_invalid_records") && !input.format.is_text() {
+        return Err(
+            ErrorKind::InvalidInput(
+                "--skip_invalid_records is only supported for PTS, XYZ and CSV input.".to_string(),
+            )
+                    .into()
+        );
+    }
+
+    let bounding_box = match matches.value_of("bounding_box") {
+        Some(value) => Some(parse_cuboid(value, "bounding_box")?),
+        None => None,
+    };
 
-    let (bounding_cube, num_points) = find_bounding_cube(&input);
+    // Without a bounding box we need to read the input twice, so we first copy stdin to disk.
+    let mut spooled_input = None;
+    if input.filename == Path::new("-") && bounding_box.is_none() {
+        let spool = TemporaryFile { path: output_directory.join("stdin.spool") };
+        println!("Copying stdin to {}.", spool.path.display());
+        spool_stdin(&spool.path).chain_err(|| "Could not read input.")?;
+        input.filename = spool.path.clone();
+        // Kept until the end, where it is removed.
+        spooled_input = Some(spool);
+    }
 
-    // Ignore errors, maybe directory is already there.
-    let _ = fs::create_dir(output_directory);
+    let input_options = InputOptions::from_matches(matches)?;
+    let outlier_removal = if matches.is_present("remove_outliers") {
+        Some(
+            OutlierRemoval {
+                k: matches
+                    .value_of("outlier_neighbours")
+                    .unwrap()
+                    .parse::<usize>()
+                    .expect("outlier_neighbours could not be parsed as integer."),
+                std_ratio: matches
+                    .value_of("outlier_std_ratio")
+                    .unwrap()
+                    .parse::<f32>()
+                    .expect("outlier_std_ratio could not be parsed as float."),
+            }
+        )
+    } else {
+        None
+    };
+    let merge_mode = matches
+        .value_of("merge_duplicates")
+        .map(
+            |mode| match mode {
+                "average" => MergeMode::Average,
+                "first" => MergeMode::KeepFirst,
+                _ => unreachable!(),
+            },
+        );
+    let bounds_check_counts = Arc::new(BoundsCheckCounts::default());
+    let (bounding_cube, bounds_check, mut num_points) = match bounding_box.clone() {
+        Some(bounding_box) => {
+            let bounding_cube = bounding_box.clone().to_cube();
+            let bounds_check = BoundsCheck {
+                bounding_box: bounding_box,
+                counts: bounds_check_counts.clone(),
+            };
+            // The number of points is only known after the input has been read.
+            (bounding_cube, Some(bounds_check), 0)
+        }
+        None => {
+            let (bounding_cube, num_points) = find_bounding_cube(&input, &input_options)
+                .chain_err(|| "Could not read input.")?;
+            (bounding_cube, None, num_points)
+        }
+    };
 
     let meta = proto::Meta {
         bounding_cube: Some(
@@ -321,35 +1229,58 @@ fn main() {
         ),
         resolution: Some(resolution),
         version: Some(octree::CURRENT_VERSION),
+        build_id: Some(rand::random()),
     };
     let mut encoded = Vec::new();
     meta.encode(&mut encoded).unwrap();
     File::create(&output_directory.join("meta.pb"))
-        .unwrap()
-        .write_all(&encoded)
-        .unwrap();
+        .and_then(|mut file| file.write_all(&encoded))
+        .chain_err(|| "Could not write meta.pb.")?;
 
     println!("Creating octree structure.");
     let pool = Pool::new(10);
 
     let (leaf_nodes_sender, leaf_nodes_receiver) = mpsc::channel();
-    pool.scoped(
-        move |scope| {
-            let (root_stream, _) = make_stream(&input);
-            let root = SplittedNode {
-                node: octree::Node::root_with_bounding_cube(bounding_cube),
-                num_points: num_points,
-            };
-            split_node(
-                scope,
-                output_directory,
-                resolution,
-                root,
-                root_stream,
-                leaf_nodes_sender.clone(),
-            );
-        }
-    );
+    let errors = Mutex::new(Vec::new());
+    let mut skipped_lines = None;
+    {
+        let errors = &errors;
+        let skipped_lines = &mut skipped_lines;
+        let input = &input;
+        let bounding_cube = bounding_cube.clone();
+        pool.scoped(
+            move |scope| {
+                let result = make_stream(input, &input_options).and_then(
+                    |(mut root_stream, _, input_skipped_lines)| {
+                        *skipped_lines = input_skipped_lines;
+                        if let Some(bounds_check) = bounds_check {
+                            root_stream = root_stream.add_stage(bounds_check);
+                        }
+                        let root = SplittedNode {
+                            node: octree::Node::root_with_bounding_cube(bounding_cube),
+                            num_points: num_points,
+                        };
+                        split_node(
+                            scope,
+                            output_directory,
+                            resolution,
+                            root,
+                            root_stream,
+                            leaf_nodes_sender.clone(),
+                            errors,
+                        )
+                    }
+                );
+                if let Err(err) = result {
+                    errors.lock().unwrap().push(err);
+                }
+            }
+        );
+    }
+    first_error(errors)?;
+    if let Some(skipped_lines) = skipped_lines {
+        print_skipped_lines(&input, &skipped_lines);
+    }
 
     let mut deepest_level = 0usize;
     let mut nodes_to_subsample = Vec::<octree::Node>::new();
@@ -358,6 +1289,73 @@ fn main() {
         nodes_to_subsample.push(leaf_node);
     }
 
+    if bounding_box.is_some() {
+        num_points = bounds_check_counts.num_inside.load(Ordering::SeqCst) as i64;
+        let num_outside = bounds_check_counts.num_outside.load(Ordering::SeqCst);
+        if num_outside > 0 && matches.is_present("strict_bounding_box") {
+            // The leaves have no parents yet, so what was written is not a valid octree.
+            remove_octree_files(output_directory, &nodes_to_subsample);
+            let first_outside = bounds_check_counts
+                .first_outside
+                .lock()
+                .unwrap()
+                .unwrap();
+            return Err(
+                format!(
+                    "{} points are outside of the bounding box given by --bounding_box, the \
+                     first one at ({}, {}, {}).",
+                    num_outside,
+                    first_outside.x,
+                    first_outside.y,
+                    first_outside.z
+                )
+                        .into(),
+            );
+        }
+        if num_outside > 0 {
+            println!(
+                "Dropped {} points outside of the bounding box given by --bounding_box.",
+                num_outside
+            );
+        }
+    }
+
+    if let Some(merge_mode) = merge_mode {
+        println!("Merging duplicate points.");
+        let num_removed = deduplicate_leaves(
+            &pool,
+            output_directory,
+            resolution,
+            &bounding_cube,
+            &nodes_to_subsample,
+            merge_mode,
+        )
+                .chain_err(|| "Could not merge duplicate points.")?;
+        println!(
+            "Merged {} duplicate points ({:.2}% of all points).",
+            num_removed,
+            100. * num_removed as f64 / num_points as f64
+        );
+    }
+
+    if let Some(outlier_removal) = outlier_removal {
+        println!("Removing outliers.");
+        let num_removed = remove_outliers(
+            &pool,
+            output_directory,
+            resolution,
+            &bounding_cube,
+            &nodes_to_subsample,
+            &outlier_removal,
+        )
+                .chain_err(|| "Could not remove outliers.")?;
+        println!(
+            "Removed {} outliers ({:.2}% of all points).",
+            num_removed,
+            100. * num_removed as f64 / num_points as f64
+        );
+    }
+
     // We start on the deepest level and work our way up the tree.
     for current_level in (0..deepest_level + 1).rev() {
         // All nodes on the same level can be subsampled in parallel.
@@ -383,18 +1381,27 @@ fn main() {
             subsample_nodes.push(parent);
         }
 
+        let errors = Mutex::new(Vec::new());
         pool.scoped(
             |scope| for node in &subsample_nodes {
+                let errors = &errors;
                 scope.execute(
                     move || {
-                        subsample_children_into(output_directory, node, resolution).unwrap();
+                        let result = subsample_children_into(output_directory, node, resolution);
+                        if let Err(err) = result {
+                            errors.lock().unwrap().push(err);
+                        }
                     }
                 );
             }
         );
+        first_error(errors)?;
 
         // The nodes that were just now created through sub-sampling will be required to create
         // their parents.
         nodes_to_subsample.extend(subsample_nodes.into_iter());
     }
+
+    drop(spooled_input);
+    Ok(())
 }
@@ -0,0 +1,105 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate clap;
+extern crate point_viewer;
+
+use point_viewer::{InternalIterator, Point};
+use point_viewer::errors::*;
+use point_viewer::octree;
+use point_viewer::pcd::{DataFormat, PcdWriter};
+use point_viewer::ply::{Encoding, PlyWriter};
+use std::path::{Path, PathBuf};
+use std::process;
+
+/// Calls 'write' for all points of the octree in 'octree_directory'.
+fn write_points<F>(octree_directory: &Path, mut write: F) -> Result<()>
+    where F: FnMut(&Point) -> Result<()>
+{
+    let octree = octree::Octree::new(octree_directory)?;
+    // Every point is stored in exactly one node, so we visit all of them.
+    for id in octree.node_ids() {
+        let mut result = Ok(());
+        octree::NodeIterator::from_disk(octree_directory, &id)?
+            .for_each(
+                |p| if result.is_ok() {
+                    result = write(p);
+                }
+            )?;
+        result?;
+    }
+    Ok(())
+}
+
+/// Writes all points of the octree in 'octree_directory' into 'output', which is a PLY file if it
+/// has a '.ply' extension and a PCD file otherwise. Returns the number of points written.
+fn export(octree_directory: &Path, output: &Path, ascii: bool) -> Result<usize> {
+    if output.extension().and_then(|e| e.to_str()) == Some("ply") {
+        let encoding = if ascii {
+            Encoding::Ascii
+        } else {
+            Encoding::BinaryLittleEndian
+        };
+        let mut writer = PlyWriter::new(output, encoding, &[])?;
+        write_points(octree_directory, |p| writer.write(p))?;
+        let num_written = writer.num_written();
+        writer.finish()?;
+        return Ok(num_written);
+    }
+
+    let data_format = if ascii {
+        DataFormat::Ascii
+    } else {
+        DataFormat::Binary
+    };
+    let mut writer = PcdWriter::new(output, data_format)?;
+    write_points(octree_directory, |p| writer.write(p))?;
+    let num_written = writer.num_written();
+    writer.finish()?;
+    Ok(num_written)
+}
+
+fn main() {
+    let matches = clap::App::new("export_octree")
+        .about("Writes all points of an octree into a single PLY or PCD file.")
+        .args(
+            &[
+                clap::Arg::with_name("ascii")
+                    .help("Write an ASCII file instead of a binary one.")
+                    .long("ascii"),
+                clap::Arg::with_name("octree_directory")
+                    .help("Input directory of the octree directory to export.")
+                    .index(1)
+                    .required(true),
+                clap::Arg::with_name("output")
+                    .help("PLY or PCD file to write, depending on the extension.")
+                    .index(2)
+                    .required(true),
+            ]
+        )
+        .get_matches();
+
+    let octree_directory = PathBuf::from(matches.value_of("octree_directory").unwrap());
+    let output = PathBuf::from(matches.value_of("output").unwrap());
+    match export(&octree_directory, &output, matches.is_present("ascii")) {
+        Ok(num_points) => println!("Wrote {} points to {}.", num_points, output.display()),
+        Err(err) => {
+            println!("Could not export octree: {}", err);
+            for cause in err.iter().skip(1) {
+                println!("Caused by: {}", cause);
+            }
+            process::exit(1);
+        }
+    }
+}
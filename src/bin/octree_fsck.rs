@@ -0,0 +1,57 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate clap;
+extern crate point_viewer;
+
+use point_viewer::octree;
+use std::path::PathBuf;
+use std::process;
+
+fn main() {
+    let matches = clap::App::new("octree_fsck")
+        .about("Verifies the integrity of an octree directory.")
+        .args(
+            &[
+                clap::Arg::with_name("octree_directory")
+                    .help("Input directory of the octree directory to check.")
+                    .index(1)
+                    .required(true),
+            ]
+        )
+        .get_matches();
+
+    let octree_directory = PathBuf::from(matches.value_of("octree_directory").unwrap());
+    let report = match octree::fsck(&octree_directory) {
+        Ok(report) => report,
+        Err(err) => {
+            println!("Could not check octree: {}", err);
+            process::exit(2);
+        }
+    };
+
+    for problem in &report.problems {
+        println!("{}", problem);
+    }
+    println!(
+        "Checked {} nodes with {} points, verified {} checksums, found {} problems.",
+        report.num_nodes_checked,
+        report.num_points_checked,
+        report.num_checksums_verified,
+        report.problems.len()
+    );
+    if !report.is_ok() {
+        process::exit(1);
+    }
+}
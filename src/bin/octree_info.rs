@@ -0,0 +1,238 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate clap;
+#[macro_use]
+extern crate json;
+extern crate point_viewer;
+
+use point_viewer::errors::*;
+use point_viewer::math::CuboidLike;
+use point_viewer::octree::{self, NodeMeta, PositionEncoding};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+
+#[derive(Debug,Default)]
+struct LevelInfo {
+    num_nodes: u64,
+    num_points: u64,
+}
+
+#[derive(Debug,Default)]
+struct DiskUsage {
+    meta: u64,
+    position: u64,
+    color: u64,
+}
+
+impl DiskUsage {
+    fn total(&self) -> u64 {
+        self.meta + self.position + self.color
+    }
+}
+
+#[derive(Debug,Default)]
+struct EncodingCounts {
+    uint8: u64,
+    uint16: u64,
+    float32: u64,
+}
+
+#[derive(Debug)]
+struct OctreeInfo {
+    octree: octree::Octree,
+    // As written in meta.pb.
+    version: i32,
+    levels: Vec<LevelInfo>,
+    encodings: EncodingCounts,
+    disk_usage: DiskUsage,
+}
+
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+impl OctreeInfo {
+    fn new(directory: &Path) -> Result<Self> {
+        // Read separately, so that octrees this build cannot load still report their version.
+        let version = octree::read_meta(directory)?
+            .version
+            .ok_or("meta.pb has no version.")?;
+        let octree = octree::Octree::new(directory)
+            .chain_err(|| format!("Could not load octree with version {}.", version))?;
+        let mut levels = Vec::<LevelInfo>::new();
+        let mut encodings = EncodingCounts::default();
+        let mut disk_usage = DiskUsage::default();
+        disk_usage.meta += file_size(&directory.join("meta.pb"));
+
+        for id in octree.node_ids() {
+            let meta = NodeMeta::from_disk(directory, &id)?;
+            while levels.len() <= id.level() {
+                levels.push(LevelInfo::default());
+            }
+            levels[id.level()].num_nodes += 1;
+            levels[id.level()].num_points += meta.num_points as u64;
+
+            match meta.position_encoding {
+                PositionEncoding::Uint8 => encodings.uint8 += 1,
+                PositionEncoding::Uint16 => encodings.uint16 += 1,
+                PositionEncoding::Float32 => encodings.float32 += 1,
+            }
+
+            disk_usage.meta += file_size(&meta.stem.with_extension(octree::META_EXT));
+            disk_usage.position += file_size(&meta.stem.with_extension(octree::POSITION_EXT));
+            disk_usage.color += file_size(&meta.stem.with_extension(octree::COLOR_EXT));
+        }
+
+        Ok(
+            OctreeInfo {
+                octree: octree,
+                version: version,
+                levels: levels,
+                encodings: encodings,
+                disk_usage: disk_usage,
+            }
+        )
+    }
+
+    fn num_nodes(&self) -> u64 {
+        self.levels.iter().map(|l| l.num_nodes).sum()
+    }
+
+    fn num_points(&self) -> u64 {
+        self.levels.iter().map(|l| l.num_points).sum()
+    }
+
+    fn deepest_level(&self) -> usize {
+        self.levels.len().saturating_sub(1)
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        let bounding_cube = self.octree.bounding_cube();
+        let min = bounding_cube.min();
+        let levels: Vec<_> = self.levels
+            .iter()
+            .enumerate()
+            .map(
+                |(level, info)| {
+                    object!{
+                        "level" => level,
+                        "num_nodes" => info.num_nodes,
+                        "num_points" => info.num_points
+                    }
+                }
+            )
+            .collect();
+        object!{
+            "bounding_cube" => object!{
+                "min" => array![min.x, min.y, min.z],
+                "edge_length" => bounding_cube.edge_length()
+            },
+            "resolution" => self.octree.resolution(),
+            "version" => self.version,
+            "num_points" => self.num_points(),
+            "num_nodes" => self.num_nodes(),
+            "deepest_level" => self.deepest_level(),
+            "levels" => levels,
+            "position_encodings" => object!{
+                "uint8" => self.encodings.uint8,
+                "uint16" => self.encodings.uint16,
+                "float32" => self.encodings.float32
+            },
+            "disk_usage" => object!{
+                "meta" => self.disk_usage.meta,
+                "position" => self.disk_usage.position,
+                "color" => self.disk_usage.color,
+                "total" => self.disk_usage.total()
+            }
+        }
+    }
+
+    fn print(&self) {
+        let bounding_cube = self.octree.bounding_cube();
+        let min = bounding_cube.min();
+        println!(
+            "Bounding cube: min ({}, {}, {}), edge length {}",
+            min.x,
+            min.y,
+            min.z,
+            bounding_cube.edge_length()
+        );
+        println!("Resolution: {}", self.octree.resolution());
+        println!("Version: {}", self.version);
+        println!("Points: {}", self.num_points());
+        println!("Nodes: {}", self.num_nodes());
+        println!("Deepest level: {}", self.deepest_level());
+        println!("");
+        println!("{:>5} {:>10} {:>14}", "Level", "Nodes", "Points");
+        for (level, info) in self.levels.iter().enumerate() {
+            println!("{:>5} {:>10} {:>14}", level, info.num_nodes, info.num_points);
+        }
+        println!("");
+        println!("Position encodings:");
+        println!("  Uint8:   {}", self.encodings.uint8);
+        println!("  Uint16:  {}", self.encodings.uint16);
+        println!("  Float32: {}", self.encodings.float32);
+        println!("");
+        println!("Disk usage:");
+        println!("  meta:     {}", format_bytes(self.disk_usage.meta));
+        println!("  position: {}", format_bytes(self.disk_usage.position));
+        println!("  color:    {}", format_bytes(self.disk_usage.color));
+        println!("  total:    {}", format_bytes(self.disk_usage.total()));
+    }
+}
+
+fn format_bytes(num_bytes: u64) -> String {
+    const UNITS: [&'static str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = num_bytes as f64;
+    let mut unit = 0;
+    while value >= 1024. && unit + 1 < UNITS.len() {
+        value /= 1024.;
+        unit += 1;
+    }
+    format!("{:.2} {} ({} bytes)", value, UNITS[unit], num_bytes)
+}
+
+fn main() {
+    let matches = clap::App::new("octree_info")
+        .about("Prints statistics about an octree directory.")
+        .args(
+            &[
+                clap::Arg::with_name("json")
+                    .help("Print the statistics as JSON.")
+                    .long("json"),
+                clap::Arg::with_name("octree_directory")
+                    .help("Input directory of the octree directory to inspect.")
+                    .index(1)
+                    .required(true),
+            ]
+        )
+        .get_matches();
+
+    let octree_directory = PathBuf::from(matches.value_of("octree_directory").unwrap());
+    let info = match OctreeInfo::new(&octree_directory) {
+        Ok(info) => info,
+        Err(err) => {
+            println!("Could not load octree: {}", err);
+            process::exit(1);
+        }
+    };
+
+    if matches.is_present("json") {
+        println!("{}", info.to_json().pretty(2));
+    } else {
+        info.print();
+    }
+}
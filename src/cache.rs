@@ -0,0 +1,196 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+struct Entry<V> {
+    value: V,
+    num_bytes: usize,
+    last_used: u64,
+}
+
+/// A cache that evicts the least recently used entries once the sum of the sizes of its values
+/// exceeds a budget, e.g. for node data keyed by node id and level of detail. The caller provides
+/// the size of each value when inserting it. The cache is not synchronized, wrap it in a 'Mutex'
+/// to share it between threads.
+pub struct LruCache<K, V> {
+    max_bytes: usize,
+    num_bytes: usize,
+    entries: HashMap<K, Entry<V>>,
+    // Maps from the time an entry was last used to its key, so the first one is the least
+    // recently used.
+    recency: BTreeMap<u64, K>,
+    time: u64,
+    num_hits: u64,
+    num_misses: u64,
+}
+
+impl<K: Clone + Eq + Hash, V> LruCache<K, V> {
+    pub fn new(max_bytes: usize) -> Self {
+        LruCache {
+            max_bytes: max_bytes,
+            num_bytes: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            time: 0,
+            num_hits: 0,
+            num_misses: 0,
+        }
+    }
+
+    fn tick(&mut self) -> u64 {
+        self.time += 1;
+        self.time
+    }
+
+    /// Returns the value for 'key' and marks it as most recently used. Counts as hit or miss.
+    pub fn get(&mut self, key: &K) -> Option<&V> {
+        if self.entries.contains_key(key) {
+            self.num_hits += 1;
+        } else {
+            self.num_misses += 1;
+        }
+        self.get_uncounted(key)
+    }
+
+    /// Like 'get', but does not count as hit or miss, e.g. for values derived from other entries
+    /// that should not show up in the statistics.
+    pub fn get_uncounted(&mut self, key: &K) -> Option<&V> {
+        let time = self.tick();
+        match self.entries.get_mut(key) {
+            Some(entry) => {
+                self.recency.remove(&entry.last_used);
+                self.recency.insert(time, key.clone());
+                entry.last_used = time;
+                Some(&entry.value)
+            }
+            None => None,
+        }
+    }
+
+    /// Inserts 'value', which takes 'num_bytes', evicting the least recently used entries to make
+    /// room for it. Values larger than the whole budget are not cached.
+    pub fn insert(&mut self, key: K, value: V, num_bytes: usize) {
+        self.remove(&key);
+        if num_bytes > self.max_bytes {
+            return;
+        }
+        while self.num_bytes + num_bytes > self.max_bytes {
+            let oldest = *self.recency.keys().next().unwrap();
+            let oldest_key = self.recency.remove(&oldest).unwrap();
+            self.remove(&oldest_key);
+        }
+        let time = self.tick();
+        self.recency.insert(time, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value: value,
+                num_bytes: num_bytes,
+                last_used: time,
+            },
+        );
+        self.num_bytes += num_bytes;
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let entry = match self.entries.remove(key) {
+            Some(entry) => entry,
+            None => return None,
+        };
+        self.recency.remove(&entry.last_used);
+        self.num_bytes -= entry.num_bytes;
+        Some(entry.value)
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.num_bytes = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The sum of the sizes of all cached values.
+    pub fn num_bytes(&self) -> usize {
+        self.num_bytes
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    /// Number of calls to 'get' that found a value.
+    pub fn num_hits(&self) -> u64 {
+        self.num_hits
+    }
+
+    /// Number of calls to 'get' that did not find a value.
+    pub fn num_misses(&self) -> u64 {
+        self.num_misses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = LruCache::new(10);
+        cache.insert("a", 1, 4);
+        cache.insert("b", 2, 4);
+        assert_eq!(Some(&1), cache.get(&"a"));
+        // "b" was used less recently than "a".
+        cache.insert("c", 3, 4);
+        assert_eq!(None, cache.get(&"b"));
+        assert_eq!(Some(&1), cache.get(&"a"));
+        assert_eq!(Some(&3), cache.get(&"c"));
+        assert_eq!(8, cache.num_bytes());
+        assert_eq!(3, cache.num_hits());
+        assert_eq!(1, cache.num_misses());
+
+        assert_eq!(Some(&1), cache.get_uncounted(&"a"));
+        assert_eq!(None, cache.get_uncounted(&"b"));
+        assert_eq!(3, cache.num_hits());
+        assert_eq!(1, cache.num_misses());
+    }
+
+    #[test]
+    fn test_replace_and_oversized_values() {
+        let mut cache = LruCache::new(10);
+        cache.insert("a", 1, 4);
+        cache.insert("a", 2, 6);
+        assert_eq!(1, cache.len());
+        assert_eq!(6, cache.num_bytes());
+        assert_eq!(Some(&2), cache.get(&"a"));
+
+        cache.insert("b", 3, 11);
+        assert_eq!(None, cache.get(&"b"));
+        assert_eq!(Some(&2), cache.get(&"a"));
+
+        cache.insert("c", 4, 10);
+        assert_eq!(1, cache.len());
+        assert_eq!(Some(4), cache.remove(&"c"));
+        assert!(cache.is_empty());
+        assert_eq!(0, cache.num_bytes());
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use errors::*;
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use zstd;
+
+/// Compression of an input file, as given by its extension.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// Returns 'path' without its compression extension, e.g. 'scan.ply' for 'scan.ply.gz'. This is
+/// useful to find out the format of the file.
+pub fn uncompressed_path(path: &Path) -> PathBuf {
+    match Compression::from_path(path) {
+        Compression::None => path.to_path_buf(),
+        _ => path.with_extension(""),
+    }
+}
+
+/// Opens 'path' for reading and transparently decompresses it if it has a '.gz' or '.zst'
+/// extension.
+pub fn open(path: &Path) -> Result<Box<Read + Send>> {
+    let file = File::open(path)
+        .chain_err(|| format!("Could not open input file {}.", path.display()))?;
+    let reader: Box<Read + Send> = match Compression::from_path(path) {
+        Compression::None => Box::new(file),
+        Compression::Gzip => {
+            Box::new(
+                GzDecoder::new(file)
+                    .chain_err(|| format!("{} is not a valid gzip file.", path.display()))?,
+            )
+        }
+        Compression::Zstd => {
+            Box::new(
+                zstd::Decoder::new(file)
+                    .chain_err(|| format!("Could not decompress {}.", path.display()))?,
+            )
+        }
+    };
+    Ok(reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression as GzCompression;
+    use flate2::write::GzEncoder;
+    use std::env;
+    use std::io::Write;
+
+    #[test]
+    fn test_uncompressed_path() {
+        assert_eq!(Path::new("scan.ply"), uncompressed_path(Path::new("scan.ply.gz")));
+        assert_eq!(Path::new("scan.pts"), uncompressed_path(Path::new("scan.pts.zst")));
+        assert_eq!(Path::new("scan.pts"), uncompressed_path(Path::new("scan.pts")));
+    }
+
+    #[test]
+    fn test_read_gzip() {
+        let path = env::temp_dir().join("compression_test_read_gzip.txt.gz");
+        {
+            let mut encoder = GzEncoder::new(File::create(&path).unwrap(), GzCompression::Default);
+            encoder.write_all(b"1 2 3\n").unwrap();
+            encoder.finish().unwrap();
+        }
+        let mut content = String::new();
+        open(&path).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!("1 2 3\n", content);
+    }
+
+    #[test]
+    fn test_read_zstd() {
+        let path = env::temp_dir().join("compression_test_read_zstd.txt.zst");
+        {
+            let mut encoder = zstd::Encoder::new(File::create(&path).unwrap(), 3).unwrap();
+            encoder.write_all(b"1 2 3\n").unwrap();
+            encoder.finish().unwrap();
+        }
+        let mut content = String::new();
+        open(&path).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!("1 2 3\n", content);
+    }
+}
@@ -0,0 +1,174 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {InternalIterator, Point};
+use errors::*;
+use math::{Vector3f, Zero};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+
+/// Decides what happens to points that fall into the same cell of the resolution grid.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum MergeMode {
+    /// Replace them by a single point with the mean position and color.
+    Average,
+    /// Keep the first point and drop all others.
+    KeepFirst,
+}
+
+struct Accumulator {
+    // Sum of the offsets to 'first', which stay small, so that averaging does not lose precision
+    // for large, e.g. georeferenced, coordinates.
+    offset: Vector3f,
+    r: u32,
+    g: u32,
+    b: u32,
+    count: u32,
+    first: Point,
+}
+
+/// Merges all points of 'points' that fall into the same cell of a grid with cells of edge length
+/// 'resolution' starting at 'origin'. The returned points are in the order in which their cell
+/// was first seen.
+pub fn merge_duplicates<I: InternalIterator>(
+    points: I,
+    origin: &Vector3f,
+    resolution: f64,
+    mode: MergeMode,
+) -> Result<Vec<Point>> {
+    let mut cells: HashMap<(i64, i64, i64), usize> = HashMap::new();
+    let mut accumulators = Vec::new();
+    points
+        .for_each(
+            |p| {
+                let cell = (
+                    ((p.position.x as f64 - origin.x as f64) / resolution).floor() as i64,
+                    ((p.position.y as f64 - origin.y as f64) / resolution).floor() as i64,
+                    ((p.position.z as f64 - origin.z as f64) / resolution).floor() as i64,
+                );
+                let index = match cells.entry(cell) {
+                    Entry::Occupied(entry) => *entry.get(),
+                    Entry::Vacant(entry) => {
+                        accumulators.push(
+                            Accumulator {
+                                offset: Vector3f::zero(),
+                                r: 0,
+                                g: 0,
+                                b: 0,
+                                count: 0,
+                                first: p.clone(),
+                            }
+                        );
+                        *entry.insert(accumulators.len() - 1)
+                    }
+                };
+                if mode == MergeMode::Average {
+                    let accumulator = &mut accumulators[index];
+                    accumulator.offset =
+                        accumulator.offset + (p.position - accumulator.first.position);
+                    accumulator.r += p.r as u32;
+                    accumulator.g += p.g as u32;
+                    accumulator.b += p.b as u32;
+                    accumulator.count += 1;
+                }
+            }
+        )?;
+
+    Ok(
+        accumulators
+            .into_iter()
+            .map(
+                |a| match mode {
+                    MergeMode::KeepFirst => a.first,
+                    MergeMode::Average => {
+                        let count = a.count;
+                        Point {
+                            position: a.first.position + a.offset / count as f32,
+                            r: (a.r / count) as u8,
+                            g: (a.g / count) as u8,
+                            b: (a.b / count) as u8,
+                        }
+                    }
+                },
+            )
+            .collect()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points() -> Vec<Point> {
+        vec![
+                Point {
+                    position: Vector3f::new(0.1, 0.1, 0.1),
+                    r: 100,
+                    g: 0,
+                    b: 0,
+                },
+                Point {
+                    position: Vector3f::new(1.5, 0.1, 0.1),
+                    r: 0,
+                    g: 0,
+                    b: 0,
+                },
+                Point {
+                    position: Vector3f::new(0.3, 0.1, 0.1),
+                    r: 200,
+                    g: 0,
+                    b: 0,
+                },
+        ]
+    }
+
+    #[test]
+    fn test_average() {
+        let merged = merge_duplicates(points(), &Vector3f::zero(), 1., MergeMode::Average).unwrap();
+        assert_eq!(2, merged.len());
+        assert!((merged[0].position.x - 0.2).abs() < 1e-6);
+        assert_eq!(150, merged[0].r);
+        assert_eq!(1.5, merged[1].position.x);
+    }
+
+    #[test]
+    fn test_average_large_coordinates() {
+        let origin = Vector3f::new(500000., 0., 0.);
+        let points: Vec<Point> = (0..9)
+            .map(
+                |i| {
+                    Point {
+                        position: Vector3f::new(500000. + i as f32 * 0.0625, 0., 0.),
+                        r: 0,
+                        g: 0,
+                        b: 0,
+                    }
+                }
+            )
+            .collect();
+        let merged = merge_duplicates(points, &origin, 1., MergeMode::Average).unwrap();
+        assert_eq!(1, merged.len());
+        // Adding up the positions themselves gives 500000.28.
+        assert_eq!(500000.25, merged[0].position.x);
+    }
+
+    #[test]
+    fn test_keep_first() {
+        let merged = merge_duplicates(points(), &Vector3f::zero(), 1., MergeMode::KeepFirst)
+            .unwrap();
+        assert_eq!(2, merged.len());
+        assert_eq!(0.1, merged[0].position.x);
+        assert_eq!(100, merged[0].r);
+    }
+}
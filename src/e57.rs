@@ -0,0 +1,841 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reader for ASTM E57 files as exported by terrestrial laser scanners. Only the point data of
+//! the 'data3D' section is read, images are ignored. Checksums are not verified.
+
+use {InternalIterator, Point};
+use byteorder::{ByteOrder, LittleEndian};
+use cgmath::{InnerSpace, Quaternion, Vector3};
+use errors::*;
+use math::Vector3f;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use xml::reader::{EventReader, XmlEvent};
+
+const SIGNATURE: &'static [u8] = b"ASTM-E57";
+const HEADER_SIZE: usize = 48;
+const CHECKSUM_SIZE: u64 = 4;
+const COMPRESSED_VECTOR_SECTION_ID: u8 = 1;
+const DATA_PACKET_TYPE: u8 = 1;
+
+fn invalid_input<T>(filename: &Path, msg: &str) -> Result<T> {
+    Err(ErrorKind::InvalidInput(format!("{}: {}", filename.display(), msg)).into())
+}
+
+/// E57 files are split into pages that end with a checksum. This reads the logical content, i.e.
+/// without the checksums, starting at a physical offset.
+struct PagedReader {
+    file: BufReader<File>,
+    page_size: u64,
+    physical_position: u64,
+}
+
+impl PagedReader {
+    fn seek(&mut self, physical_offset: u64) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(physical_offset))?;
+        self.physical_position = physical_offset;
+        Ok(())
+    }
+}
+
+impl Read for PagedReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let page_data_size = self.page_size - CHECKSUM_SIZE;
+        let offset_in_page = self.physical_position % self.page_size;
+        if offset_in_page >= page_data_size {
+            let mut checksum = [0u8; CHECKSUM_SIZE as usize];
+            let num_to_skip = (self.page_size - offset_in_page) as usize;
+            self.file.read_exact(&mut checksum[..num_to_skip])?;
+            self.physical_position += num_to_skip as u64;
+        }
+        let num_left_in_page = page_data_size - self.physical_position % self.page_size;
+        let len = (buf.len() as u64).min(num_left_in_page) as usize;
+        let num_read = self.file.read(&mut buf[..len])?;
+        self.physical_position += num_read as u64;
+        Ok(num_read)
+    }
+}
+
+/// A minimal DOM of the XML section.
+#[derive(Debug,Default)]
+struct XmlNode {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<XmlNode>,
+    text: String,
+}
+
+impl XmlNode {
+    fn parse(xml: &[u8]) -> Result<Self> {
+        let mut stack = vec![XmlNode::default()];
+        for event in EventReader::new(xml) {
+            let event =
+                event.chain_err(|| ErrorKind::InvalidInput("Invalid XML section.".to_string()))?;
+            match event {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    stack.push(
+                        XmlNode {
+                            name: name.local_name,
+                            attributes: attributes
+                                .into_iter()
+                                .map(|a| (a.name.local_name, a.value))
+                                .collect(),
+                            children: Vec::new(),
+                            text: String::new(),
+                        }
+                    );
+                }
+                XmlEvent::EndElement { .. } => {
+                    let node = stack.pop().unwrap();
+                    stack.last_mut().unwrap().children.push(node);
+                }
+                XmlEvent::Characters(text) |
+                XmlEvent::CData(text) => stack.last_mut().unwrap().text.push_str(&text),
+                _ => (),
+            }
+        }
+        match stack.pop().unwrap().children.pop() {
+            Some(root) => Ok(root),
+            None => Err(ErrorKind::InvalidInput("Empty XML section.".to_string()).into()),
+        }
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|&&(ref n, _)| n == name)
+            .map(|&(_, ref v)| v as &str)
+    }
+
+    fn child(&self, name: &str) -> Option<&XmlNode> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    /// Returns the numeric value of the child 'name', which can be a Float, Integer or
+    /// ScaledInteger element.
+    fn number(&self, name: &str) -> Option<f64> {
+        let child = match self.child(name) {
+            Some(child) => child,
+            None => return None,
+        };
+        let value = match child.text.trim().parse::<f64>() {
+            Ok(value) => value,
+            // Empty elements have their default value.
+            Err(_) => 0.,
+        };
+        if child.attribute("type") == Some("ScaledInteger") {
+            let scale = child.attribute("scale").and_then(|s| s.parse().ok()).unwrap_or(1.);
+            let offset = child.attribute("offset").and_then(|s| s.parse().ok()).unwrap_or(0.);
+            return Some(value * scale + offset);
+        }
+        Some(value)
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+enum Encoding {
+    Float { double: bool },
+    Integer { minimum: i64, maximum: i64 },
+    ScaledInteger {
+        minimum: i64,
+        maximum: i64,
+        scale: f64,
+        offset: f64,
+    },
+}
+
+impl Encoding {
+    fn from_node(filename: &Path, node: &XmlNode) -> Result<Self> {
+        let parse_i64 = |name: &str| -> Result<i64> {
+            match node.attribute(name).map(|v| v.parse::<i64>()) {
+                Some(Ok(value)) => Ok(value),
+                _ => {
+                    invalid_input(
+                        filename,
+                        &format!("Field '{}' has no valid '{}'.", node.name, name),
+                    )
+                }
+            }
+        };
+        let parse_range = || -> Result<(i64, i64)> {
+            let (minimum, maximum) = (parse_i64("minimum")?, parse_i64("maximum")?);
+            if minimum > maximum {
+                return invalid_input(
+                    filename,
+                    &format!("Field '{}' has a minimum larger than its maximum.", node.name),
+                );
+            }
+            Ok((minimum, maximum))
+        };
+        let parse_f64 = |name: &str, default: f64| -> f64 {
+            node.attribute(name)
+                .and_then(|v| v.parse::<f64>().ok())
+                .unwrap_or(default)
+        };
+        match node.attribute("type") {
+            Some("Float") => {
+                Ok(Encoding::Float { double: node.attribute("precision") != Some("single") })
+            }
+            Some("Integer") => {
+                let (minimum, maximum) = parse_range()?;
+                Ok(
+                    Encoding::Integer {
+                        minimum: minimum,
+                        maximum: maximum,
+                    }
+                )
+            }
+            Some("ScaledInteger") => {
+                let (minimum, maximum) = parse_range()?;
+                Ok(
+                    Encoding::ScaledInteger {
+                        minimum: minimum,
+                        maximum: maximum,
+                        scale: parse_f64("scale", 1.),
+                        offset: parse_f64("offset", 0.),
+                    }
+                )
+            }
+            other => {
+                invalid_input(
+                    filename,
+                    &format!("Field '{}' has unsupported type {:?}.", node.name, other),
+                )
+            }
+        }
+    }
+
+    /// The range of values of integer encodings.
+    fn range(&self) -> Option<(f64, f64)> {
+        match *self {
+            Encoding::Float { .. } => None,
+            Encoding::Integer { minimum, maximum } => Some((minimum as f64, maximum as f64)),
+            Encoding::ScaledInteger {
+                minimum,
+                maximum,
+                scale,
+                offset,
+            } => Some((minimum as f64 * scale + offset, maximum as f64 * scale + offset)),
+        }
+    }
+
+    /// Number of bits a single value takes in the bytestream.
+    fn num_bits(&self) -> usize {
+        match *self {
+            Encoding::Float { double } => if double { 64 } else { 32 },
+            Encoding::Integer { minimum, maximum } |
+            Encoding::ScaledInteger { minimum, maximum, .. } => {
+                // Does not overflow, since the minimum is not larger than the maximum.
+                let num_values = (maximum as u64).wrapping_sub(minimum as u64);
+                64 - num_values.leading_zeros() as usize
+            }
+        }
+    }
+}
+
+/// Returns 'minimum + raw', where 'raw' is at most 'maximum - minimum' of the field. Wraps, since
+/// 'raw' does not fit into an i64 for fields spanning more than half of its range.
+fn offset_by(raw: u64, minimum: i64) -> i64 {
+    raw.wrapping_add(minimum as u64) as i64
+}
+
+/// Decodes the values of a single field from its bytestream, which is split across data packets.
+struct ByteStream {
+    encoding: Encoding,
+    num_bits: usize,
+    data: Vec<u8>,
+    bit_position: usize,
+    values: VecDeque<f64>,
+}
+
+impl ByteStream {
+    fn new(encoding: Encoding) -> Self {
+        ByteStream {
+            num_bits: encoding.num_bits(),
+            encoding: encoding,
+            data: Vec::new(),
+            bit_position: 0,
+            values: VecDeque::new(),
+        }
+    }
+
+    fn has_value(&self) -> bool {
+        // Integers with a single possible value are not stored at all.
+        self.num_bits == 0 || !self.values.is_empty()
+    }
+
+    fn next_value(&mut self) -> f64 {
+        if self.num_bits == 0 {
+            return self.decode(0);
+        }
+        self.values.pop_front().unwrap()
+    }
+
+    fn decode(&self, raw: u64) -> f64 {
+        let mut bytes = [0u8; 8];
+        LittleEndian::write_u64(&mut bytes, raw);
+        match self.encoding {
+            Encoding::Float { double: true } => LittleEndian::read_f64(&bytes),
+            Encoding::Float { double: false } => LittleEndian::read_f32(&bytes) as f64,
+            Encoding::Integer { minimum, .. } => offset_by(raw, minimum) as f64,
+            Encoding::ScaledInteger {
+                minimum,
+                scale,
+                offset,
+                ..
+            } => offset_by(raw, minimum) as f64 * scale + offset,
+        }
+    }
+
+    /// Adds the next buffer of this bytestream and decodes all values that are complete.
+    fn append(&mut self, buffer: &[u8]) {
+        if self.num_bits == 0 {
+            return;
+        }
+        self.data.extend_from_slice(buffer);
+        // Values are packed starting at the least significant bit.
+        while self.bit_position + self.num_bits <= self.data.len() * 8 {
+            let mut raw = 0u64;
+            let mut num_bits_read = 0;
+            while num_bits_read < self.num_bits {
+                let byte = self.data[self.bit_position / 8] as u64;
+                let bit_offset = self.bit_position % 8;
+                let num_bits = (8 - bit_offset).min(self.num_bits - num_bits_read);
+                let bits = (byte >> bit_offset) & ((1 << num_bits) - 1);
+                raw |= bits << num_bits_read;
+                num_bits_read += num_bits;
+                self.bit_position += num_bits;
+            }
+            let value = self.decode(raw);
+            self.values.push_back(value);
+        }
+        let num_bytes_consumed = self.bit_position / 8;
+        self.data.drain(..num_bytes_consumed);
+        self.bit_position -= num_bytes_consumed * 8;
+    }
+}
+
+/// Indices into the fields of a record for everything we turn into a 'Point'.
+#[derive(Debug,Default)]
+struct FieldIndices {
+    cartesian: Option<[usize; 3]>,
+    spherical: Option<[usize; 3]>,
+    invalid_state: Option<usize>,
+    color: Option<[usize; 3]>,
+    intensity: Option<usize>,
+}
+
+#[derive(Debug)]
+struct Scan {
+    rotation: Quaternion<f64>,
+    translation: Vector3<f64>,
+    // Physical offset of the binary section containing the points.
+    file_offset: u64,
+    record_count: u64,
+    encodings: Vec<Encoding>,
+    indices: FieldIndices,
+    color_ranges: [(f64, f64); 3],
+    intensity_range: (f64, f64),
+}
+
+impl Scan {
+    fn from_node(filename: &Path, node: &XmlNode) -> Result<Self> {
+        let points = match node.child("points") {
+            Some(points) if points.attribute("type") == Some("CompressedVector") => points,
+            _ => return invalid_input(filename, "Scan has no 'points'."),
+        };
+        let file_offset = points.attribute("fileOffset").and_then(|v| v.parse().ok());
+        let record_count = points.attribute("recordCount").and_then(|v| v.parse().ok());
+        let (file_offset, record_count) = match (file_offset, record_count) {
+            (Some(file_offset), Some(record_count)) => (file_offset, record_count),
+            _ => return invalid_input(filename, "'points' has no valid fileOffset or recordCount."),
+        };
+        let prototype = match points.child("prototype") {
+            Some(prototype) => prototype,
+            None => return invalid_input(filename, "'points' has no prototype."),
+        };
+
+        let mut encodings = Vec::new();
+        for field in &prototype.children {
+            encodings.push(Encoding::from_node(filename, field)?);
+        }
+        let indices = {
+            let index = |name: &str| prototype.children.iter().position(|c| c.name == name);
+            let index3 = |a: &str, b: &str, c: &str| match (index(a), index(b), index(c)) {
+                (Some(a), Some(b), Some(c)) => Some([a, b, c]),
+                _ => None,
+            };
+            FieldIndices {
+                cartesian: index3("cartesianX", "cartesianY", "cartesianZ"),
+                spherical: index3("sphericalRange", "sphericalAzimuth", "sphericalElevation"),
+                invalid_state: index("cartesianInvalidState").or(index("sphericalInvalidState")),
+                color: index3("colorRed", "colorGreen", "colorBlue"),
+                intensity: index("intensity"),
+            }
+        };
+        if indices.cartesian.is_none() && indices.spherical.is_none() {
+            return invalid_input(filename, "Scan has neither cartesian nor spherical coordinates.");
+        }
+
+        let (color_ranges, intensity_range) = {
+            // The limits are optional, the range of the field's encoding is the fallback.
+            let limits = |limits: &str, minimum: &str, maximum: &str, field: Option<usize>| {
+                let from_limits = node.child(limits)
+                    .and_then(
+                        |l| match (l.number(minimum), l.number(maximum)) {
+                            (Some(min), Some(max)) if min < max => Some((min, max)),
+                            _ => None,
+                        },
+                    );
+                from_limits
+                    .or(field.and_then(|i| encodings[i].range()))
+                    .unwrap_or((0., 1.))
+            };
+            let color_field = |channel: usize| indices.color.map(|c| c[channel]);
+            let color_ranges = [
+                limits("colorLimits", "colorRedMinimum", "colorRedMaximum", color_field(0)),
+                limits("colorLimits", "colorGreenMinimum", "colorGreenMaximum", color_field(1)),
+                limits("colorLimits", "colorBlueMinimum", "colorBlueMaximum", color_field(2)),
+            ];
+            let intensity_range = limits(
+                "intensityLimits",
+                "intensityMinimum",
+                "intensityMaximum",
+                indices.intensity,
+            );
+            (color_ranges, intensity_range)
+        };
+
+        let (rotation, translation) = match node.child("pose") {
+            Some(pose) => {
+                let rotation = match pose.child("rotation") {
+                    Some(r) => {
+                        Quaternion::new(
+                            r.number("w").unwrap_or(1.),
+                            r.number("x").unwrap_or(0.),
+                            r.number("y").unwrap_or(0.),
+                            r.number("z").unwrap_or(0.),
+                        )
+                                .normalize()
+                    }
+                    None => Quaternion::new(1., 0., 0., 0.),
+                };
+                let translation = match pose.child("translation") {
+                    Some(t) => {
+                        Vector3::new(
+                            t.number("x").unwrap_or(0.),
+                            t.number("y").unwrap_or(0.),
+                            t.number("z").unwrap_or(0.),
+                        )
+                    }
+                    None => Vector3::new(0., 0., 0.),
+                };
+                (rotation, translation)
+            }
+            None => (Quaternion::new(1., 0., 0., 0.), Vector3::new(0., 0., 0.)),
+        };
+
+        Ok(
+            Scan {
+                rotation: rotation,
+                translation: translation,
+                file_offset: file_offset,
+                record_count: record_count,
+                encodings: encodings,
+                indices: indices,
+                color_ranges: color_ranges,
+                intensity_range: intensity_range,
+            }
+        )
+    }
+
+    /// Turns the values of a record into a point in the global frame. Returns None if the record
+    /// has no valid position.
+    fn to_point(&self, record: &[f64]) -> Option<Point> {
+        if let Some(i) = self.indices.invalid_state {
+            if record[i] != 0. {
+                return None;
+            }
+        }
+        let local = match (self.indices.cartesian, self.indices.spherical) {
+            (Some(c), _) => Vector3::new(record[c[0]], record[c[1]], record[c[2]]),
+            (None, Some(s)) => {
+                let (range, azimuth, elevation) = (record[s[0]], record[s[1]], record[s[2]]);
+                Vector3::new(
+                    range * elevation.cos() * azimuth.cos(),
+                    range * elevation.cos() * azimuth.sin(),
+                    range * elevation.sin(),
+                )
+            }
+            (None, None) => unreachable!(),
+        };
+        let global = self.rotation * local + self.translation;
+
+        let normalize = |value: f64, range: (f64, f64)| -> u8 {
+            let normalized = (value - range.0) / (range.1 - range.0);
+            (normalized * 255.).max(0.).min(255.).round() as u8
+        };
+        let (r, g, b) = match (self.indices.color, self.indices.intensity) {
+            (Some(c), _) => {
+                (normalize(record[c[0]], self.color_ranges[0]),
+                 normalize(record[c[1]], self.color_ranges[1]),
+                 normalize(record[c[2]], self.color_ranges[2]))
+            }
+            (None, Some(i)) => {
+                let gray = normalize(record[i], self.intensity_range);
+                (gray, gray, gray)
+            }
+            (None, None) => (255, 255, 255),
+        };
+        Some(
+            Point {
+                position: Vector3f::new(global.x as f32, global.y as f32, global.z as f32),
+                r: r,
+                g: g,
+                b: b,
+            }
+        )
+    }
+}
+
+/// Reads the points of all scans in an E57 file, transformed by the pose of their scan.
+pub struct E57Iterator {
+    reader: PagedReader,
+    filename: PathBuf,
+    scans: Vec<Scan>,
+}
+
+impl E57Iterator {
+    pub fn new<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        let filename = filename.as_ref();
+        let file = File::open(filename)
+            .chain_err(|| format!("Could not open input file {}.", filename.display()))?;
+        let file_size = file.metadata()?.len();
+        let mut file = BufReader::new(file);
+
+        let mut header = [0u8; HEADER_SIZE];
+        file.read_exact(&mut header)?;
+        if &header[..8] != SIGNATURE {
+            return invalid_input(filename, "Not an E57 file.");
+        }
+        let major_version = LittleEndian::read_u32(&header[8..12]);
+        if major_version != 1 {
+            return invalid_input(
+                filename,
+                &format!("Unsupported E57 version {}.", major_version),
+            );
+        }
+        let xml_physical_offset = LittleEndian::read_u64(&header[24..32]);
+        let xml_logical_length = LittleEndian::read_u64(&header[32..40]);
+        let page_size = LittleEndian::read_u64(&header[40..48]);
+        if page_size <= CHECKSUM_SIZE {
+            return invalid_input(filename, &format!("Invalid page size {}.", page_size));
+        }
+        // Checked before allocating a buffer for it, the logical length excludes the checksums.
+        if xml_logical_length > file_size {
+            return invalid_input(
+                filename,
+                &format!(
+                    "XML section of {} bytes is larger than the file.",
+                    xml_logical_length
+                ),
+            );
+        }
+
+        let mut reader = PagedReader {
+            file: file,
+            page_size: page_size,
+            physical_position: HEADER_SIZE as u64,
+        };
+        reader.seek(xml_physical_offset)?;
+        let mut xml = vec![0u8; xml_logical_length as usize];
+        reader.read_exact(&mut xml)?;
+        let root = XmlNode::parse(&xml)
+            .chain_err(|| format!("Could not parse XML section of {}.", filename.display()))?;
+
+        let mut scans = Vec::new();
+        if let Some(data3d) = root.child("data3D") {
+            for node in &data3d.children {
+                scans.push(Scan::from_node(filename, node)?);
+            }
+        }
+        Ok(
+            E57Iterator {
+                reader: reader,
+                filename: filename.to_path_buf(),
+                scans: scans,
+            }
+        )
+    }
+
+    fn read_scan<F: FnMut(&Point)>(&mut self, scan_index: usize, f: &mut F) -> Result<()> {
+        let scan = &self.scans[scan_index];
+        self.reader.seek(scan.file_offset)?;
+        let mut section_header = [0u8; 32];
+        self.reader.read_exact(&mut section_header)?;
+        if section_header[0] != COMPRESSED_VECTOR_SECTION_ID {
+            return invalid_input(&self.filename, "Invalid compressed vector section.");
+        }
+        let data_physical_offset = LittleEndian::read_u64(&section_header[16..24]);
+        self.reader.seek(data_physical_offset)?;
+
+        let mut streams: Vec<ByteStream> = scan.encodings
+            .iter()
+            .map(|e| ByteStream::new(e.clone()))
+            .collect();
+        let mut record = vec![0.; streams.len()];
+        let mut packet = Vec::new();
+        let mut num_records_read = 0;
+        while num_records_read < scan.record_count {
+            let mut packet_header = [0u8; 4];
+            self.reader.read_exact(&mut packet_header)?;
+            let packet_length = LittleEndian::read_u16(&packet_header[2..4]) as usize + 1;
+            if packet_length < 4 {
+                return invalid_input(&self.filename, "Invalid packet length.");
+            }
+            packet.resize(packet_length - 4, 0);
+            self.reader.read_exact(&mut packet)?;
+            // Index and empty packets carry no points.
+            if packet_header[0] != DATA_PACKET_TYPE {
+                continue;
+            }
+
+            let num_streams = LittleEndian::read_u16(&packet[0..2]) as usize;
+            if num_streams != streams.len() || packet.len() < 2 + 2 * num_streams {
+                return invalid_input(&self.filename, "Invalid data packet.");
+            }
+            let mut offset = 2 + 2 * num_streams;
+            for (i, stream) in streams.iter_mut().enumerate() {
+                let length = LittleEndian::read_u16(&packet[2 + 2 * i..4 + 2 * i]) as usize;
+                if offset + length > packet.len() {
+                    return invalid_input(&self.filename, "Invalid data packet.");
+                }
+                stream.append(&packet[offset..offset + length]);
+                offset += length;
+            }
+
+            while num_records_read < scan.record_count && streams.iter().all(|s| s.has_value()) {
+                for (value, stream) in record.iter_mut().zip(streams.iter_mut()) {
+                    *value = stream.next_value();
+                }
+                num_records_read += 1;
+                if let Some(point) = scan.to_point(&record) {
+                    f(&point);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl InternalIterator for E57Iterator {
+    fn size_hint(&self) -> Option<usize> {
+        // Records with an invalid state are skipped, so we only know the number of points if
+        // there are none.
+        if self.scans.iter().any(|s| s.indices.invalid_state.is_some()) {
+            return None;
+        }
+        Some(self.scans.iter().map(|s| s.record_count as usize).sum())
+    }
+
+    fn for_each<F: FnMut(&Point)>(mut self, mut f: F) -> Result<()> {
+        for scan_index in 0..self.scans.len() {
+            self.read_scan(scan_index, &mut f)
+                .chain_err(|| format!("Could not read points of {}.", self.filename.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::WriteBytesExt;
+    use std::env;
+    use std::io::Write;
+
+    const PAGE_SIZE: usize = 1024;
+
+    fn physical(logical: usize) -> usize {
+        logical + logical / (PAGE_SIZE - CHECKSUM_SIZE as usize) * CHECKSUM_SIZE as usize
+    }
+
+    /// Writes an E57 file with a single scan of 'points', given as (x, y, z, red). Green and blue
+    /// are 0. Checksums are left empty.
+    fn write_e57(path: &Path, points: &[(f32, f32, f32, u8)], pose: &str) {
+        // One bytestream per field of the prototype.
+        let mut buffers = vec![Vec::new(); 6];
+        for &(x, y, z, red) in points {
+            buffers[0].write_f32::<LittleEndian>(x).unwrap();
+            buffers[1].write_f32::<LittleEndian>(y).unwrap();
+            buffers[2].write_f32::<LittleEndian>(z).unwrap();
+            buffers[3].push(red);
+            buffers[4].push(0);
+            buffers[5].push(0);
+        }
+        let mut packet = Vec::new();
+        packet.write_u16::<LittleEndian>(buffers.len() as u16).unwrap();
+        for buffer in &buffers {
+            packet.write_u16::<LittleEndian>(buffer.len() as u16).unwrap();
+        }
+        for buffer in &buffers {
+            packet.extend_from_slice(buffer);
+        }
+        while (packet.len() + 4) % 4 != 0 {
+            packet.push(0);
+        }
+
+        let section_offset = HEADER_SIZE;
+        let data_offset = section_offset + 32;
+        let xml_offset = data_offset + 4 + packet.len();
+
+        let xml = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<e57Root type="Structure" xmlns="http://www.astm.org/COMMIT/E57/2010-e57-v1.0">
+  <data3D type="Vector" allowHeterogeneousChildren="1">
+    <vectorChild type="Structure">
+      {}
+      <points type="CompressedVector" fileOffset="{}" recordCount="{}">
+        <prototype type="Structure">
+          <cartesianX type="Float" precision="single"/>
+          <cartesianY type="Float" precision="single"/>
+          <cartesianZ type="Float" precision="single"/>
+          <colorRed type="Integer" minimum="0" maximum="255"/>
+          <colorGreen type="Integer" minimum="0" maximum="255"/>
+          <colorBlue type="Integer" minimum="0" maximum="255"/>
+        </prototype>
+      </points>
+    </vectorChild>
+  </data3D>
+</e57Root>"#,
+            pose,
+            physical(section_offset),
+            points.len()
+        );
+
+        let mut logical = Vec::new();
+        logical.extend_from_slice(SIGNATURE);
+        logical.write_u32::<LittleEndian>(1).unwrap();
+        logical.write_u32::<LittleEndian>(0).unwrap();
+        let file_length_position = logical.len();
+        logical.write_u64::<LittleEndian>(0).unwrap();
+        logical.write_u64::<LittleEndian>(physical(xml_offset) as u64).unwrap();
+        logical.write_u64::<LittleEndian>(xml.len() as u64).unwrap();
+        logical.write_u64::<LittleEndian>(PAGE_SIZE as u64).unwrap();
+
+        logical.push(COMPRESSED_VECTOR_SECTION_ID);
+        logical.extend_from_slice(&[0; 7]);
+        logical
+            .write_u64::<LittleEndian>((32 + 4 + packet.len()) as u64)
+            .unwrap();
+        logical.write_u64::<LittleEndian>(physical(data_offset) as u64).unwrap();
+        logical.write_u64::<LittleEndian>(0).unwrap();
+
+        logical.push(DATA_PACKET_TYPE);
+        logical.push(0);
+        logical
+            .write_u16::<LittleEndian>((4 + packet.len() - 1) as u16)
+            .unwrap();
+        logical.extend_from_slice(&packet);
+        logical.extend_from_slice(xml.as_bytes());
+
+        let file_length = physical(logical.len()) as u64;
+        LittleEndian::write_u64(
+            &mut logical[file_length_position..file_length_position + 8],
+            file_length,
+        );
+
+        let mut data = Vec::new();
+        for page in logical.chunks(PAGE_SIZE - CHECKSUM_SIZE as usize) {
+            data.extend_from_slice(page);
+            data.extend_from_slice(&[0; CHECKSUM_SIZE as usize]);
+        }
+        File::create(path).unwrap().write_all(&data).unwrap();
+    }
+
+    fn read_points(path: &Path) -> Vec<Point> {
+        let mut points = Vec::new();
+        E57Iterator::new(path)
+            .unwrap()
+            .for_each(|p| points.push(p.clone()))
+            .unwrap();
+        points
+    }
+
+    #[test]
+    fn test_read_points_across_pages() {
+        // Enough points so that the data packet spans several pages.
+        let input: Vec<_> = (0..200)
+            .map(|i| (i as f32, 2. * i as f32, -1., i as u8))
+            .collect();
+        let path = env::temp_dir().join("e57_test_read_points_across_pages.e57");
+        write_e57(&path, &input, "");
+
+        assert_eq!(Some(200), E57Iterator::new(&path).unwrap().size_hint());
+        let points = read_points(&path);
+        assert_eq!(200, points.len());
+        assert_eq!(Vector3f::new(199., 398., -1.), points[199].position);
+        assert_eq!(199, points[199].r);
+        assert_eq!(0, points[199].g);
+    }
+
+    #[test]
+    fn test_pose_is_applied() {
+        let path = env::temp_dir().join("e57_test_pose_is_applied.e57");
+        // Rotation by 90 degrees around z, followed by a translation.
+        let pose = r#"<pose type="Structure">
+            <rotation type="Structure">
+              <w type="Float">0.7071067811865476</w>
+              <x type="Float">0</x>
+              <y type="Float">0</y>
+              <z type="Float">0.7071067811865476</z>
+            </rotation>
+            <translation type="Structure">
+              <x type="Float">10</x>
+              <y type="Float">0</y>
+              <z type="Float">0</z>
+            </translation>
+          </pose>"#;
+        write_e57(&path, &[(1., 0., 0., 255)], pose);
+        let points = read_points(&path);
+        assert_eq!(1, points.len());
+        assert!((points[0].position - Vector3f::new(10., 1., 0.)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn test_full_integer_range() {
+        let encoding = Encoding::Integer {
+            minimum: i64::min_value(),
+            maximum: i64::max_value(),
+        };
+        assert_eq!(64, encoding.num_bits());
+        assert_eq!(i64::min_value(), offset_by(0, i64::min_value()));
+        assert_eq!(i64::max_value(), offset_by(u64::max_value(), i64::min_value()));
+        assert_eq!(-1, offset_by(4, -5));
+    }
+
+    #[test]
+    fn test_xml_length_larger_than_file() {
+        let path = env::temp_dir().join("e57_test_xml_length_larger_than_file.e57");
+        write_e57(&path, &[(1., 0., 0., 255)], "");
+        let mut data = Vec::new();
+        File::open(&path).unwrap().read_to_end(&mut data).unwrap();
+        LittleEndian::write_u64(&mut data[32..40], u64::max_value());
+        File::create(&path).unwrap().write_all(&data).unwrap();
+        assert!(E57Iterator::new(&path).is_err());
+    }
+}
@@ -17,26 +17,99 @@
 extern crate byteorder;
 extern crate bytes;
 extern crate cgmath;
+extern crate flate2;
 extern crate num;
 extern crate num_traits;
 extern crate prost;
 #[macro_use]
 extern crate prost_derive;
+extern crate rand;
+extern crate scoped_pool;
 extern crate walkdir;
+extern crate xml;
+extern crate zstd;
 #[macro_use]
 extern crate error_chain;
 
+pub mod cache;
+pub mod compression;
+pub mod duplicates;
+pub mod e57;
 pub mod math;
 pub mod octree;
+pub mod outliers;
+pub mod pcd;
+pub mod pipeline;
 pub mod ply;
 pub mod pts;
 pub mod errors;
 
 pub trait InternalIterator {
-    fn for_each<F: FnMut(&Point)>(self, F);
+    /// Calls 'f' for every point. Stops at the first point that cannot be read and returns the
+    /// error.
+    fn for_each<F: FnMut(&Point)>(self, F) -> errors::Result<()>;
     fn size_hint(&self) -> Option<usize>;
 }
 
+impl InternalIterator for Vec<Point> {
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.len())
+    }
+
+    fn for_each<F: FnMut(&Point)>(self, mut f: F) -> errors::Result<()> {
+        for p in &self {
+            f(p);
+        }
+        Ok(())
+    }
+}
+
+/// Groups the points of a pull-based point iterator into batches of up to 'batch_size' points,
+/// which is cheaper than handling points one by one. An error ends iteration, the points already
+/// collected into the failing batch are dropped.
+pub struct Batches<I> {
+    points: I,
+    batch_size: usize,
+    is_done: bool,
+}
+
+impl<I> Batches<I>
+    where I: Iterator<Item = errors::Result<Point>>
+{
+    pub fn new(points: I, batch_size: usize) -> Self {
+        assert!(batch_size > 0);
+        Batches {
+            points: points,
+            batch_size: batch_size,
+            is_done: false,
+        }
+    }
+}
+
+impl<I> Iterator for Batches<I>
+    where I: Iterator<Item = errors::Result<Point>>
+{
+    type Item = errors::Result<Vec<Point>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_done {
+            return None;
+        }
+        let mut batch = Vec::with_capacity(self.batch_size);
+        while batch.len() < self.batch_size {
+            match self.points.next() {
+                Some(Ok(point)) => batch.push(point),
+                Some(Err(err)) => {
+                    self.is_done = true;
+                    return Some(Err(err));
+                }
+                None => break,
+            }
+        }
+        if batch.is_empty() { None } else { Some(Ok(batch)) }
+    }
+}
+
 #[derive(Debug,Clone)]
 pub struct Point {
     pub position: math::Vector3f,
@@ -48,3 +121,44 @@ pub struct Point {
 pub mod proto {
     include!(concat!(env!("OUT_DIR"), "/proto.rs"));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f32) -> Point {
+        Point {
+            position: math::Vector3f::new(x, 0., 0.),
+            r: 0,
+            g: 0,
+            b: 0,
+        }
+    }
+
+    fn batch_lengths<I: Iterator<Item = errors::Result<Point>>>(batches: Batches<I>) -> Vec<usize> {
+        batches.map(|batch| batch.unwrap().len()).collect()
+    }
+
+    #[test]
+    fn test_batches() {
+        let points: Vec<errors::Result<Point>> = (0..5).map(|i| Ok(point(i as f32))).collect();
+        let mut batches = Batches::new(points.into_iter(), 2);
+        let first = batches.next().unwrap().unwrap();
+        assert_eq!(vec![0., 1.], first.iter().map(|p| p.position.x).collect::<Vec<_>>());
+        assert_eq!(vec![2, 1], batch_lengths(batches));
+
+        let points: Vec<errors::Result<Point>> = (0..4).map(|i| Ok(point(i as f32))).collect();
+        assert_eq!(vec![4], batch_lengths(Batches::new(points.into_iter(), 4)));
+        let points: Vec<errors::Result<Point>> = Vec::new();
+        assert!(Batches::new(points.into_iter(), 4).next().is_none());
+    }
+
+    #[test]
+    fn test_batches_stop_at_error() {
+        let points: Vec<errors::Result<Point>> =
+            vec![Ok(point(0.)), Ok(point(1.)), Err("broken".into()), Ok(point(2.))];
+        let mut batches = Batches::new(points.into_iter(), 3);
+        assert!(batches.next().unwrap().is_err());
+        assert!(batches.next().is_none());
+    }
+}
@@ -34,6 +34,14 @@ pub trait CuboidLike {
         min.x <= p.x && p.x <= max.x && min.y <= p.y && p.y <= max.y && min.z <= p.z && p.z <= max.z
     }
 
+    /// Returns true if this box and 'other' overlap.
+    fn intersects<C: CuboidLike>(&self, other: &C) -> bool {
+        let (min, max) = (self.min(), self.max());
+        let (other_min, other_max) = (other.min(), other.max());
+        min.x <= other_max.x && other_min.x <= max.x && min.y <= other_max.y &&
+        other_min.y <= max.y && min.z <= other_max.z && other_min.z <= max.z
+    }
+
     /// The center of the box.
     fn center(&self) -> Vector3f {
         let min = self.min();
@@ -213,6 +221,12 @@ impl Cuboid {
         }
     }
 
+    /// Creates a box spanning from 'min' to 'max'.
+    pub fn from_min_max(min: Vector3f, max: Vector3f) -> Self {
+        assert!(min.x <= max.x && min.y <= max.y && min.z <= max.z);
+        Cuboid { min: min, max: max }
+    }
+
     /// Grows the box to contain 'p'.
     pub fn update(&mut self, p: &Vector3f) {
         self.min.x = self.min.x.min(p.x);
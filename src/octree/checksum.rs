@@ -0,0 +1,102 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io::{self, Write};
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// A 64 bit FNV-1a hash over a stream of bytes. This is not cryptographically secure, it is only
+/// meant to detect truncated or corrupted node files.
+#[derive(Debug,Clone,Copy)]
+pub struct Checksum {
+    state: u64,
+}
+
+impl Checksum {
+    pub fn new() -> Self {
+        Checksum { state: FNV_OFFSET_BASIS }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.state ^= *byte as u64;
+            self.state = self.state.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn value(&self) -> u64 {
+        self.state
+    }
+}
+
+/// Forwards all writes to 'inner' while keeping a checksum of everything written.
+#[derive(Debug)]
+pub struct ChecksumWriter<W: Write> {
+    inner: W,
+    checksum: Checksum,
+}
+
+impl<W: Write> ChecksumWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChecksumWriter {
+            inner: inner,
+            checksum: Checksum::new(),
+        }
+    }
+
+    pub fn checksum(&self) -> u64 {
+        self.checksum.value()
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let num_written = self.inner.write(buf)?;
+        self.checksum.update(&buf[..num_written]);
+        Ok(num_written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_values() {
+        assert_eq!(0xcbf29ce484222325, Checksum::new().value());
+
+        let mut checksum = Checksum::new();
+        checksum.update(b"a");
+        assert_eq!(0xaf63dc4c8601ec8c, checksum.value());
+
+        let mut checksum = Checksum::new();
+        checksum.update(b"foobar");
+        assert_eq!(0x85944171f73967e8, checksum.value());
+    }
+
+    #[test]
+    fn test_writer_matches_checksum() {
+        let mut writer = ChecksumWriter::new(Vec::new());
+        writer.write_all(b"foo").unwrap();
+        writer.write_all(b"bar").unwrap();
+        let mut checksum = Checksum::new();
+        checksum.update(b"foobar");
+        assert_eq!(checksum.value(), writer.checksum());
+    }
+}
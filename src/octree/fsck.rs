@@ -0,0 +1,368 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use InternalIterator;
+use errors::*;
+use math::{CuboidLike, Vector3f};
+use octree::{self, COLOR_EXT, ChildIndex, META_EXT, Node, NodeId, NodeIterator, NodeMeta,
+             POSITION_EXT};
+use octree::checksum::Checksum;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use walkdir;
+
+/// Points are allowed to be this far outside of their node, relative to its edge length, to
+/// account for floating point errors while encoding.
+const CONTAINMENT_TOLERANCE: f32 = 1e-4;
+
+/// A single inconsistency found in an octree directory.
+#[derive(Debug)]
+pub enum Problem {
+    /// There is a position or color file on disk, but no parseable node protobuf.
+    InvalidMeta(NodeId, String),
+    /// The given file of the node is missing.
+    MissingFile(NodeId, &'static str),
+    /// The given file of the node has a different size than the node protobuf implies.
+    WrongFileSize {
+        id: NodeId,
+        extension: &'static str,
+        expected: u64,
+        actual: u64,
+    },
+    /// The given file of the node does not match the checksum recorded while building.
+    ChecksumMismatch(NodeId, &'static str),
+    /// The given file of the node could not be read.
+    UnreadableFile(NodeId, &'static str, String),
+    /// The points of the node could not be read.
+    UnreadablePoints(NodeId, String),
+    /// The node exists on disk, but its parent does not.
+    Orphan(NodeId),
+    /// The bounding cube in the node protobuf does not match the node's position in the tree.
+    WrongBoundingCube(NodeId),
+    /// The given number of points in the node lie outside of its bounding cube.
+    PointsOutsideOfNode(NodeId, i64),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Problem::InvalidMeta(ref id, ref msg) => write!(f, "{}: invalid meta: {}", id, msg),
+            Problem::MissingFile(ref id, extension) => {
+                write!(f, "{}: missing .{} file", id, extension)
+            }
+            Problem::WrongFileSize {
+                ref id,
+                extension,
+                expected,
+                actual,
+            } => {
+                write!(
+                    f,
+                    "{}: .{} file has {} bytes, expected {}",
+                    id,
+                    extension,
+                    actual,
+                    expected
+                )
+            }
+            Problem::ChecksumMismatch(ref id, extension) => {
+                write!(f, "{}: checksum mismatch in .{} file", id, extension)
+            }
+            Problem::UnreadableFile(ref id, extension, ref msg) => {
+                write!(f, "{}: could not read .{} file: {}", id, extension, msg)
+            }
+            Problem::UnreadablePoints(ref id, ref msg) => {
+                write!(f, "{}: could not read points: {}", id, msg)
+            }
+            Problem::Orphan(ref id) => write!(f, "{}: parent node does not exist", id),
+            Problem::WrongBoundingCube(ref id) => {
+                write!(f, "{}: bounding cube does not match position in tree", id)
+            }
+            Problem::PointsOutsideOfNode(ref id, num_points) => {
+                write!(f, "{}: {} points lie outside of the node", id, num_points)
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FsckReport {
+    pub num_nodes_checked: usize,
+    pub num_points_checked: i64,
+    pub num_checksums_verified: usize,
+    pub problems: Vec<Problem>,
+}
+
+impl FsckReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+fn is_node_name(name: &str) -> bool {
+    name.starts_with('r') && name[1..].chars().all(|c| c >= '0' && c <= '7')
+}
+
+/// Returns the ids of all nodes that have at least one file in 'directory'.
+fn find_node_ids(directory: &Path) -> HashSet<NodeId> {
+    let mut ids = HashSet::new();
+    for entry in walkdir::WalkDir::new(directory)
+            .into_iter()
+            .filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let extension = path.extension().and_then(|e| e.to_str());
+        if extension != Some(META_EXT) && extension != Some(POSITION_EXT) &&
+           extension != Some(COLOR_EXT) {
+            continue;
+        }
+        match path.file_stem().and_then(|s| s.to_str()) {
+            Some(stem) if is_node_name(stem) => {
+                ids.insert(NodeId::from_str(stem));
+            }
+            _ => (),
+        }
+    }
+    ids
+}
+
+fn file_checksum(path: &Path) -> Result<u64> {
+    let mut file = File::open(path)?;
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut checksum = Checksum::new();
+    loop {
+        let num_read = file.read(&mut buf)?;
+        if num_read == 0 {
+            break;
+        }
+        checksum.update(&buf[..num_read]);
+    }
+    Ok(checksum.value())
+}
+
+fn same_cube<C: CuboidLike>(a: &C, b: &C) -> bool {
+    let size = a.size();
+    let epsilon = CONTAINMENT_TOLERANCE * size.x.max(size.y).max(size.z);
+    let diff_min = a.min() - b.min();
+    let diff_max = a.max() - b.max();
+    diff_min.x.abs() <= epsilon && diff_min.y.abs() <= epsilon && diff_min.z.abs() <= epsilon &&
+    diff_max.x.abs() <= epsilon && diff_max.y.abs() <= epsilon && diff_max.z.abs() <= epsilon
+}
+
+/// Checks a single node whose meta was already parsed and adds all problems to 'report'.
+fn check_node(directory: &Path, node: &Node, meta: &NodeMeta, report: &mut FsckReport) {
+    let id = node.id;
+    let num_problems = report.problems.len();
+
+    let files = [
+        (
+            POSITION_EXT,
+            meta.position_encoding.bytes_per_coordinate() as u64 * 3,
+            meta.position_checksum,
+        ),
+        (COLOR_EXT, 3, meta.color_checksum),
+    ];
+    for &(extension, bytes_per_point, checksum) in &files {
+        let path = meta.stem.with_extension(extension);
+        let actual = match fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => {
+                report.problems.push(Problem::MissingFile(id, extension));
+                continue;
+            }
+        };
+        let expected = bytes_per_point * meta.num_points as u64;
+        if actual != expected {
+            report
+                .problems
+                .push(
+                    Problem::WrongFileSize {
+                        id: id,
+                        extension: extension,
+                        expected: expected,
+                        actual: actual,
+                    }
+                );
+            continue;
+        }
+        if let Some(checksum) = checksum {
+            match file_checksum(&path) {
+                Ok(actual) => {
+                    if actual != checksum {
+                        report.problems.push(Problem::ChecksumMismatch(id, extension));
+                    }
+                    report.num_checksums_verified += 1;
+                }
+                Err(err) => {
+                    report
+                        .problems
+                        .push(Problem::UnreadableFile(id, extension, err.to_string()));
+                }
+            }
+        }
+    }
+
+    if !same_cube(&node.bounding_cube, &meta.bounding_cube) {
+        report.problems.push(Problem::WrongBoundingCube(id));
+    }
+
+    // Only read the points if the files are complete, otherwise the iterator would run past their
+    // end.
+    if report.problems.len() != num_problems {
+        return;
+    }
+
+    let min = meta.bounding_cube.min();
+    let max = meta.bounding_cube.max();
+    let epsilon = CONTAINMENT_TOLERANCE * meta.bounding_cube.edge_length();
+    let tolerance = Vector3f::new(epsilon, epsilon, epsilon);
+    let (min, max) = (min - tolerance, max + tolerance);
+    let mut num_outside = 0;
+    let result = NodeIterator::from_disk(directory, &id).and_then(
+        |iterator| {
+            iterator.for_each(
+                |p| {
+                    let v = &p.position;
+                    if !(min.x <= v.x && v.x <= max.x && min.y <= v.y && v.y <= max.y &&
+                         min.z <= v.z && v.z <= max.z) {
+                        num_outside += 1;
+                    }
+                }
+            )
+        }
+    );
+    if let Err(err) = result {
+        report
+            .problems
+            .push(Problem::UnreadablePoints(id, err.to_string()));
+        return;
+    }
+    report.num_points_checked += meta.num_points;
+    if num_outside > 0 {
+        report.problems.push(Problem::PointsOutsideOfNode(id, num_outside));
+    }
+}
+
+/// Verifies the integrity of the octree in 'directory': 'meta.pb' must be readable and have the
+/// current version, every node must have a parseable protobuf and position and color files of the
+/// correct size, every node except the root must have a parent, all points must lie inside their
+/// node and the files must match the checksums stored at build time, if any.
+///
+/// Returns an error only if the octree could not be checked at all, all inconsistencies are
+/// collected in the returned report.
+pub fn fsck<P: AsRef<Path>>(directory: P) -> Result<FsckReport> {
+    let directory = directory.as_ref();
+    let octree = octree::Octree::new(directory)?;
+
+    let mut report = FsckReport {
+        num_nodes_checked: 0,
+        num_points_checked: 0,
+        num_checksums_verified: 0,
+        problems: Vec::new(),
+    };
+
+    let mut unvisited = find_node_ids(directory);
+    let root = Node::root_with_bounding_cube(octree.bounding_cube().clone());
+    if !unvisited.contains(&root.id) {
+        report.problems.push(Problem::MissingFile(root.id, META_EXT));
+    }
+
+    // Walk the tree from the root and only descend into nodes that exist. Everything that is left
+    // unvisited afterwards has no parent.
+    let mut open = vec![root];
+    while let Some(node) = open.pop() {
+        if !unvisited.remove(&node.id) {
+            continue;
+        }
+        report.num_nodes_checked += 1;
+
+        match NodeMeta::from_disk(directory, &node.id) {
+            Ok(meta) => check_node(directory, &node, &meta, &mut report),
+            Err(Error(ErrorKind::NodeNotFound, _)) => {
+                report.problems.push(Problem::MissingFile(node.id, META_EXT));
+            }
+            Err(err) => {
+                report
+                    .problems
+                    .push(Problem::InvalidMeta(node.id, err.to_string()));
+            }
+        }
+
+        for child_index in 0..8 {
+            open.push(node.get_child(ChildIndex::from_u8(child_index)));
+        }
+    }
+
+    let mut orphans: Vec<_> = unvisited.into_iter().collect();
+    orphans.sort_by_key(|id| id.to_string());
+    for id in orphans {
+        report.num_nodes_checked += 1;
+        report.problems.push(Problem::Orphan(id));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use octree::testing;
+    use std::fs::OpenOptions;
+    use std::path::PathBuf;
+
+    fn write_test_octree(name: &str) -> PathBuf {
+        let root = testing::test_node("r");
+        let child = testing::test_node("r0");
+        testing::write_test_octree(
+            name,
+            &[
+                ("r", vec![testing::red_point(root.bounding_cube.center())]),
+                ("r0", vec![testing::red_point(child.bounding_cube.center())]),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_valid_octree() {
+        let directory = write_test_octree("fsck_test_valid_octree");
+        let report = fsck(&directory).unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+        assert_eq!(2, report.num_nodes_checked);
+        assert_eq!(4, report.num_checksums_verified);
+    }
+
+    #[test]
+    fn test_truncated_and_orphaned_nodes() {
+        let directory = write_test_octree("fsck_test_truncated_and_orphaned_nodes");
+        OpenOptions::new()
+            .write(true)
+            .open(directory.join("r0.rgb"))
+            .unwrap()
+            .set_len(1)
+            .unwrap();
+        fs::copy(directory.join("r0.pb"), directory.join("r01234.pb")).unwrap();
+
+        let report = fsck(&directory).unwrap();
+        assert_eq!(3, report.num_nodes_checked);
+        let problems: Vec<_> = report.problems.iter().map(|p| p.to_string()).collect();
+        assert_eq!(
+            vec![
+                "r0: .rgb file has 1 bytes, expected 3",
+                "r01234: parent node does not exist",
+            ],
+            problems
+        );
+    }
+}
@@ -12,22 +12,35 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use {InternalIterator, Point};
 use bytes::{Buf, IntoBuf};
 use errors::*;
 use math::{Cube, Cuboid, CuboidLike, Frustum, Matrix4f, Vector2f, Vector3f};
 use prost::Message;
 use proto;
+use scoped_pool::Pool;
 use std::cmp;
 use std::collections::HashMap;
 use std::fs::{self, File};
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
 use walkdir;
 
+mod checksum;
+mod fsck;
 mod node;
+mod query;
+// Only meant for tests, but public behind a feature so that the tests of other crates can use it.
+#[cfg(any(test, feature = "testing"))]
+#[doc(hidden)]
+pub mod testing;
 
-pub use self::node::{ChildIndex, Node, NodeId, NodeIterator, NodeMeta, NodeWriter,
-                     PositionEncoding};
+pub use self::fsck::{FsckReport, Problem, fsck};
+pub use self::node::{COLOR_EXT, ChildIndex, META_EXT, Node, NodeId, NodeIterator, NodeMeta,
+                     NodePoints, NodeWriter, POSITION_EXT, PositionEncoding};
+pub use self::query::QueryResult;
 
 pub const CURRENT_VERSION: i32 = 7;
 
@@ -84,6 +97,9 @@ pub struct Octree {
     // Maps from node id to number of points.
     nodes: HashMap<NodeId, u64>,
     bounding_cube: Cube,
+    resolution: f64,
+    version: i32,
+    build_id: u64,
 }
 
 #[derive(Debug)]
@@ -99,6 +115,21 @@ pub struct NodeData {
     pub color: Vec<u8>,
 }
 
+// Returns 'value' or an error naming the 'field' that is missing in meta.pb.
+fn required<T>(value: Option<T>, field: &str) -> Result<T> {
+    value.ok_or_else(|| format!("meta.pb has no {}.", field).into())
+}
+
+/// Reads 'meta.pb' of the octree in 'directory' as it is, without checking its version.
+pub fn read_meta(directory: &Path) -> Result<proto::Meta> {
+    let mut data = Vec::new();
+    File::open(&directory.join("meta.pb"))?
+        .read_to_end(&mut data)?;
+    let len = data.len();
+    proto::Meta::decode(&mut Buf::take(data.into_buf(), len))
+        .chain_err(|| "Could not parse meta.pb")
+}
+
 impl Octree {
     pub fn new<P: AsRef<Path>>(directory: P) -> Result<Self> {
         let directory = directory.as_ref();
@@ -107,26 +138,35 @@ impl Octree {
             return Err(ErrorKind::InvalidVersion(3).into());
         }
 
-        let meta = {
-            let mut data = Vec::new();
-            File::open(&directory.join("meta.pb"))?
-                .read_to_end(&mut data)?;
-            let len = data.len();
-            proto::Meta::decode(&mut Buf::take(data.into_buf(), len))
-                .chain_err(|| "Could not parse meta.pb")?
+        let meta = read_meta(directory)?;
+
+        // Octrees without build id use the time they were written instead.
+        let build_id = match meta.build_id {
+            Some(build_id) => build_id,
+            None => {
+                fs::metadata(&directory.join("meta.pb"))?
+                    .modified()?
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0)
+            }
         };
 
-        let version = meta.version.unwrap();
+        let version = required(meta.version, "version")?;
         if version != CURRENT_VERSION {
             return Err(ErrorKind::InvalidVersion(version).into());
         }
 
         let bounding_cube = {
-            let bounding_cube = meta.bounding_cube.unwrap();
-            let min = bounding_cube.min.unwrap();
+            let bounding_cube = required(meta.bounding_cube, "bounding cube")?;
+            let min = required(bounding_cube.min, "bounding cube min")?;
             Cube::new(
-                Vector3f::new(min.x.unwrap(), min.y.unwrap(), min.z.unwrap()),
-                bounding_cube.edge_length.unwrap(),
+                Vector3f::new(
+                    required(min.x, "bounding cube min")?,
+                    required(min.y, "bounding cube min")?,
+                    required(min.z, "bounding cube min")?,
+                ),
+                required(bounding_cube.edge_length, "bounding cube edge length")?,
             )
         };
 
@@ -143,22 +183,102 @@ impl Octree {
             if !file_name_str.starts_with("r") || !file_name_str.ends_with(".xyz") {
                 continue;
             }
-            let num_points = fs::metadata(path).unwrap().len() / 12;
+            // The size of the positions depends on their encoding, colors always take 3 bytes.
+            let num_points = fs::metadata(path.with_extension(COLOR_EXT))
+                .map(|metadata| metadata.len() / 3)
+                .unwrap_or(0);
             nodes.insert(
                 NodeId::from_str(path.file_stem().unwrap().to_str().unwrap()),
                 num_points,
             );
         }
 
+        let resolution = required(meta.resolution, "resolution")?;
+
         Ok(
             Octree {
                 directory: directory.into(),
                 nodes: nodes,
                 bounding_cube: bounding_cube,
+                resolution: resolution,
+                version: version,
+                build_id: build_id,
             }
         )
     }
 
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn bounding_cube(&self) -> &Cube {
+        &self.bounding_cube
+    }
+
+    pub fn resolution(&self) -> f64 {
+        self.resolution
+    }
+
+    /// The version read from meta.pb.
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    /// Changes whenever the octree is rebuilt.
+    pub fn build_id(&self) -> u64 {
+        self.build_id
+    }
+
+    /// Returns true if the node 'id' is part of the octree.
+    pub fn has_node(&self, id: &NodeId) -> bool {
+        self.nodes.contains_key(id)
+    }
+
+    /// Returns the number of points in the node 'id' without reading it, or None if the node is
+    /// not part of the octree.
+    pub fn num_points(&self, id: &NodeId) -> Option<u64> {
+        self.nodes.get(id).cloned()
+    }
+
+    /// Returns the ids of all nodes in the octree in no particular order.
+    pub fn node_ids(&self) -> Vec<NodeId> {
+        self.nodes.keys().cloned().collect()
+    }
+
+    /// Calls 'f' for every point in the nodes 'node_ids', reading the nodes in parallel on
+    /// 'pool'. 'f' is called concurrently from multiple threads, but the points of one node are
+    /// always visited in order by the same thread. Returns the first error encountered, all nodes
+    /// are visited regardless.
+    pub fn for_each_point_in_parallel<F>(
+        &self,
+        pool: &Pool,
+        node_ids: &[NodeId],
+        f: F,
+    ) -> Result<()>
+        where F: Fn(&NodeId, &Point) + Sync
+    {
+        let errors = Mutex::new(Vec::new());
+        pool.scoped(
+            |scope| for id in node_ids {
+                let f = &f;
+                let errors = &errors;
+                scope.execute(
+                    move || {
+                        let result = NodeIterator::from_disk(&self.directory, id)
+                            .and_then(|iterator| iterator.for_each(|p| f(id, p)));
+                        if let Err(err) = result {
+                            errors.lock().unwrap().push(err);
+                        }
+                    }
+                );
+            }
+        );
+        match errors.into_inner().unwrap().into_iter().next() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
     pub fn get_visible_nodes(
         &self,
         projection_matrix: &Matrix4f,
@@ -281,3 +401,68 @@ impl Octree {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn write_test_octree(name: &str) -> Octree {
+        let nodes: Vec<(&str, Vec<Point>)> = ["r", "r0", "r1"]
+            .iter()
+            .map(
+                |id| {
+                    let center = testing::test_node(id).bounding_cube.center();
+                    (*id, vec![testing::red_point(center); 2])
+                }
+            )
+            .collect();
+        Octree::new(testing::write_test_octree(name, &nodes)).unwrap()
+    }
+
+    #[test]
+    fn test_num_points() {
+        let octree = write_test_octree("octree_test_num_points");
+        assert_eq!(Some(2), octree.num_points(&NodeId::from_str("r0")));
+        assert_eq!(None, octree.num_points(&NodeId::from_str("r2")));
+    }
+
+    #[test]
+    fn test_for_each_point_in_parallel() {
+        let octree = write_test_octree("octree_test_for_each_point_in_parallel");
+        let visited = Mutex::new(Vec::new());
+        octree
+            .for_each_point_in_parallel(
+                &Pool::new(2),
+                &octree.node_ids(),
+                |id, p| visited.lock().unwrap().push((*id, p.position)),
+            )
+            .unwrap();
+        let visited = visited.into_inner().unwrap();
+        assert_eq!(6, visited.len());
+        let ids: HashSet<_> = visited.iter().map(|&(ref id, _)| id.to_string()).collect();
+        assert_eq!(3, ids.len());
+        for &(ref id, ref position) in &visited {
+            assert!(
+                testing::test_node(&id.to_string())
+                    .bounding_cube
+                    .contains(position)
+            );
+        }
+    }
+
+    #[test]
+    fn test_for_each_point_in_parallel_returns_error() {
+        let octree = write_test_octree("octree_test_for_each_point_in_parallel_returns_error");
+        let num_points = Mutex::new(0);
+        let node_ids = [NodeId::from_str("r"), NodeId::from_str("r7")];
+        let result = octree.for_each_point_in_parallel(
+            &Pool::new(2),
+            &node_ids,
+            |_, _| *num_points.lock().unwrap() += 1,
+        );
+        assert!(result.is_err());
+        // The nodes that can be read are still visited.
+        assert_eq!(2, num_points.into_inner().unwrap());
+    }
+}
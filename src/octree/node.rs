@@ -17,6 +17,7 @@ use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use bytes::{Buf, IntoBuf};
 use errors::*;
 use math::{Cube, CuboidLike, Vector3f, Zero, clamp};
+use octree::checksum::ChecksumWriter;
 use num;
 use num_traits;
 use prost::Message;
@@ -84,8 +85,8 @@ impl NodeId {
         NodeId { level, index }
     }
 
-    /// Returns the path on disk where the data for this node is saved.
-    fn get_stem(&self, directory: &Path) -> PathBuf {
+    /// Returns the path on disk where the data for this node is saved, without extension.
+    pub fn get_stem(&self, directory: &Path) -> PathBuf {
         directory.join(&self.to_string())
     }
 
@@ -95,7 +96,7 @@ impl NodeId {
     }
 
     /// Returns the NodeId for the corresponding 'child_index'.
-    fn get_child_id(&self, child_index: ChildIndex) -> Self {
+    pub fn get_child_id(&self, child_index: ChildIndex) -> Self {
         NodeId {
             level: self.level + 1,
             index: (self.index << 3) + child_index.0 as usize,
@@ -124,7 +125,7 @@ impl NodeId {
     }
 
     /// Returns the level of this node in the octree, with 0 being the root.
-    fn level(&self) -> usize {
+    pub fn level(&self) -> usize {
         self.level as usize
     }
 }
@@ -221,6 +222,9 @@ pub struct NodeMeta {
     pub num_points: i64,
     pub position_encoding: PositionEncoding,
     pub bounding_cube: Cube,
+    // Checksums of the position and color files, if they were recorded while building.
+    pub position_checksum: Option<u64>,
+    pub color_checksum: Option<u64>,
 }
 
 impl NodeMeta {
@@ -240,24 +244,28 @@ impl NodeMeta {
                 .chain_err(|| "Could not parse node protobuf.")?
         };
 
+        let invalid = || ErrorKind::InvalidInput(format!("Incomplete node protobuf for {}.", id));
         Ok(
             NodeMeta {
-                num_points: meta.num_points.unwrap(),
+                num_points: meta.num_points.ok_or_else(&invalid)?,
                 position_encoding: PositionEncoding::from_proto(
-                    proto::node::PositionEncoding::from_i32(
-                        meta.position_encoding.unwrap(),
-                    )
-                            .unwrap()
+                    meta.position_encoding
+                        .and_then(proto::node::PositionEncoding::from_i32)
+                        .ok_or_else(&invalid)?
                 ),
                 // TODO(hrapp): Would be nice to have a from_proto and to_proto as a trait.
                 bounding_cube: {
-                    let proto = meta.bounding_cube.unwrap();
-                    let min = proto.min.unwrap();
-                    Cube::new(
-                        Vector3f::new(min.x.unwrap(), min.y.unwrap(), min.z.unwrap()),
-                        proto.edge_length.unwrap(),
-                    )
+                    let proto = meta.bounding_cube.ok_or_else(&invalid)?;
+                    let min = proto.min.ok_or_else(&invalid)?;
+                    match (min.x, min.y, min.z, proto.edge_length) {
+                        (Some(x), Some(y), Some(z), Some(edge_length)) => {
+                            Cube::new(Vector3f::new(x, y, z), edge_length)
+                        }
+                        _ => return Err(invalid().into()),
+                    }
                 },
+                position_checksum: meta.position_checksum,
+                color_checksum: meta.color_checksum,
                 stem: stem,
             }
         )
@@ -273,6 +281,8 @@ pub struct NodeIterator {
     xyz_reader: BufReader<File>,
     rgb_reader: BufReader<File>,
     meta: NodeMeta,
+    point: Point,
+    num_points_read: i64,
 }
 
 impl NodeIterator {
@@ -283,9 +293,112 @@ impl NodeIterator {
                 xyz_reader: BufReader::new(File::open(&meta.stem.with_extension(POSITION_EXT))?),
                 rgb_reader: BufReader::new(File::open(&meta.stem.with_extension(COLOR_EXT))?),
                 meta: meta,
+                point: Point {
+                    position: Vector3f::zero(),
+                    r: 0,
+                    g: 0,
+                    b: 0,
+                },
+                num_points_read: 0,
             }
         )
     }
+
+    fn read_point(&mut self) -> Result<()> {
+        let edge_length = self.meta.bounding_cube.edge_length();
+        let min = self.meta.bounding_cube.min();
+
+        // I tried pulling out this match by taking a function pointer to a 'decode_position'
+        // function. This replaces a branch per point vs a function call per point and turned
+        // out to be marginally slower.
+        match self.meta.position_encoding {
+            PositionEncoding::Float32 => {
+                self.point.position.x = decode(
+                    self.xyz_reader.read_f32::<LittleEndian>()?,
+                    min.x,
+                    edge_length,
+                );
+                self.point.position.y = decode(
+                    self.xyz_reader.read_f32::<LittleEndian>()?,
+                    min.y,
+                    edge_length,
+                );
+                self.point.position.z = decode(
+                    self.xyz_reader.read_f32::<LittleEndian>()?,
+                    min.z,
+                    edge_length,
+                );
+            }
+            PositionEncoding::Uint8 => {
+                self.point.position.x =
+                    fixpoint_decode(self.xyz_reader.read_u8()?, min.x, edge_length);
+                self.point.position.y =
+                    fixpoint_decode(self.xyz_reader.read_u8()?, min.y, edge_length);
+                self.point.position.z =
+                    fixpoint_decode(self.xyz_reader.read_u8()?, min.z, edge_length);
+            }
+            PositionEncoding::Uint16 => {
+                self.point.position.x = fixpoint_decode(
+                    self.xyz_reader.read_u16::<LittleEndian>()?,
+                    min.x,
+                    edge_length,
+                );
+                self.point.position.y = fixpoint_decode(
+                    self.xyz_reader.read_u16::<LittleEndian>()?,
+                    min.y,
+                    edge_length,
+                );
+                self.point.position.z = fixpoint_decode(
+                    self.xyz_reader.read_u16::<LittleEndian>()?,
+                    min.z,
+                    edge_length,
+                );
+            }
+        }
+
+        self.point.r = self.rgb_reader.read_u8()?;
+        self.point.g = self.rgb_reader.read_u8()?;
+        self.point.b = self.rgb_reader.read_u8()?;
+        Ok(())
+    }
+}
+
+/// Pull-based iterator over the points of a node. Iteration stops after the first error.
+pub struct NodePoints(NodeIterator);
+
+impl Iterator for NodePoints {
+    type Item = Result<Point>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = &mut self.0;
+        if node.num_points_read >= node.meta.num_points {
+            return None;
+        }
+        match node.read_point() {
+            Ok(()) => {
+                node.num_points_read += 1;
+                Some(Ok(node.point.clone()))
+            }
+            Err(err) => {
+                node.num_points_read = node.meta.num_points;
+                Some(Err(err))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let num_left = (self.0.meta.num_points - self.0.num_points_read) as usize;
+        (0, Some(num_left))
+    }
+}
+
+impl IntoIterator for NodeIterator {
+    type Item = Result<Point>;
+    type IntoIter = NodePoints;
+
+    fn into_iter(self) -> Self::IntoIter {
+        NodePoints(self)
+    }
 }
 
 impl InternalIterator for NodeIterator {
@@ -293,70 +406,11 @@ impl InternalIterator for NodeIterator {
         Some(self.meta.num_points as usize)
     }
 
-    fn for_each<F: FnMut(&Point)>(mut self, mut f: F) {
-        let mut point = Point {
-            position: Vector3f::zero(),
-            r: 0,
-            g: 0,
-            b: 0,
-        };
-
-        let edge_length = self.meta.bounding_cube.edge_length();
-        let min = self.meta.bounding_cube.min();
-        for _ in 0..self.meta.num_points {
-            // I tried pulling out this match by taking a function pointer to a 'decode_position'
-            // function. This replaces a branch per point vs a function call per point and turned
-            // out to be marginally slower.
-            match self.meta.position_encoding {
-                PositionEncoding::Float32 => {
-                    point.position.x = decode(
-                        self.xyz_reader.read_f32::<LittleEndian>().unwrap(),
-                        min.x,
-                        edge_length,
-                    );
-                    point.position.y = decode(
-                        self.xyz_reader.read_f32::<LittleEndian>().unwrap(),
-                        min.y,
-                        edge_length,
-                    );
-                    point.position.z = decode(
-                        self.xyz_reader.read_f32::<LittleEndian>().unwrap(),
-                        min.z,
-                        edge_length,
-                    );
-                }
-                PositionEncoding::Uint8 => {
-                    point.position.x =
-                        fixpoint_decode(self.xyz_reader.read_u8().unwrap(), min.x, edge_length);
-                    point.position.y =
-                        fixpoint_decode(self.xyz_reader.read_u8().unwrap(), min.y, edge_length);
-                    point.position.z =
-                        fixpoint_decode(self.xyz_reader.read_u8().unwrap(), min.z, edge_length);
-                }
-                PositionEncoding::Uint16 => {
-                    point.position.x = fixpoint_decode(
-                        self.xyz_reader.read_u16::<LittleEndian>().unwrap(),
-                        min.x,
-                        edge_length,
-                    );
-                    point.position.y = fixpoint_decode(
-                        self.xyz_reader.read_u16::<LittleEndian>().unwrap(),
-                        min.y,
-                        edge_length,
-                    );
-                    point.position.z = fixpoint_decode(
-                        self.xyz_reader.read_u16::<LittleEndian>().unwrap(),
-                        min.z,
-                        edge_length,
-                    );
-                }
-            }
-
-            point.r = self.rgb_reader.read_u8().unwrap();
-            point.g = self.rgb_reader.read_u8().unwrap();
-            point.b = self.rgb_reader.read_u8().unwrap();
-            f(&point);
+    fn for_each<F: FnMut(&Point)>(self, mut f: F) -> Result<()> {
+        for point in self {
+            f(&point?);
         }
+        Ok(())
     }
 }
 
@@ -429,8 +483,8 @@ fn decode(value: f32, min: f32, edge_length: f32) -> f32 {
 
 #[derive(Debug)]
 pub struct NodeWriter {
-    xyz_writer: BufWriter<File>,
-    rgb_writer: BufWriter<File>,
+    xyz_writer: ChecksumWriter<BufWriter<File>>,
+    rgb_writer: ChecksumWriter<BufWriter<File>>,
     bounding_cube: Cube,
     position_encoding: PositionEncoding,
     stem: PathBuf,
@@ -458,6 +512,8 @@ impl Drop for NodeWriter {
                 ),
                 position_encoding: Some(self.position_encoding.to_proto() as i32),
                 num_points: Some(self.num_written),
+                position_checksum: Some(self.xyz_writer.checksum()),
+                color_checksum: Some(self.rgb_writer.checksum()),
             };
             let mut buf = Vec::new();
             proto.encode(&mut buf).unwrap();
@@ -477,8 +533,12 @@ impl NodeWriter {
 
         let stem = node.id.get_stem(output_directory);
         NodeWriter {
-            xyz_writer: BufWriter::new(File::create(&stem.with_extension(POSITION_EXT)).unwrap()),
-            rgb_writer: BufWriter::new(File::create(&stem.with_extension(COLOR_EXT)).unwrap()),
+            xyz_writer: ChecksumWriter::new(
+                BufWriter::new(File::create(&stem.with_extension(POSITION_EXT)).unwrap()),
+            ),
+            rgb_writer: ChecksumWriter::new(
+                BufWriter::new(File::create(&stem.with_extension(COLOR_EXT)).unwrap()),
+            ),
             stem: stem,
             position_encoding: PositionEncoding::new(&node.bounding_cube, resolution),
             bounding_cube: node.bounding_cube.clone(),
@@ -548,7 +608,11 @@ impl NodeWriter {
 
 #[cfg(test)]
 mod tests {
-    use super::{ChildIndex, NodeId};
+    use super::{COLOR_EXT, ChildIndex, NodeId, NodeIterator};
+    use InternalIterator;
+    use math::Vector3f;
+    use octree::testing;
+    use std::fs::OpenOptions;
 
     #[test]
     fn test_parent_node_name() {
@@ -570,4 +634,49 @@ mod tests {
         );
         assert_eq!(None, NodeId::from_str("r").child_index());
     }
+
+    fn write_three_points(name: &str) -> ::std::path::PathBuf {
+        let points = (0..3)
+            .map(|i| testing::red_point(Vector3f::new(0.1 * i as f32, 0.5, 0.5)))
+            .collect();
+        testing::write_test_octree(name, &[("r", points)])
+    }
+
+    #[test]
+    fn test_node_points() {
+        let directory = write_three_points("node_test_node_points");
+        let points = NodeIterator::from_disk(&directory, &NodeId::from_str("r"))
+            .unwrap()
+            .into_iter();
+        assert_eq!((0, Some(3)), points.size_hint());
+        let xs: Vec<f32> = points.map(|p| p.unwrap().position.x).collect();
+        assert_eq!(3, xs.len());
+        assert!((xs[2] - 0.2).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_node_points_stop_at_error() {
+        let directory = write_three_points("node_test_node_points_stop_at_error");
+        // Leaves only the color of the first point.
+        OpenOptions::new()
+            .write(true)
+            .open(directory.join("r").with_extension(COLOR_EXT))
+            .unwrap()
+            .set_len(3)
+            .unwrap();
+        let id = NodeId::from_str("r");
+        let mut points = NodeIterator::from_disk(&directory, &id)
+            .unwrap()
+            .into_iter();
+        assert!(points.next().unwrap().is_ok());
+        assert!(points.next().unwrap().is_err());
+        assert!(points.next().is_none());
+
+        let mut num_points = 0;
+        let result = NodeIterator::from_disk(&directory, &id)
+            .unwrap()
+            .for_each(|_| num_points += 1);
+        assert!(result.is_err());
+        assert_eq!(1, num_points);
+    }
 }
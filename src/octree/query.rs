@@ -0,0 +1,270 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {InternalIterator, Point};
+use errors::*;
+use math::{Cube, Cuboid, CuboidLike, InnerSpace, Vector3f};
+use octree::{ChildIndex, Node, NodeId, NodeIterator, Octree};
+use std::f32;
+
+/// Points found by a query. If more points matched than requested, only the first ones are
+/// returned and 'is_truncated' is set.
+#[derive(Debug)]
+pub struct QueryResult {
+    pub points: Vec<Point>,
+    pub is_truncated: bool,
+}
+
+/// Returns the distance along the ray from 'origin' in 'direction' at which it enters the box
+/// between 'min' and 'max', 0 if 'origin' is inside, or None if the ray misses the box.
+fn ray_entry(
+    origin: &Vector3f,
+    direction: &Vector3f,
+    min: &Vector3f,
+    max: &Vector3f,
+) -> Option<f32> {
+    let mut entry = 0f32;
+    let mut exit = f32::INFINITY;
+    for axis in 0..3 {
+        if direction[axis] == 0. {
+            if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                return None;
+            }
+            continue;
+        }
+        let t0 = (min[axis] - origin[axis]) / direction[axis];
+        let t1 = (max[axis] - origin[axis]) / direction[axis];
+        entry = entry.max(t0.min(t1));
+        exit = exit.min(t0.max(t1));
+        if entry > exit {
+            return None;
+        }
+    }
+    Some(entry)
+}
+
+impl Octree {
+    /// Returns the nodes of the octree for which 'is_relevant' is true for their bounding cube.
+    /// Children of nodes that are not relevant are skipped.
+    fn nodes_where<F>(&self, is_relevant: F) -> Vec<Node>
+        where F: Fn(&Cube) -> bool
+    {
+        let mut open = vec![Node::root_with_bounding_cube(self.bounding_cube.clone())];
+        let mut nodes = Vec::new();
+        while let Some(node) = open.pop() {
+            if !self.nodes.contains_key(&node.id) || !is_relevant(&node.bounding_cube) {
+                continue;
+            }
+            for child_index in 0..8 {
+                open.push(node.get_child(ChildIndex::from_u8(child_index)));
+            }
+            nodes.push(node);
+        }
+        nodes
+    }
+
+    /// Returns the ids of all nodes whose bounding cube intersects 'region'.
+    pub fn nodes_intersecting<C: CuboidLike>(&self, region: &C) -> Vec<NodeId> {
+        self.nodes_where(|cube| region.intersects(cube))
+            .into_iter()
+            .map(|node| node.id)
+            .collect()
+    }
+
+    fn points_where<F>(&self, node_ids: &[NodeId], max_points: usize, f: F) -> Result<QueryResult>
+        where F: Fn(&Point) -> bool
+    {
+        let mut result = QueryResult {
+            points: Vec::new(),
+            is_truncated: false,
+        };
+        for id in node_ids {
+            NodeIterator::from_disk(&self.directory, id)?
+                .for_each(
+                    |p| {
+                        if !f(p) {
+                            return;
+                        }
+                        if result.points.len() < max_points {
+                            result.points.push(p.clone());
+                        } else {
+                            result.is_truncated = true;
+                        }
+                    },
+                )?;
+            if result.is_truncated {
+                break;
+            }
+        }
+        Ok(result)
+    }
+
+    /// Returns up to 'max_points' points inside 'bounding_box'.
+    pub fn points_in_box(&self, bounding_box: &Cuboid, max_points: usize) -> Result<QueryResult> {
+        let node_ids = self.nodes_intersecting(bounding_box);
+        self.points_where(
+            &node_ids,
+            max_points,
+            |p| bounding_box.contains(&p.position),
+        )
+    }
+
+    /// Returns up to 'max_points' points that are at most 'radius' away from 'center'.
+    pub fn points_in_sphere(
+        &self,
+        center: &Vector3f,
+        radius: f32,
+        max_points: usize,
+    ) -> Result<QueryResult> {
+        let extent = Vector3f::new(radius, radius, radius);
+        let node_ids = self.nodes_intersecting(
+            &Cuboid::from_min_max(*center - extent, *center + extent),
+        );
+        self.points_where(
+            &node_ids,
+            max_points,
+            |p| (p.position - *center).magnitude2() <= radius * radius,
+        )
+    }
+
+    /// Returns the point closest to 'origin' of all points that are at most 'radius' away from
+    /// the ray starting at 'origin' in 'direction', e.g. to pick the point under the mouse.
+    pub fn pick(
+        &self,
+        origin: &Vector3f,
+        direction: &Vector3f,
+        radius: f32,
+    ) -> Result<Option<Point>> {
+        if direction.magnitude2() == 0. {
+            return Err(ErrorKind::InvalidInput("The ray has no direction.".to_string()).into());
+        }
+        let direction = direction.normalize();
+        let extent = Vector3f::new(radius, radius, radius);
+        // Points can be up to 'radius' outside of the node that contains them.
+        let entry = |cube: &Cube| {
+            ray_entry(
+                origin,
+                &direction,
+                &(cube.min() - extent),
+                &(cube.max() + extent),
+            )
+        };
+        let mut nodes: Vec<(f32, NodeId)> = self.nodes_where(|cube| entry(cube).is_some())
+            .into_iter()
+            .map(|node| (entry(&node.bounding_cube).unwrap(), node.id))
+            .collect();
+        nodes.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let mut closest: Option<(f32, Point)> = None;
+        for &(node_entry, ref id) in &nodes {
+            // Nodes are sorted by where the ray enters them, so none of the remaining nodes can
+            // contain a closer point.
+            if closest.as_ref().map_or(false, |&(distance, _)| distance < node_entry) {
+                break;
+            }
+            NodeIterator::from_disk(&self.directory, id)?
+                .for_each(
+                    |p| {
+                        let v = p.position - *origin;
+                        let distance = v.dot(direction);
+                        if distance < 0. || v.magnitude2() - distance * distance > radius * radius {
+                            return;
+                        }
+                        let is_closer = closest
+                            .as_ref()
+                            .map_or(true, |&(closest_distance, _)| distance < closest_distance);
+                        if is_closer {
+                            closest = Some((distance, p.clone()));
+                        }
+                    },
+                )?;
+        }
+        Ok(closest.map(|(_, p)| p))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use octree::testing;
+
+    // Writes an octree with two points in the root and two in its first child and returns it
+    // with the center of the child.
+    fn write_test_octree(name: &str) -> (Octree, Vector3f) {
+        let offset = Vector3f::new(0.1, 0.1, 0.1);
+        let points = |id| {
+            let center = testing::test_node(id).bounding_cube.center();
+            vec![testing::red_point(center), testing::red_point(center + offset)]
+        };
+        let directory =
+            testing::write_test_octree(name, &[("r", points("r")), ("r0", points("r0"))]);
+        (Octree::new(&directory).unwrap(), testing::test_node("r0").bounding_cube.center())
+    }
+
+    fn around(center: &Vector3f, extent: f32) -> Cuboid {
+        let extent = Vector3f::new(extent, extent, extent);
+        Cuboid::from_min_max(*center - extent, *center + extent)
+    }
+
+    #[test]
+    fn test_points_in_box() {
+        let (octree, child_center) = write_test_octree("query_test_points_in_box");
+        let result = octree
+            .points_in_box(&around(&child_center, 0.01), 10)
+            .unwrap();
+        assert_eq!(1, result.points.len());
+        assert!((result.points[0].position - child_center).magnitude() < 0.01);
+        assert!(!result.is_truncated);
+
+        let everything = around(&Vector3f::new(0.5, 0.5, 0.5), 0.5);
+        assert_eq!(4, octree.points_in_box(&everything, 10).unwrap().points.len());
+        let result = octree.points_in_box(&everything, 3).unwrap();
+        assert_eq!(3, result.points.len());
+        assert!(result.is_truncated);
+    }
+
+    #[test]
+    fn test_points_in_sphere() {
+        let (octree, child_center) = write_test_octree("query_test_points_in_sphere");
+        let result = octree
+            .points_in_sphere(&child_center, 0.01, 10)
+            .unwrap();
+        assert_eq!(1, result.points.len());
+        // The other point of the child is 0.17 away.
+        assert_eq!(2, octree.points_in_sphere(&child_center, 0.2, 10).unwrap().points.len());
+    }
+
+    #[test]
+    fn test_pick() {
+        let (octree, child_center) = write_test_octree("query_test_pick");
+        // Along the diagonal through the child center, which passes all points.
+        let direction = Vector3f::new(1., 1., 1.);
+        let origin = child_center - direction;
+        let picked = octree
+            .pick(&origin, &direction, 0.01)
+            .unwrap()
+            .unwrap();
+        assert!((picked.position - child_center).magnitude() < 0.01);
+
+        let picked = octree
+            .pick(&(child_center + direction * 0.05), &direction, 0.01)
+            .unwrap()
+            .unwrap();
+        assert!((picked.position - Vector3f::new(0.35, 0.35, 0.35)).magnitude() < 0.01);
+
+        assert!(octree.pick(&origin, &-direction, 0.01).unwrap().is_none());
+        assert!(octree.pick(&origin, &Vector3f::new(1., 0., 0.), 0.01).unwrap().is_none());
+        assert!(octree.pick(&origin, &Vector3f::new(0., 0., 0.), 0.01).is_err());
+    }
+}
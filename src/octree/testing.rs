@@ -0,0 +1,87 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Writes small octrees for the tests of this crate and of the crates using it.
+
+use Point;
+use math::{Cube, Vector3f};
+use octree::{CURRENT_VERSION, ChildIndex, Node, NodeWriter};
+use prost::Message;
+use proto;
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+pub const TEST_RESOLUTION: f64 = 0.001;
+
+/// Returns the node 'id', e.g. "r03", of the octree spanning the unit cube.
+pub fn test_node(id: &str) -> Node {
+    let mut node = Node::root_with_bounding_cube(Cube::new(Vector3f::new(0., 0., 0.), 1.));
+    for digit in id.chars().skip(1) {
+        let child_index = digit.to_digit(8).expect("Invalid node id.");
+        node = node.get_child(ChildIndex::from_u8(child_index as u8));
+    }
+    node
+}
+
+/// Returns a red point at 'position'.
+pub fn red_point(position: Vector3f) -> Point {
+    Point {
+        position: position,
+        r: 255,
+        g: 0,
+        b: 0,
+    }
+}
+
+/// Writes an octree spanning the unit cube into the temporary directory 'name', replacing what
+/// was there before. 'nodes' are the ids of the nodes with their points.
+pub fn write_test_octree(name: &str, nodes: &[(&str, Vec<Point>)]) -> PathBuf {
+    let directory = env::temp_dir().join(name);
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+
+    let meta = proto::Meta {
+        bounding_cube: Some(
+            proto::BoundingCube {
+                min: Some(
+                    proto::Vector3f {
+                        x: Some(0.),
+                        y: Some(0.),
+                        z: Some(0.),
+                    }
+                ),
+                edge_length: Some(1.),
+            }
+        ),
+        resolution: Some(TEST_RESOLUTION),
+        version: Some(CURRENT_VERSION),
+        build_id: Some(1),
+    };
+    let mut encoded = Vec::new();
+    meta.encode(&mut encoded).unwrap();
+    File::create(&directory.join("meta.pb"))
+        .unwrap()
+        .write_all(&encoded)
+        .unwrap();
+
+    for &(id, ref points) in nodes {
+        let mut writer = NodeWriter::new(&directory, &test_node(id), TEST_RESOLUTION);
+        for p in points {
+            writer.write(p);
+        }
+    }
+    directory
+}
@@ -0,0 +1,254 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use Point;
+use math::{Cuboid, CuboidLike, InnerSpace, Vector3f};
+use std::collections::HashMap;
+
+/// Statistical outlier removal: A point is an outlier if the mean distance to its 'k' nearest
+/// neighbours is more than 'std_ratio' standard deviations above the mean of this value over all
+/// points considered together. Points that do not have 'k' neighbours are kept, since there is
+/// not enough data to judge them.
+#[derive(Debug,Clone)]
+pub struct OutlierRemoval {
+    pub k: usize,
+    pub std_ratio: f32,
+}
+
+type Cell = (i64, i64, i64);
+
+/// A uniform grid over points to speed up nearest neighbour queries.
+struct Grid<'a> {
+    points: Vec<&'a Vector3f>,
+    cells: HashMap<Cell, Vec<usize>>,
+    min: Vector3f,
+    cell_size: f32,
+    // The largest extent of the grid in cells, to know when to stop searching.
+    max_extent: i64,
+}
+
+impl<'a> Grid<'a> {
+    fn new(points: Vec<&'a Vector3f>, points_per_cell: usize) -> Self {
+        let mut bounding_box = Cuboid::new();
+        for p in &points {
+            bounding_box.update(p);
+        }
+        let size = bounding_box.size();
+        let max_size = size.x.max(size.y).max(size.z).max(1e-6);
+        let num_cells_per_side = ((points.len() / points_per_cell.max(1)) as f32)
+            .cbrt()
+            .max(1.);
+        let cell_size = max_size / num_cells_per_side;
+
+        let mut grid = Grid {
+            points: Vec::new(),
+            cells: HashMap::new(),
+            min: bounding_box.min(),
+            cell_size: cell_size,
+            max_extent: num_cells_per_side.ceil() as i64 + 1,
+        };
+        for (index, p) in points.iter().enumerate() {
+            let cell = grid.cell(p);
+            grid.cells.entry(cell).or_insert_with(Vec::new).push(index);
+        }
+        grid.points = points;
+        grid
+    }
+
+    fn cell(&self, p: &Vector3f) -> Cell {
+        (
+            ((p.x - self.min.x) / self.cell_size).floor() as i64,
+            ((p.y - self.min.y) / self.cell_size).floor() as i64,
+            ((p.z - self.min.z) / self.cell_size).floor() as i64,
+        )
+    }
+
+    /// Returns the mean distance of 'query' to its 'k' nearest neighbours in the grid, ignoring
+    /// the point with index 'skip'. Returns None if there are not enough points.
+    fn mean_neighbour_distance(&self, query: &Vector3f, skip: usize, k: usize) -> Option<f32> {
+        // Squared distances of the closest points found so far, sorted ascending.
+        let mut nearest: Vec<f32> = Vec::with_capacity(k + 1);
+        let center = self.cell(query);
+        let mut ring = 0i64;
+        loop {
+            self.visit_ring(
+                &center,
+                ring,
+                &mut |index| {
+                    if index == skip {
+                        return;
+                    }
+                    let distance2 = (*self.points[index] - *query).magnitude2();
+                    if nearest.len() == k && distance2 >= nearest[k - 1] {
+                        return;
+                    }
+                    // Text inputs reject non-finite positions, but other inputs can contain
+                    // NaN, which has no place in the order.
+                    if distance2.is_nan() {
+                        return;
+                    }
+                    let position = match nearest
+                              .binary_search_by(|d| d.partial_cmp(&distance2).unwrap()) {
+                        Ok(position) | Err(position) => position,
+                    };
+                    nearest.insert(position, distance2);
+                    nearest.truncate(k);
+                },
+            );
+
+            // All points in rings further out are at least 'ring * cell_size' away.
+            let min_distance_outside = ring as f32 * self.cell_size;
+            if nearest.len() == k &&
+               nearest[k - 1] <= min_distance_outside * min_distance_outside {
+                break;
+            }
+            if ring > self.max_extent {
+                break;
+            }
+            ring += 1;
+        }
+
+        if nearest.len() < k {
+            return None;
+        }
+        Some(nearest.iter().map(|d2| d2.sqrt()).sum::<f32>() / k as f32)
+    }
+
+    /// Calls 'f' with the indices of all points in cells that are exactly 'ring' cells away from
+    /// 'center' in the maximum norm.
+    fn visit_ring<F: FnMut(usize)>(&self, center: &Cell, ring: i64, f: &mut F) {
+        for dx in -ring..ring + 1 {
+            for dy in -ring..ring + 1 {
+                for dz in -ring..ring + 1 {
+                    if dx.abs() != ring && dy.abs() != ring && dz.abs() != ring {
+                        continue;
+                    }
+                    let cell = (center.0 + dx, center.1 + dy, center.2 + dz);
+                    if let Some(indices) = self.cells.get(&cell) {
+                        for index in indices {
+                            f(*index);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl OutlierRemoval {
+    /// Returns the indices of the outliers in 'points'. 'halo' are additional points, e.g. from
+    /// neighbouring nodes, that are used as neighbours but are not classified themselves.
+    pub fn find_outliers(&self, points: &[Point], halo: &[Point]) -> Vec<usize> {
+        assert!(self.k > 0);
+        if points.is_empty() {
+            return Vec::new();
+        }
+        let grid = Grid::new(
+            points
+                .iter()
+                .chain(halo.iter())
+                .map(|p| &p.position)
+                .collect(),
+            self.k,
+        );
+        let mean_distances: Vec<Option<f32>> = points
+            .iter()
+            .enumerate()
+            .map(|(index, p)| grid.mean_neighbour_distance(&p.position, index, self.k))
+            .collect();
+
+        let known: Vec<f32> = mean_distances.iter().filter_map(|d| *d).collect();
+        if known.is_empty() {
+            // Too few points to say anything.
+            return Vec::new();
+        }
+        let mean = known.iter().sum::<f32>() / known.len() as f32;
+        let variance = known
+            .iter()
+            .map(|d| (d - mean) * (d - mean))
+            .sum::<f32>() / known.len() as f32;
+        let threshold = mean + self.std_ratio * variance.sqrt();
+
+        mean_distances
+            .iter()
+            .enumerate()
+            .filter(|&(_, d)| d.map_or(false, |d| d > threshold))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(x: f32, y: f32, z: f32) -> Point {
+        Point {
+            position: Vector3f::new(x, y, z),
+            r: 0,
+            g: 0,
+            b: 0,
+        }
+    }
+
+    #[test]
+    fn test_isolated_point_is_outlier() {
+        let mut points = Vec::new();
+        for x in 0..20 {
+            for y in 0..20 {
+                points.push(point(x as f32 * 0.1, y as f32 * 0.1, 0.));
+            }
+        }
+        points.push(point(1., 1., 5.));
+        let removal = OutlierRemoval {
+            k: 8,
+            std_ratio: 2.,
+        };
+        assert_eq!(vec![400], removal.find_outliers(&points, &[]));
+    }
+
+    #[test]
+    fn test_halo_provides_neighbours() {
+        let mut points: Vec<_> = (0..10).map(|i| point(i as f32 * 0.1, 0., 0.)).collect();
+        points.push(point(2., 0., 0.));
+        let halo: Vec<_> = (0..10).map(|i| point(2.1 + i as f32 * 0.1, 0., 0.)).collect();
+        let removal = OutlierRemoval {
+            k: 4,
+            std_ratio: 2.,
+        };
+        assert!(removal.find_outliers(&points, &halo).is_empty());
+        assert_eq!(vec![10], removal.find_outliers(&points, &[]));
+    }
+
+    #[test]
+    fn test_sparse_points_are_kept() {
+        let points = vec![point(0., 0., 0.), point(5., 0., 0.), point(5., 5., 0.)];
+        let removal = OutlierRemoval {
+            k: 4,
+            std_ratio: 1.,
+        };
+        assert!(removal.find_outliers(&points, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_nan_position_does_not_panic() {
+        let mut points: Vec<_> = (0..10).map(|i| point(i as f32 * 0.1, 0., 0.)).collect();
+        points.push(point(::std::f32::NAN, 0., 0.));
+        let removal = OutlierRemoval {
+            k: 4,
+            std_ratio: 2.,
+        };
+        assert!(!removal.find_outliers(&points, &[]).contains(&10));
+    }
+}
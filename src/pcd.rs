@@ -0,0 +1,724 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reading and writing of PCD files as used by the Point Cloud Library.
+
+use {InternalIterator, Point};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use compression;
+use errors::*;
+use math::Vector3f;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum DataFormat {
+    Ascii,
+    Binary,
+    /// LZF compressed and stored field by field. Only supported for reading.
+    BinaryCompressed,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum FieldType {
+    Int,
+    Uint,
+    Float,
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    size: usize,
+    field_type: FieldType,
+    count: usize,
+}
+
+impl Field {
+    /// Reads the first element of this field as a number.
+    fn read_number(&self, buf: &[u8]) -> f64 {
+        match (self.field_type, self.size) {
+            (FieldType::Float, 4) => LittleEndian::read_f32(buf) as f64,
+            (FieldType::Float, 8) => LittleEndian::read_f64(buf),
+            (FieldType::Uint, 1) => buf[0] as f64,
+            (FieldType::Uint, 2) => LittleEndian::read_u16(buf) as f64,
+            (FieldType::Uint, 4) => LittleEndian::read_u32(buf) as f64,
+            (FieldType::Uint, 8) => LittleEndian::read_u64(buf) as f64,
+            (FieldType::Int, 1) => buf[0] as i8 as f64,
+            (FieldType::Int, 2) => LittleEndian::read_i16(buf) as f64,
+            (FieldType::Int, 4) => LittleEndian::read_i32(buf) as f64,
+            (FieldType::Int, 8) => LittleEndian::read_i64(buf) as f64,
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Header {
+    fields: Vec<Field>,
+    num_points: usize,
+    data_format: DataFormat,
+}
+
+/// Returns the bits of 'value', i.e. the packed color of an 'rgb' field.
+fn float_bits(value: f32) -> u32 {
+    let mut buf = [0u8; 4];
+    LittleEndian::write_f32(&mut buf, value);
+    LittleEndian::read_u32(&buf)
+}
+
+fn float_from_bits(bits: u32) -> f32 {
+    let mut buf = [0u8; 4];
+    LittleEndian::write_u32(&mut buf, bits);
+    LittleEndian::read_f32(&buf)
+}
+
+fn parse_header<R: BufRead>(reader: &mut R, filename: &Path) -> Result<Header> {
+    let invalid = |msg: String| -> Error {
+        ErrorKind::InvalidInput(format!("{}: {}", filename.display(), msg)).into()
+    };
+
+    let mut names = Vec::new();
+    let mut sizes = Vec::new();
+    let mut types: Vec<FieldType> = Vec::new();
+    let mut counts = Vec::new();
+    let mut num_points = None;
+    let mut line = String::new();
+    let mut data_format = None;
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid("Unexpected end of file in header.".to_string()));
+        }
+        let entries: Vec<&str> = line.split_whitespace().collect();
+        if entries.is_empty() || entries[0].starts_with('#') {
+            continue;
+        }
+        let values = &entries[1..];
+        let parse_usizes = |values: &[&str]| -> Result<Vec<usize>> {
+            values
+                .iter()
+                .map(|v| v.parse::<usize>().map_err(|_| invalid(format!("Invalid line: {}", line))))
+                .collect()
+        };
+        match entries[0] {
+            "FIELDS" => names = values.iter().map(|v| v.to_string()).collect(),
+            "SIZE" => sizes = parse_usizes(values)?,
+            "COUNT" => counts = parse_usizes(values)?,
+            "TYPE" => {
+                types = values
+                    .iter()
+                    .map(
+                        |v| match *v {
+                            "I" => Ok(FieldType::Int),
+                            "U" => Ok(FieldType::Uint),
+                            "F" => Ok(FieldType::Float),
+                            _ => Err(invalid(format!("Invalid type: {}", v))),
+                        },
+                    )
+                    .collect::<Result<_>>()?
+            }
+            "POINTS" if values.len() == 1 => num_points = Some(parse_usizes(values)?[0]),
+            "DATA" if values.len() == 1 => {
+                data_format = Some(
+                    match values[0] {
+                        "ascii" => DataFormat::Ascii,
+                        "binary" => DataFormat::Binary,
+                        "binary_compressed" => DataFormat::BinaryCompressed,
+                        other => {
+                            return Err(invalid(format!("Unsupported data format: {}", other)))
+                        }
+                    }
+                );
+                // The data follows right after this line.
+                break;
+            }
+            "VERSION" | "WIDTH" | "HEIGHT" | "VIEWPOINT" => (),
+            _ => return Err(invalid(format!("Invalid line: {}", line))),
+        }
+    }
+
+    // COUNT is optional and defaults to 1.
+    if counts.is_empty() {
+        counts = vec![1; names.len()];
+    }
+    if sizes.len() != names.len() || types.len() != names.len() || counts.len() != names.len() {
+        return Err(invalid("FIELDS, SIZE, TYPE and COUNT do not match.".to_string()));
+    }
+    let num_points = match num_points {
+        Some(num_points) => num_points,
+        None => return Err(invalid("Header has no POINTS.".to_string())),
+    };
+
+    let mut fields = Vec::new();
+    for (((name, size), field_type), count) in
+        names
+            .into_iter()
+            .zip(sizes.into_iter())
+            .zip(types.into_iter())
+            .zip(counts.into_iter()) {
+        let valid = match field_type {
+            FieldType::Float => size == 4 || size == 8,
+            FieldType::Int | FieldType::Uint => size == 1 || size == 2 || size == 4 || size == 8,
+        };
+        if !valid || count == 0 {
+            return Err(invalid(format!("Unsupported field '{}'.", name)));
+        }
+        fields.push(
+            Field {
+                name: name,
+                size: size,
+                field_type: field_type,
+                count: count,
+            }
+        );
+    }
+    Ok(
+        Header {
+            fields: fields,
+            num_points: num_points,
+            data_format: data_format.unwrap(),
+        }
+    )
+}
+
+/// Decompresses LZF compressed 'input', which must decompress to exactly 'output_len' bytes.
+fn lzf_decompress(input: &[u8], output_len: usize) -> Option<Vec<u8>> {
+    let mut output = Vec::with_capacity(output_len);
+    let mut i = 0;
+    while i < input.len() {
+        let control = input[i] as usize;
+        i += 1;
+        if control < 32 {
+            // A run of literal bytes.
+            let len = control + 1;
+            if i + len > input.len() {
+                return None;
+            }
+            output.extend_from_slice(&input[i..i + len]);
+            i += len;
+        } else {
+            // A back reference into the output.
+            let mut len = control >> 5;
+            if len == 7 {
+                if i >= input.len() {
+                    return None;
+                }
+                len += input[i] as usize;
+                i += 1;
+            }
+            len += 2;
+            if i >= input.len() {
+                return None;
+            }
+            let distance = ((control & 0x1f) << 8) + input[i] as usize + 1;
+            i += 1;
+            if distance > output.len() {
+                return None;
+            }
+            let start = output.len() - distance;
+            // The reference can overlap the bytes we are writing, so we copy byte by byte.
+            for j in 0..len {
+                let byte = output[start + j];
+                output.push(byte);
+            }
+        }
+    }
+    if output.len() != output_len {
+        return None;
+    }
+    Some(output)
+}
+
+/// Where the values of a point are found in a record.
+#[derive(Debug)]
+struct Layout {
+    // Index of the fields for x, y, z and the packed color.
+    position: [usize; 3],
+    color: Option<usize>,
+    // Byte offset of each field in a binary record.
+    offsets: Vec<usize>,
+    // Index of the first value of each field in an ASCII line.
+    columns: Vec<usize>,
+    record_size: usize,
+}
+
+impl Layout {
+    fn new(header: &Header, filename: &Path) -> Result<Self> {
+        let index = |name: &str| header.fields.iter().position(|f| f.name == name);
+        let position = match (index("x"), index("y"), index("z")) {
+            (Some(x), Some(y), Some(z)) => [x, y, z],
+            _ => {
+                return Err(
+                    ErrorKind::InvalidInput(
+                        format!(
+                            "{}: PCD must contain fields 'x', 'y' and 'z'.",
+                            filename.display()
+                        ),
+                    )
+                            .into()
+                )
+            }
+        };
+        let color = index("rgb").or(index("rgba"));
+        if let Some(color) = color {
+            if header.fields[color].size != 4 {
+                return Err(
+                    ErrorKind::InvalidInput(
+                        format!("{}: Color must be packed into 4 bytes.", filename.display()),
+                    )
+                            .into()
+                );
+            }
+        }
+
+        let mut offsets = Vec::new();
+        let mut columns = Vec::new();
+        let mut record_size = 0;
+        let mut num_columns = 0;
+        for field in &header.fields {
+            offsets.push(record_size);
+            columns.push(num_columns);
+            record_size += field.size * field.count;
+            num_columns += field.count;
+        }
+        Ok(
+            Layout {
+                position: position,
+                color: color,
+                offsets: offsets,
+                columns: columns,
+                record_size: record_size,
+            }
+        )
+    }
+}
+
+/// Returns None for points without a valid position, which PCL uses in organized clouds.
+fn make_point(x: f64, y: f64, z: f64, packed_color: Option<u32>) -> Option<Point> {
+    if !x.is_finite() || !y.is_finite() || !z.is_finite() {
+        return None;
+    }
+    let (r, g, b) = match packed_color {
+        Some(c) => ((c >> 16) as u8, (c >> 8) as u8, c as u8),
+        None => (255, 255, 255),
+    };
+    Some(
+        Point {
+            position: Vector3f::new(x as f32, y as f32, z as f32),
+            r: r,
+            g: g,
+            b: b,
+        }
+    )
+}
+
+/// Reads the points of a PCD file. The color is taken from a packed 'rgb' or 'rgba' field.
+pub struct PcdIterator {
+    reader: BufReader<Box<Read + Send>>,
+    filename: PathBuf,
+    header: Header,
+    layout: Layout,
+}
+
+impl PcdIterator {
+    /// Opens a PCD file, which can be compressed with gzip or zstd as indicated by a '.gz' or
+    /// '.zst' extension.
+    pub fn new<P: AsRef<Path>>(filename: P) -> Result<Self> {
+        let reader = compression::open(filename.as_ref())?;
+        Self::from_reader(reader, filename)
+    }
+
+    /// Reads a PCD file from 'reader'. 'name' is only used in error messages.
+    pub fn from_reader<P: AsRef<Path>>(reader: Box<Read + Send>, name: P) -> Result<Self> {
+        let mut reader = BufReader::new(reader);
+        let header = parse_header(&mut reader, name.as_ref())?;
+        let layout = Layout::new(&header, name.as_ref())?;
+        Ok(
+            PcdIterator {
+                reader: reader,
+                filename: name.as_ref().to_path_buf(),
+                header: header,
+                layout: layout,
+            }
+        )
+    }
+
+    fn point_from_record(&self, record: &[u8]) -> Option<Point> {
+        let number = |i: usize| {
+            self.header.fields[i].read_number(&record[self.layout.offsets[i]..])
+        };
+        let color = self.layout
+            .color
+            .map(|c| LittleEndian::read_u32(&record[self.layout.offsets[c]..]));
+        let p = &self.layout.position;
+        make_point(number(p[0]), number(p[1]), number(p[2]), color)
+    }
+
+    fn point_from_line(&self, line: &str, line_number: usize) -> Result<Option<Point>> {
+        let values: Vec<&str> = line.split_whitespace().collect();
+        let invalid_value = |i: usize| -> Error {
+            ErrorKind::InvalidInput(
+                format!(
+                    "{}:{}: Missing or invalid value for '{}'.",
+                    self.filename.display(),
+                    line_number,
+                    self.header.fields[i].name
+                ),
+            )
+                    .into()
+        };
+        // PCL writes invalid points as 'nan', which parses fine.
+        let number = |i: usize| -> Result<f64> {
+            values
+                .get(self.layout.columns[i])
+                .and_then(|v| v.parse::<f64>().ok())
+                .ok_or_else(|| invalid_value(i))
+        };
+        let color = match self.layout.color {
+            // A packed float color has to be parsed as f32 to keep its bits intact.
+            Some(c) if self.header.fields[c].field_type == FieldType::Float => {
+                let value = values
+                    .get(self.layout.columns[c])
+                    .and_then(|v| v.parse::<f32>().ok())
+                    .ok_or_else(|| invalid_value(c))?;
+                Some(float_bits(value))
+            }
+            Some(c) => Some(number(c)? as u32),
+            None => None,
+        };
+        let p = &self.layout.position;
+        Ok(make_point(number(p[0])?, number(p[1])?, number(p[2])?, color))
+    }
+
+    fn for_each_ascii<F: FnMut(&Point)>(mut self, mut f: F) -> Result<()> {
+        let mut line = String::new();
+        let mut num_points_read = 0;
+        let mut line_number = 0;
+        while num_points_read < self.header.num_points {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            line_number += 1;
+            if line.trim().is_empty() {
+                continue;
+            }
+            num_points_read += 1;
+            if let Some(point) = self.point_from_line(&line, line_number)? {
+                f(&point);
+            }
+        }
+        self.check_num_points_read(num_points_read)
+    }
+
+    fn for_each_binary<F: FnMut(&Point)>(mut self, mut f: F) -> Result<()> {
+        let mut record = vec![0u8; self.layout.record_size];
+        for num_points_read in 0..self.header.num_points {
+            if self.reader.read_exact(&mut record).is_err() {
+                return self.check_num_points_read(num_points_read);
+            }
+            if let Some(point) = self.point_from_record(&record) {
+                f(&point);
+            }
+        }
+        Ok(())
+    }
+
+    fn for_each_binary_compressed<F: FnMut(&Point)>(mut self, mut f: F) -> Result<()> {
+        let mut sizes = [0u8; 8];
+        self.reader.read_exact(&mut sizes)?;
+        let compressed_size = LittleEndian::read_u32(&sizes[0..4]) as usize;
+        let uncompressed_size = LittleEndian::read_u32(&sizes[4..8]) as usize;
+        let mut compressed = vec![0u8; compressed_size];
+        self.reader.read_exact(&mut compressed)?;
+        let num_points = self.header.num_points;
+        let data = lzf_decompress(&compressed, uncompressed_size)
+            .and_then(
+                |data| if data.len() == num_points * self.layout.record_size {
+                    Some(data)
+                } else {
+                    None
+                },
+            );
+        let data = match data {
+            Some(data) => data,
+            None => {
+                return Err(
+                    ErrorKind::InvalidInput(
+                        format!("{}: Invalid compressed data.", self.filename.display()),
+                    )
+                            .into()
+                )
+            }
+        };
+
+        // The data is stored field by field, we reassemble a record per point.
+        let mut record = vec![0u8; self.layout.record_size];
+        for i in 0..num_points {
+            for (field, offset) in self.header.fields.iter().zip(self.layout.offsets.iter()) {
+                let len = field.size * field.count;
+                let start = offset * num_points + i * len;
+                record[*offset..*offset + len].copy_from_slice(&data[start..start + len]);
+            }
+            if let Some(point) = self.point_from_record(&record) {
+                f(&point);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_num_points_read(&self, num_points_read: usize) -> Result<()> {
+        if num_points_read < self.header.num_points {
+            return Err(
+                ErrorKind::InvalidInput(
+                    format!(
+                        "{}: Unexpected end of file at point {} of {}.",
+                        self.filename.display(),
+                        num_points_read,
+                        self.header.num_points
+                    ),
+                )
+                        .into()
+            );
+        }
+        Ok(())
+    }
+}
+
+impl InternalIterator for PcdIterator {
+    fn size_hint(&self) -> Option<usize> {
+        // Points without a valid position are skipped, so we do not know the exact number.
+        None
+    }
+
+    fn for_each<F: FnMut(&Point)>(self, f: F) -> Result<()> {
+        match self.header.data_format {
+            DataFormat::Ascii => self.for_each_ascii(f),
+            DataFormat::Binary => self.for_each_binary(f),
+            DataFormat::BinaryCompressed => self.for_each_binary_compressed(f),
+        }
+    }
+}
+
+// The number of points is only known once all points are written, so we reserve space for it in
+// the header and fill it in at the end.
+const COUNT_PLACEHOLDER_LEN: usize = 20;
+
+/// Writes points into a PCD file with the fields 'x y z rgb'. The number of points in the header
+/// is filled in by 'finish' or when the writer is dropped.
+pub struct PcdWriter {
+    writer: Option<BufWriter<File>>,
+    data_format: DataFormat,
+    num_written: usize,
+    // Byte offsets of the numbers after WIDTH and POINTS in the header.
+    count_positions: Vec<u64>,
+}
+
+impl PcdWriter {
+    pub fn new<P: AsRef<Path>>(filename: P, data_format: DataFormat) -> Result<Self> {
+        if data_format == DataFormat::BinaryCompressed {
+            return Err(
+                ErrorKind::InvalidInput("Writing binary_compressed PCD is not supported.".into())
+                    .into()
+            );
+        }
+        let filename = filename.as_ref();
+        let mut writer = BufWriter::new(
+            File::create(filename)
+                .chain_err(|| format!("Could not create {}.", filename.display()))?,
+        );
+
+        let mut count_positions = Vec::new();
+        let mut header = String::new();
+        header.push_str("# .PCD v0.7 - Point Cloud Data file format\n");
+        header.push_str("VERSION 0.7\n");
+        header.push_str("FIELDS x y z rgb\n");
+        header.push_str("SIZE 4 4 4 4\n");
+        header.push_str("TYPE F F F F\n");
+        header.push_str("COUNT 1 1 1 1\n");
+        header.push_str("WIDTH ");
+        count_positions.push(header.len() as u64);
+        header.push_str(&format!("{:1$}\n", 0, COUNT_PLACEHOLDER_LEN));
+        header.push_str("HEIGHT 1\n");
+        header.push_str("VIEWPOINT 0 0 0 1 0 0 0\n");
+        header.push_str("POINTS ");
+        count_positions.push(header.len() as u64);
+        header.push_str(&format!("{:1$}\n", 0, COUNT_PLACEHOLDER_LEN));
+        header.push_str(
+            match data_format {
+                DataFormat::Ascii => "DATA ascii\n",
+                _ => "DATA binary\n",
+            },
+        );
+        writer.write_all(header.as_bytes())?;
+
+        Ok(
+            PcdWriter {
+                writer: Some(writer),
+                data_format: data_format,
+                num_written: 0,
+                count_positions: count_positions,
+            }
+        )
+    }
+
+    pub fn write(&mut self, p: &Point) -> Result<()> {
+        let writer = self.writer.as_mut().unwrap();
+        let color = ((p.r as u32) << 16) | ((p.g as u32) << 8) | p.b as u32;
+        match self.data_format {
+            DataFormat::Ascii => {
+                writeln!(
+                    writer,
+                    "{} {} {} {:e}",
+                    p.position.x,
+                    p.position.y,
+                    p.position.z,
+                    float_from_bits(color)
+                )?
+            }
+            _ => {
+                writer.write_f32::<LittleEndian>(p.position.x)?;
+                writer.write_f32::<LittleEndian>(p.position.y)?;
+                writer.write_f32::<LittleEndian>(p.position.z)?;
+                writer.write_u32::<LittleEndian>(color)?;
+            }
+        }
+        self.num_written += 1;
+        Ok(())
+    }
+
+    pub fn num_written(&self) -> usize {
+        self.num_written
+    }
+
+    /// Fills in the number of points and closes the file.
+    pub fn finish(mut self) -> Result<()> {
+        self.write_counts()
+    }
+
+    fn write_counts(&mut self) -> Result<()> {
+        let writer = match self.writer.take() {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        let mut file = writer
+            .into_inner()
+            .chain_err(|| "Could not write PCD file.")?;
+        let count = format!("{:<1$}", self.num_written, COUNT_PLACEHOLDER_LEN);
+        for position in &self.count_positions {
+            file.seek(SeekFrom::Start(*position))?;
+            file.write_all(count.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for PcdWriter {
+    fn drop(&mut self) {
+        // Errors can only be reported by calling 'finish'.
+        let _ = self.write_counts();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Cursor;
+
+    fn test_points() -> Vec<Point> {
+        (0..10)
+            .map(
+                |i| {
+                    Point {
+                        position: Vector3f::new(i as f32, 0.5 * i as f32, -1.),
+                        r: i as u8,
+                        g: 100,
+                        b: 255 - i as u8,
+                    }
+                }
+            )
+            .collect()
+    }
+
+    fn read(iterator: PcdIterator) -> Vec<Point> {
+        let mut points = Vec::new();
+        iterator.for_each(|p| points.push(p.clone())).unwrap();
+        points
+    }
+
+    fn assert_points_eq(expected: &[Point], actual: &[Point]) {
+        assert_eq!(expected.len(), actual.len());
+        for (a, b) in expected.iter().zip(actual.iter()) {
+            assert_eq!(a.position, b.position);
+            assert_eq!((a.r, a.g, a.b), (b.r, b.g, b.b));
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let cases = [
+            (DataFormat::Ascii, "pcd_test_ascii.pcd"),
+            (DataFormat::Binary, "pcd_test_binary.pcd"),
+        ];
+        for &(data_format, name) in &cases {
+            let path = env::temp_dir().join(name);
+            let points = test_points();
+            {
+                let mut writer = PcdWriter::new(&path, data_format).unwrap();
+                for p in &points {
+                    writer.write(p).unwrap();
+                }
+                writer.finish().unwrap();
+            }
+            assert_points_eq(&points, &read(PcdIterator::new(&path).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_ascii_with_nan_and_rgba() {
+        let data = "VERSION 0.7\nFIELDS x y z rgba\nSIZE 4 4 4 4\nTYPE F F F U\nCOUNT 1 1 1 1\n\
+                    WIDTH 2\nHEIGHT 1\nPOINTS 2\nDATA ascii\n1 2 3 4278255360\nnan nan nan 0\n";
+        let iterator = PcdIterator::from_reader(Box::new(Cursor::new(data)), "test").unwrap();
+        let points = read(iterator);
+        assert_eq!(1, points.len());
+        assert_eq!(Vector3f::new(1., 2., 3.), points[0].position);
+        assert_eq!((0, 255, 0), (points[0].r, points[0].g, points[0].b));
+    }
+
+    #[test]
+    fn test_binary_compressed() {
+        // Two points, stored as all x values followed by all y and z values. Only the first value
+        // is stored as literal bytes, the rest is a back reference to it.
+        let header = "FIELDS x y z\nSIZE 4 4 4\nTYPE F F F\nPOINTS 2\nDATA binary_compressed\n";
+        let mut data = header.as_bytes().to_vec();
+        let mut uncompressed = Vec::new();
+        for _ in 0..6 {
+            uncompressed.write_f32::<LittleEndian>(1.5).unwrap();
+        }
+        let compressed = [3, 0, 0, 0xc0, 0x3f, 7 << 5, 11, 3];
+        data.write_u32::<LittleEndian>(compressed.len() as u32).unwrap();
+        data.write_u32::<LittleEndian>(uncompressed.len() as u32).unwrap();
+        data.extend_from_slice(&compressed);
+        assert_eq!(Some(uncompressed), lzf_decompress(&compressed, 24));
+
+        let iterator = PcdIterator::from_reader(Box::new(Cursor::new(data)), "test").unwrap();
+        let points = read(iterator);
+        assert_eq!(2, points.len());
+        assert_eq!(Vector3f::new(1.5, 1.5, 1.5), points[1].position);
+        assert_eq!(255, points[1].r);
+    }
+}
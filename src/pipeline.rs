@@ -0,0 +1,289 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {InternalIterator, Point};
+use errors::*;
+use math::{Cuboid, CuboidLike, InnerSpace, Matrix4f, Vector3f};
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+/// A single processing step that is applied to every point flowing through a 'Pipeline'.
+pub trait Stage {
+    /// Modifies 'point' in place. Returns false if the point should be dropped.
+    fn process(&mut self, point: &mut Point) -> bool;
+
+    /// Returns true if this stage never drops points.
+    fn keeps_all_points(&self) -> bool {
+        false
+    }
+}
+
+/// Applies a rigid transformation, e.g. a scanner pose, to every point.
+pub struct Transform {
+    matrix: Matrix4f,
+}
+
+impl Transform {
+    pub fn new(matrix: Matrix4f) -> Self {
+        Transform { matrix: matrix }
+    }
+}
+
+impl Stage for Transform {
+    fn process(&mut self, point: &mut Point) -> bool {
+        point.position = (self.matrix * point.position.extend(1.)).truncate();
+        true
+    }
+
+    fn keeps_all_points(&self) -> bool {
+        true
+    }
+}
+
+/// Drops all points outside of a box.
+pub struct Crop {
+    bounding_box: Cuboid,
+}
+
+impl Crop {
+    pub fn new(bounding_box: Cuboid) -> Self {
+        Crop { bounding_box: bounding_box }
+    }
+}
+
+impl Stage for Crop {
+    fn process(&mut self, point: &mut Point) -> bool {
+        self.bounding_box.contains(&point.position)
+    }
+}
+
+/// Drops all points that are further than 'max_distance' away from 'origin', e.g. the scanner
+/// position.
+pub struct MaxDistance {
+    origin: Vector3f,
+    max_distance_squared: f32,
+}
+
+impl MaxDistance {
+    pub fn new(origin: Vector3f, max_distance: f32) -> Self {
+        MaxDistance {
+            origin: origin,
+            max_distance_squared: max_distance * max_distance,
+        }
+    }
+}
+
+impl Stage for MaxDistance {
+    fn process(&mut self, point: &mut Point) -> bool {
+        (point.position - self.origin).magnitude2() <= self.max_distance_squared
+    }
+}
+
+/// Keeps only every n-th point.
+pub struct EveryNth {
+    n: usize,
+    count: usize,
+}
+
+impl EveryNth {
+    pub fn new(n: usize) -> Self {
+        assert!(n > 0);
+        EveryNth { n: n, count: 0 }
+    }
+}
+
+impl Stage for EveryNth {
+    fn process(&mut self, _: &mut Point) -> bool {
+        let keep = self.count % self.n == 0;
+        self.count += 1;
+        keep
+    }
+}
+
+/// Keeps each point with probability 'fraction'. The random generator is seeded with a constant,
+/// so that reading the same input twice keeps the same points.
+pub struct RandomSubsample {
+    fraction: f32,
+    rng: XorShiftRng,
+}
+
+impl RandomSubsample {
+    pub fn new(fraction: f32) -> Self {
+        RandomSubsample {
+            fraction: fraction,
+            rng: XorShiftRng::from_seed([0x193a6754, 0xa8a7d469, 0x97830e05, 0x113ba7bb]),
+        }
+    }
+}
+
+impl Stage for RandomSubsample {
+    fn process(&mut self, _: &mut Point) -> bool {
+        self.rng.next_f32() < self.fraction
+    }
+}
+
+/// Maps every color channel through a lookup table.
+pub struct RemapColors {
+    table: Vec<u8>,
+}
+
+impl RemapColors {
+    pub fn new(table: Vec<u8>) -> Self {
+        assert_eq!(256, table.len());
+        RemapColors { table: table }
+    }
+
+    /// Multiplies all channels by 'gain', saturating at 255. Useful to brighten dark scans.
+    pub fn with_gain(gain: f32) -> Self {
+        Self::new(
+            (0..256)
+                .map(|v| (v as f32 * gain).max(0.).min(255.).round() as u8)
+                .collect(),
+        )
+    }
+}
+
+impl Stage for RemapColors {
+    fn process(&mut self, point: &mut Point) -> bool {
+        point.r = self.table[point.r as usize];
+        point.g = self.table[point.g as usize];
+        point.b = self.table[point.b as usize];
+        true
+    }
+
+    fn keeps_all_points(&self) -> bool {
+        true
+    }
+}
+
+/// Keeps only the points for which 'predicate' returns true, e.g. to drop points by color.
+pub struct Filter<F> {
+    predicate: F,
+}
+
+impl<F: FnMut(&Point) -> bool> Filter<F> {
+    pub fn new(predicate: F) -> Self {
+        Filter { predicate: predicate }
+    }
+}
+
+impl<F: FnMut(&Point) -> bool> Stage for Filter<F> {
+    fn process(&mut self, point: &mut Point) -> bool {
+        (self.predicate)(point)
+    }
+}
+
+/// Runs every point of 'inner' through a sequence of stages, in the order they were added.
+pub struct Pipeline<I> {
+    inner: I,
+    stages: Vec<Box<Stage + Send>>,
+}
+
+impl<I: InternalIterator> Pipeline<I> {
+    pub fn new(inner: I) -> Self {
+        Pipeline {
+            inner: inner,
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn add_stage<S: Stage + Send + 'static>(mut self, stage: S) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+}
+
+impl<I: InternalIterator> InternalIterator for Pipeline<I> {
+    fn size_hint(&self) -> Option<usize> {
+        if self.stages.iter().all(|s| s.keeps_all_points()) {
+            self.inner.size_hint()
+        } else {
+            None
+        }
+    }
+
+    fn for_each<F: FnMut(&Point)>(self, mut f: F) -> Result<()> {
+        let mut stages = self.stages;
+        self.inner
+            .for_each(
+                |p| {
+                    let mut point = p.clone();
+                    for stage in &mut stages {
+                        if !stage.process(&mut point) {
+                            return;
+                        }
+                    }
+                    f(&point);
+                }
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points_along_x(num_points: usize) -> Vec<Point> {
+        (0..num_points)
+                .map(
+                    |i| {
+                        Point {
+                            position: Vector3f::new(i as f32, 0., 0.),
+                            r: 100,
+                            g: 100,
+                            b: 100,
+                        }
+                    }
+                )
+                .collect()
+    }
+
+    fn collect<I: InternalIterator>(iterator: I) -> Vec<Point> {
+        let mut points = Vec::new();
+        iterator.for_each(|p| points.push(p.clone())).unwrap();
+        points
+    }
+
+    #[test]
+    fn test_transform_and_crop() {
+        let pipeline = Pipeline::new(points_along_x(10))
+            .add_stage(Transform::new(Matrix4f::from_translation(Vector3f::new(1., 2., 3.))))
+            .add_stage(
+                Crop::new(
+                    Cuboid::from_min_max(Vector3f::new(2., 0., 0.), Vector3f::new(4., 5., 5.)),
+                ),
+            );
+        assert_eq!(None, pipeline.size_hint());
+        let points = collect(pipeline);
+        assert_eq!(3, points.len());
+        assert_eq!(Vector3f::new(2., 2., 3.), points[0].position);
+        assert_eq!(Vector3f::new(4., 2., 3.), points[2].position);
+    }
+
+    #[test]
+    fn test_decimation() {
+        let every_third = Pipeline::new(points_along_x(10)).add_stage(EveryNth::new(3));
+        assert_eq!(4, collect(every_third).len());
+
+        let random_half = Pipeline::new(points_along_x(1000)).add_stage(RandomSubsample::new(0.5));
+        let num_kept = collect(random_half).len();
+        assert!(400 < num_kept && num_kept < 600);
+    }
+
+    #[test]
+    fn test_remap_colors() {
+        let pipeline = Pipeline::new(points_along_x(1)).add_stage(RemapColors::with_gain(3.));
+        assert_eq!(Some(1), pipeline.size_hint());
+        assert_eq!(255, collect(pipeline)[0].r);
+    }
+}
@@ -13,13 +13,14 @@
 // limitations under the License.
 
 use {InternalIterator, Point};
-use byteorder::{ByteOrder, LittleEndian};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+use compression;
 use errors::*;
-use math::Vector3f;
+use math::{InnerSpace, Vector3f};
+use rand::{Rng, SeedableRng, XorShiftRng};
 use std::fs::File;
-use std::io::{BufRead, BufReader, Seek, SeekFrom};
-use std::ops::Index;
-use std::path::Path;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
 use std::str;
 
 #[derive(Debug)]
@@ -54,26 +55,37 @@ impl DataType {
             _ => Err(ErrorKind::InvalidInput(format!("Invalid data type: {}", input)).into()),
         }
     }
-}
 
-impl Header {
-    fn has_element(&self, name: &str) -> bool {
-        self.elements.iter().any(|e| e.name == name)
+    fn size(&self) -> usize {
+        match *self {
+            DataType::Int8 | DataType::Uint8 => 1,
+            DataType::Int16 | DataType::Uint16 => 2,
+            DataType::Int32 | DataType::Uint32 | DataType::Float32 => 4,
+            DataType::Float64 => 8,
+        }
     }
-}
 
-impl<'a> Index<&'a str> for Header {
-    type Output = Element;
-    fn index(&self, name: &'a str) -> &Self::Output {
-        for element in &self.elements {
-            if element.name == name {
-                return element;
+    /// Reads a value of this type as integer, e.g. the length of a list or a vertex index.
+    fn read_integer<R: Read>(&self, reader: &mut R) -> io::Result<i64> {
+        let mut buf = [0u8; 8];
+        let buf = &mut buf[..self.size()];
+        reader.read_exact(buf)?;
+        Ok(
+            match *self {
+                DataType::Int8 => buf[0] as i8 as i64,
+                DataType::Uint8 => buf[0] as i64,
+                DataType::Int16 => LittleEndian::read_i16(buf) as i64,
+                DataType::Uint16 => LittleEndian::read_u16(buf) as i64,
+                DataType::Int32 => LittleEndian::read_i32(buf) as i64,
+                DataType::Uint32 => LittleEndian::read_u32(buf) as i64,
+                DataType::Float32 => LittleEndian::read_f32(buf) as i64,
+                DataType::Float64 => LittleEndian::read_f64(buf) as i64,
             }
-        }
-        panic!("Element {} does not exist.", name);
+        )
     }
 }
 
+
 #[derive(Debug,PartialEq)]
 enum Format {
     BinaryLittleEndianV1,
@@ -81,38 +93,76 @@ enum Format {
     AsciiV1,
 }
 
-// TODO(hrapp): Maybe support list properties too?
-#[derive(Debug)]
-struct ScalarProperty {
+#[derive(Debug,Clone)]
+struct Property {
     name: String,
+    // For list properties, this is the type of the items.
     data_type: DataType,
+    // Only set for list properties.
+    list_count_type: Option<DataType>,
+}
+
+impl Property {
+    /// Skips the value of this property for one element.
+    fn skip<R: Read>(&self, reader: &mut R) -> io::Result<()> {
+        let num_items = match self.list_count_type {
+            Some(count_type) => read_list_length(reader, count_type)?,
+            None => 1,
+        };
+        skip_bytes(reader, num_items as u64 * self.data_type.size() as u64)
+    }
 }
 
 #[derive(Debug)]
 struct Element {
     name: String,
     count: i64,
-    properties: Vec<ScalarProperty>,
+    properties: Vec<Property>,
 }
 
-impl<'a> Index<&'a str> for Element {
-    type Output = ScalarProperty;
-    fn index(&self, name: &'a str) -> &Self::Output {
-        for p in &self.properties {
-            if p.name == name {
-                return p;
+impl Element {
+    /// Skips the data of all instances of this element.
+    fn skip<R: Read>(&self, reader: &mut R) -> io::Result<()> {
+        if self.properties.iter().all(|p| p.list_count_type.is_none()) {
+            let num_bytes_per_element: usize =
+                self.properties.iter().map(|p| p.data_type.size()).sum();
+            return skip_bytes(reader, self.count as u64 * num_bytes_per_element as u64);
+        }
+        for _ in 0..self.count {
+            for property in &self.properties {
+                property.skip(reader)?;
             }
         }
-        panic!("Property does not exist!")
+        Ok(())
+    }
+}
+
+fn read_list_length<R: Read>(reader: &mut R, count_type: DataType) -> io::Result<usize> {
+    let num_items = count_type.read_integer(reader)?;
+    if num_items < 0 {
+        return Err(
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid list length {}.", num_items),
+            )
+        );
     }
+    Ok(num_items as usize)
 }
 
-fn parse_header<R: BufRead>(reader: &mut R) -> Result<(Header, usize)> {
+fn skip_bytes<R: Read>(reader: &mut R, num_bytes: u64) -> io::Result<()> {
+    let num_skipped = io::copy(&mut reader.by_ref().take(num_bytes), &mut io::sink())?;
+    if num_skipped != num_bytes {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Unexpected end of file."));
+    }
+    Ok(())
+}
+
+fn parse_header<R: BufRead>(reader: &mut R) -> Result<Header> {
     use errors::ErrorKind::InvalidInput;
 
-    let mut header_len = 0;
     let mut line = String::new();
-    header_len += reader.read_line(&mut line)?;
+    reader.read_line(&mut line)?;
     if line.trim() != "ply" {
         return Err(InvalidInput("Not a PLY file".to_string()).into());
     }
@@ -122,8 +172,14 @@ fn parse_header<R: BufRead>(reader: &mut R) -> Result<(Header, usize)> {
     let mut elements = Vec::new();
     loop {
         line.clear();
-        header_len += reader.read_line(&mut line)?;
+        let num_read = reader.read_line(&mut line)?;
+        if num_read == 0 {
+            return Err(InvalidInput("Unexpected end of file in header".to_string()).into());
+        }
         let entries: Vec<&str> = line.trim().split_whitespace().collect();
+        if entries.is_empty() {
+            continue;
+        }
         match entries[0] {
             "format" if entries.len() == 3 => {
                 if entries[2] != "1.0" {
@@ -147,15 +203,15 @@ fn parse_header<R: BufRead>(reader: &mut R) -> Result<(Header, usize)> {
                 if let Some(element) = current_element.take() {
                     elements.push(element);
                 }
+                let count = match entries[2].parse::<i64>() {
+                    // Negative counts would wrap around when used as sizes.
+                    Ok(count) if count >= 0 => count,
+                    _ => return Err(InvalidInput(format!("Invalid count: {}", entries[2])).into()),
+                };
                 current_element = Some(
                     Element {
                         name: entries[1].to_string(),
-                        count:
-                            entries[2]
-                                .parse::<i64>()
-                                .chain_err(
-                                    || InvalidInput(format!("Invalid count: {}", entries[2])),
-                                )?,
+                        count: count,
                         properties: Vec::new(),
                     }
                 );
@@ -169,14 +225,18 @@ fn parse_header<R: BufRead>(reader: &mut R) -> Result<(Header, usize)> {
                 };
                 let property = match entries[1] {
                     "list" if entries.len() == 5 => {
-                        // We do not support list properties.
-                        continue;
+                        Property {
+                            name: entries[4].to_string(),
+                            data_type: DataType::from_str(entries[3])?,
+                            list_count_type: Some(DataType::from_str(entries[2])?),
+                        }
                     }
                     data_type_str if entries.len() == 3 => {
                         let data_type = DataType::from_str(data_type_str)?;
-                        ScalarProperty {
+                        Property {
                             name: entries[2].to_string(),
                             data_type: data_type,
+                            list_count_type: None,
                         }
                     }
                     _ => return Err(InvalidInput(format!("Invalid line: {}", line)).into()),
@@ -198,11 +258,10 @@ fn parse_header<R: BufRead>(reader: &mut R) -> Result<(Header, usize)> {
     }
 
     Ok(
-        (Header {
-             elements: elements,
-             format: format.unwrap(),
-         },
-         header_len)
+        Header {
+            elements: elements,
+            format: format.unwrap(),
+        }
     )
 }
 
@@ -278,123 +337,448 @@ macro_rules! create_skip_fn {
     )
 }
 
-/// Opens a PLY file and checks that it is the correct format we support. Seeks in the file to the
-/// beginning of the binary data which must be (x, y, z, r, g, b) tuples.
-fn open(ply_file: &Path) -> Result<(BufReader<File>, i64, Vec<ReadingFn>)> {
-    let mut file = File::open(ply_file)
-        .chain_err(|| "Could not open input file.")?;
-    let mut reader = BufReader::new(file);
-    let (header, header_len) = parse_header(&mut reader)?;
-    file = reader.into_inner();
-    file.seek(SeekFrom::Start(header_len as u64))?;
-
-    if !header.has_element("vertex") {
-        panic!("Header does not have element 'vertex'");
-    }
+/// Reads the PLY header from 'reader' and checks that it is a format we support. Afterwards,
+/// 'reader' is positioned at the beginning of the binary data.
+fn read_header<R: BufRead>(reader: &mut R, ply_file: &Path) -> Result<Header> {
+    let header = parse_header(reader)
+        .chain_err(|| format!("Could not parse PLY header of {}.", ply_file.display()))?;
 
     if header.format != Format::BinaryLittleEndianV1 {
-        panic!("Unsupported PLY format: {:?}", header.format);
+        return Err(
+            ErrorKind::InvalidInput(
+                format!(
+                    "{}: Unsupported PLY format: {:?}",
+                    ply_file.display(),
+                    header.format
+                ),
+            )
+                    .into()
+        );
     }
+    Ok(header)
+}
 
-    let vertex = &header["vertex"];
-    let mut seen_x = false;
-    let mut seen_y = false;
-    let mut seen_z = false;
+// A run of scalar properties that is read in one go, optionally followed by a list property that
+// is skipped.
+struct Segment {
+    readers: Vec<ReadingFn>,
+    num_bytes: usize,
+    list: Option<Property>,
+}
 
-    let mut readers: Vec<ReadingFn> = Vec::new();
-    let mut num_bytes_per_point = 0;
+/// Reads the points of the 'vertex' element. Properties other than position and color, including
+/// list properties, are skipped.
+struct VertexReader {
+    segments: Vec<Segment>,
+    // Holds the bytes of a single segment.
+    buffer: Vec<u8>,
+}
 
-    for prop in &vertex.properties {
-        match &prop.name as &str {
-            "x" => {
-                readers.push(
-                    read_casted_property!(
-                        prop.data_type,
-                        point.position.x,
-                        &mut num_bytes_per_point
-                    )
-                );
-                seen_x = true;
-            }
-            "y" => {
-                readers.push(
-                    read_casted_property!(
-                        prop.data_type,
-                        point.position.y,
-                        &mut num_bytes_per_point
-                    )
+impl VertexReader {
+    fn new(vertex: &Element, ply_file: &Path) -> Result<Self> {
+        let mut seen_x = false;
+        let mut seen_y = false;
+        let mut seen_z = false;
+
+        let mut segments = Vec::new();
+        let mut readers: Vec<ReadingFn> = Vec::new();
+        let mut num_bytes = 0;
+
+        for prop in &vertex.properties {
+            if prop.list_count_type.is_some() {
+                println!("Will ignore list property '{}' on 'vertex'.", prop.name);
+                segments.push(
+                    Segment {
+                        readers: readers,
+                        num_bytes: num_bytes,
+                        list: Some(prop.clone()),
+                    }
                 );
-                seen_y = true;
+                readers = Vec::new();
+                num_bytes = 0;
+                continue;
             }
-            "z" => {
-                readers.push(
-                    read_casted_property!(
-                        prop.data_type,
-                        point.position.z,
-                        &mut num_bytes_per_point
-                    )
-                );
-                seen_z = true;
+            match &prop.name as &str {
+                "x" => {
+                    readers.push(
+                        read_casted_property!(prop.data_type, point.position.x, &mut num_bytes),
+                    );
+                    seen_x = true;
+                }
+                "y" => {
+                    readers.push(
+                        read_casted_property!(prop.data_type, point.position.y, &mut num_bytes),
+                    );
+                    seen_y = true;
+                }
+                "z" => {
+                    readers.push(
+                        read_casted_property!(prop.data_type, point.position.z, &mut num_bytes),
+                    );
+                    seen_z = true;
+                }
+                "r" | "red" => {
+                    readers.push(read_casted_property!(prop.data_type, point.r, &mut num_bytes));
+                }
+                "g" | "green" => {
+                    readers.push(read_casted_property!(prop.data_type, point.g, &mut num_bytes));
+                }
+                "b" | "blue" => {
+                    readers.push(read_casted_property!(prop.data_type, point.b, &mut num_bytes));
+                }
+                other => {
+                    println!("Will ignore property '{}' on 'vertex'.", other);
+                    use self::DataType::*;
+                    match prop.data_type {
+                        Uint8 | Int8 => readers.push(create_skip_fn!(&mut num_bytes, 1)),
+                        Uint16 | Int16 => readers.push(create_skip_fn!(&mut num_bytes, 2)),
+                        Uint32 | Int32 | Float32 => {
+                            readers.push(create_skip_fn!(&mut num_bytes, 4))
+                        }
+                        Float64 => readers.push(create_skip_fn!(&mut num_bytes, 8)),
+                    }
+                }
             }
-            "r" | "red" => {
-                readers
-                    .push(read_casted_property!(prop.data_type, point.r, &mut num_bytes_per_point));
+        }
+        segments.push(
+            Segment {
+                readers: readers,
+                num_bytes: num_bytes,
+                list: None,
             }
-            "g" | "green" => {
-                readers
-                    .push(read_casted_property!(prop.data_type, point.g, &mut num_bytes_per_point));
+        );
+
+        if !seen_x || !seen_y || !seen_z {
+            return Err(
+                ErrorKind::InvalidInput(
+                    format!(
+                        "{}: PLY must contain properties 'x', 'y', 'z' for 'vertex'.",
+                        ply_file.display()
+                    ),
+                )
+                        .into()
+            );
+        }
+
+        let buffer_len = segments.iter().map(|s| s.num_bytes).max().unwrap();
+        Ok(
+            VertexReader {
+                segments: segments,
+                buffer: vec![0; buffer_len],
             }
-            "b" | "blue" => {
-                readers
-                    .push(read_casted_property!(prop.data_type, point.b, &mut num_bytes_per_point));
+        )
+    }
+
+    /// Reads the next vertex into 'point'. Properties that are not in the file are not touched.
+    fn read<R: Read>(&mut self, reader: &mut R, point: &mut Point) -> io::Result<()> {
+        for segment in &self.segments {
+            let buffer = &mut self.buffer[..segment.num_bytes];
+            reader.read_exact(buffer)?;
+            let mut nread = 0;
+            for r in &segment.readers {
+                let cnread = nread;
+                r(&mut nread, &buffer[cnread..], point);
             }
-            other => {
-                println!("Will ignore property '{}' on 'vertex'.", other);
-                use self::DataType::*;
-                match prop.data_type {
-                    Uint8 | Int8 => readers.push(create_skip_fn!(&mut num_bytes_per_point, 1)),
-                    Uint16 | Int16 => readers.push(create_skip_fn!(&mut num_bytes_per_point, 2)),
-                    Uint32 | Int32 | Float32 => {
-                        readers.push(create_skip_fn!(&mut num_bytes_per_point, 4))
-                    }
-                    Float64 => readers.push(create_skip_fn!(&mut num_bytes_per_point, 8)),
-                }
+            if let Some(ref list) = segment.list {
+                list.skip(reader)?;
             }
         }
+        Ok(())
     }
+}
 
-    if !seen_x || !seen_y || !seen_z {
-        panic!("PLY must contain properties 'x', 'y', 'z' for 'vertex'.");
+fn default_point() -> Point {
+    Point {
+        position: Vector3f::new(0., 0., 0.),
+        r: 255,
+        g: 255,
+        b: 255,
     }
-
-    // We align the buffer of this 'BufReader' to points, so that we can index this buffer and know
-    // that it will always contain full points to parse.
-    Ok(
-        (BufReader::with_capacity(num_bytes_per_point * 1024, file),
-         header["vertex"].count,
-         readers)
-    )
 }
 
-
 /// Abstraction to read binary points from ply files into points.
 pub struct PlyIterator {
-    reader: BufReader<File>,
-    readers: Vec<ReadingFn>,
+    reader: BufReader<Box<Read + Send>>,
+    vertex_reader: VertexReader,
+    filename: PathBuf,
+    // The last point read. Properties that are not in the file keep their default value.
+    point: Point,
+    num_points_read: i64,
     pub num_total_points: i64,
 }
 
 impl PlyIterator {
+    /// Opens a PLY file, which can be compressed with gzip or zstd as indicated by a '.gz' or
+    /// '.zst' extension.
     pub fn new<P: AsRef<Path>>(ply_file: P) -> Result<Self> {
-        let (reader, num_total_points, readers) = open(ply_file.as_ref())?;
+        let reader = compression::open(ply_file.as_ref())?;
+        Self::from_reader(reader, ply_file)
+    }
+
+    /// Reads a PLY file from 'reader', which does not need to be seekable, e.g. stdin. 'name' is
+    /// only used in error messages. Elements in front of 'vertex', e.g. faces, are skipped.
+    pub fn from_reader<P: AsRef<Path>>(reader: Box<Read + Send>, name: P) -> Result<Self> {
+        let name = name.as_ref();
+        let mut reader = BufReader::new(reader);
+        let header = read_header(&mut reader, name)?;
+        let mut vertex = None;
+        for element in &header.elements {
+            if element.name == "vertex" {
+                vertex = Some(element);
+                break;
+            }
+            element
+                .skip(&mut reader)
+                .chain_err(
+                    || format!("{}: Could not skip element '{}'.", name.display(), element.name),
+                )?;
+        }
+        let vertex = match vertex {
+            Some(vertex) => vertex,
+            None => {
+                return Err(
+                    ErrorKind::InvalidInput(
+                        format!("{}: Header does not have element 'vertex'.", name.display()),
+                    )
+                            .into()
+                )
+            }
+        };
         Ok(
             PlyIterator {
                 reader: reader,
-                readers: readers,
-                num_total_points: num_total_points,
+                vertex_reader: VertexReader::new(vertex, name)?,
+                filename: name.to_path_buf(),
+                point: default_point(),
+                num_points_read: 0,
+                num_total_points: vertex.count,
             }
         )
     }
+
+    fn read_point(&mut self) -> Result<()> {
+        if let Err(err) = self.vertex_reader.read(&mut self.reader, &mut self.point) {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                return Err(
+                    ErrorKind::InvalidInput(
+                        format!(
+                            "{}: Unexpected end of file at point {} of {}.",
+                            self.filename.display(),
+                            self.num_points_read,
+                            self.num_total_points
+                        ),
+                    )
+                            .into()
+                );
+            }
+            return Err(err.into());
+        }
+        Ok(())
+    }
+}
+
+/// All vertices and faces of a PLY file, e.g. a triangle mesh.
+pub struct Mesh {
+    pub vertices: Vec<Point>,
+    /// Indices into 'vertices'. Faces can have any number of vertices.
+    pub faces: Vec<Vec<u32>>,
+}
+
+impl Mesh {
+    /// Reads a PLY file, which can be compressed like for 'PlyIterator::new'.
+    pub fn from_file<P: AsRef<Path>>(ply_file: P) -> Result<Self> {
+        let reader = compression::open(ply_file.as_ref())?;
+        Self::from_reader(reader, ply_file)
+    }
+
+    /// Like 'PlyIterator::from_reader', but the elements can be in any order. A file without a
+    /// 'face' element is a mesh without faces.
+    pub fn from_reader<P: AsRef<Path>>(reader: Box<Read + Send>, name: P) -> Result<Self> {
+        let name = name.as_ref();
+        let mut reader = BufReader::new(reader);
+        let header = read_header(&mut reader, name)?;
+        let mut vertices = None;
+        let mut faces = Vec::new();
+        for element in &header.elements {
+            let result = match &element.name as &str {
+                "vertex" => {
+                    let mut vertex_reader = VertexReader::new(element, name)?;
+                    let mut points = Vec::with_capacity(element.count as usize);
+                    let mut point = default_point();
+                    let mut result = Ok(());
+                    for _ in 0..element.count {
+                        result = vertex_reader.read(&mut reader, &mut point);
+                        if result.is_err() {
+                            break;
+                        }
+                        points.push(point.clone());
+                    }
+                    vertices = Some(points);
+                    result
+                }
+                "face" => read_faces(&mut reader, element, &mut faces),
+                _ => element.skip(&mut reader),
+            };
+            result
+                .chain_err(
+                    || format!("{}: Could not read element '{}'.", name.display(), element.name),
+                )?;
+        }
+        let vertices = match vertices {
+            Some(vertices) => vertices,
+            None => {
+                return Err(
+                    ErrorKind::InvalidInput(
+                        format!("{}: Header does not have element 'vertex'.", name.display()),
+                    )
+                            .into()
+                )
+            }
+        };
+        for face in &faces {
+            if let Some(index) = face.iter().find(|i| **i as usize >= vertices.len()) {
+                return Err(
+                    ErrorKind::InvalidInput(
+                        format!(
+                            "{}: Face references vertex {}, but there are only {} vertices.",
+                            name.display(),
+                            index,
+                            vertices.len()
+                        ),
+                    )
+                            .into()
+                );
+            }
+        }
+        Ok(
+            Mesh {
+                vertices: vertices,
+                faces: faces,
+            }
+        )
+    }
+
+    /// Returns the vertices and additional points sampled uniformly at random on the faces, on
+    /// average one point per 'spacing' x 'spacing' area. Colors are interpolated between the
+    /// vertices. Faces with more than three vertices are split into a triangle fan.
+    pub fn sample_points(&self, spacing: f32) -> Vec<Point> {
+        assert!(spacing > 0.);
+        // Seeded with a constant so that sampling the same mesh twice gives the same points.
+        let mut rng = XorShiftRng::from_seed([0x7d3a5c11, 0x26e48b9f, 0x9b1f03d7, 0x4c8e62a5]);
+        let mut points = self.vertices.clone();
+        for face in self.faces.iter().filter(|f| f.len() >= 3) {
+            let a = &self.vertices[face[0] as usize];
+            for i in 1..face.len() - 1 {
+                let b = &self.vertices[face[i] as usize];
+                let c = &self.vertices[face[i + 1] as usize];
+                let area = 0.5 *
+                           (b.position - a.position)
+                               .cross(c.position - a.position)
+                               .magnitude();
+                // Round randomly, so that many small triangles still get the right density.
+                let num_samples = area / (spacing * spacing);
+                let mut num_samples_rounded = num_samples.floor() as usize;
+                if rng.next_f32() < num_samples.fract() {
+                    num_samples_rounded += 1;
+                }
+                for _ in 0..num_samples_rounded {
+                    let mut u = rng.next_f32();
+                    let mut v = rng.next_f32();
+                    if u + v > 1. {
+                        u = 1. - u;
+                        v = 1. - v;
+                    }
+                    let w = 1. - u - v;
+                    let mix = |ca: u8, cb: u8, cc: u8| {
+                        (w * ca as f32 + u * cb as f32 + v * cc as f32).round().min(255.) as u8
+                    };
+                    points.push(
+                        Point {
+                            position: a.position * w + b.position * u + c.position * v,
+                            r: mix(a.r, b.r, c.r),
+                            g: mix(a.g, b.g, c.g),
+                            b: mix(a.b, b.b, c.b),
+                        }
+                    );
+                }
+            }
+        }
+        points
+    }
+}
+
+/// Reads the 'vertex_indices' (or 'vertex_index') list of every face into 'faces' and skips all
+/// other properties.
+fn read_faces<R: Read>(
+    reader: &mut R,
+    face: &Element,
+    faces: &mut Vec<Vec<u32>>,
+) -> io::Result<()> {
+    faces.reserve(face.count as usize);
+    for _ in 0..face.count {
+        let mut indices = Vec::new();
+        for property in &face.properties {
+            match (&property.name as &str, property.list_count_type) {
+                ("vertex_indices", Some(count_type)) |
+                ("vertex_index", Some(count_type)) => {
+                    let num_indices = read_list_length(reader, count_type)?;
+                    for _ in 0..num_indices {
+                        let index = property.data_type.read_integer(reader)?;
+                        if index < 0 || index > u32::max_value() as i64 {
+                            return Err(
+                                io::Error::new(
+                                    io::ErrorKind::InvalidData,
+                                    format!("Invalid vertex index {}.", index),
+                                )
+                            );
+                        }
+                        indices.push(index as u32);
+                    }
+                }
+                _ => property.skip(reader)?,
+            }
+        }
+        faces.push(indices);
+    }
+    Ok(())
+}
+
+/// Pull-based iterator over the points of a PLY file. Iteration stops after the first error.
+pub struct PlyPoints(PlyIterator);
+
+impl Iterator for PlyPoints {
+    type Item = Result<Point>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ply = &mut self.0;
+        if ply.num_points_read >= ply.num_total_points {
+            return None;
+        }
+        match ply.read_point() {
+            Ok(()) => {
+                ply.num_points_read += 1;
+                Some(Ok(ply.point.clone()))
+            }
+            Err(err) => {
+                ply.num_points_read = ply.num_total_points;
+                Some(Err(err))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let num_left = (self.0.num_total_points - self.0.num_points_read) as usize;
+        (0, Some(num_left))
+    }
+}
+
+impl IntoIterator for PlyIterator {
+    type Item = Result<Point>;
+    type IntoIter = PlyPoints;
+
+    fn into_iter(self) -> Self::IntoIter {
+        PlyPoints(self)
+    }
 }
 
 impl InternalIterator for PlyIterator {
@@ -402,42 +786,216 @@ impl InternalIterator for PlyIterator {
         Some(self.num_total_points as usize)
     }
 
-    fn for_each<F: FnMut(&Point)>(mut self, mut func: F) {
-        let mut point = Point {
-            position: Vector3f::new(0., 0., 0.),
-            r: 255,
-            g: 255,
-            b: 255,
-        };
+    fn for_each<F: FnMut(&Point)>(self, mut func: F) -> Result<()> {
+        for point in self {
+            func(&point?);
+        }
+        Ok(())
+    }
+}
 
-        for _ in 0..self.num_total_points {
-            let mut nread = 0;
+/// Encoding of the data of a PLY file written by 'PlyWriter'.
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+}
 
-            // We made sure before that the internal buffer of 'reader' is aligned to the number of
-            // bytes for a single point, therefore we can access it here and know that we can always
-            // read into it and are sure that it contains at least a full point.
-            {
-                let buf = self.reader.fill_buf().unwrap();
-                for r in &self.readers {
-                    let cnread = nread;
-                    r(&mut nread, &buf[cnread..], &mut point);
-                }
+// The number of points is only known once all points are written, so we reserve space for it in
+// the header and fill it in at the end.
+const COUNT_PLACEHOLDER_LEN: usize = 20;
+
+/// Returns the header for points with 'extra_properties' and the byte offset of 'num_points' in
+/// it.
+fn vertex_header(
+    encoding: Encoding,
+    num_points: &str,
+    extra_properties: &[&str],
+) -> Result<(String, u64)> {
+    let mut header = String::new();
+    header.push_str("ply\n");
+    header.push_str(
+        match encoding {
+            Encoding::Ascii => "format ascii 1.0\n",
+            Encoding::BinaryLittleEndian => "format binary_little_endian 1.0\n",
+        },
+    );
+    header.push_str("element vertex ");
+    let count_position = header.len() as u64;
+    header.push_str(&format!("{}\n", num_points));
+    header.push_str("property float x\n");
+    header.push_str("property float y\n");
+    header.push_str("property float z\n");
+    header.push_str("property uchar red\n");
+    header.push_str("property uchar green\n");
+    header.push_str("property uchar blue\n");
+    for name in extra_properties {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(
+                ErrorKind::InvalidInput(format!("Invalid property name '{}'.", name)).into()
+            );
+        }
+        header.push_str(&format!("property float {}\n", name));
+    }
+    header.push_str("end_header\n");
+    Ok((header, count_position))
+}
+
+fn write_vertex<W: Write>(
+    writer: &mut W,
+    encoding: Encoding,
+    p: &Point,
+    extra: &[f32],
+) -> io::Result<()> {
+    match encoding {
+        Encoding::Ascii => {
+            write!(
+                writer,
+                "{} {} {} {} {} {}",
+                p.position.x,
+                p.position.y,
+                p.position.z,
+                p.r,
+                p.g,
+                p.b
+            )?;
+            for value in extra {
+                write!(writer, " {}", value)?;
+            }
+            writeln!(writer, "")
+        }
+        Encoding::BinaryLittleEndian => {
+            writer.write_f32::<LittleEndian>(p.position.x)?;
+            writer.write_f32::<LittleEndian>(p.position.y)?;
+            writer.write_f32::<LittleEndian>(p.position.z)?;
+            writer.write_all(&[p.r, p.g, p.b])?;
+            for value in extra {
+                writer.write_f32::<LittleEndian>(*value)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Writes 'points' in the same format as 'PlyWriter' to 'writer', which does not need to be a
+/// file, e.g. to send them over the network.
+pub fn write_points<W: Write>(mut writer: W, encoding: Encoding, points: &[Point]) -> Result<()> {
+    let (header, _) = vertex_header(encoding, &points.len().to_string(), &[])?;
+    writer.write_all(header.as_bytes())?;
+    for p in points {
+        write_vertex(&mut writer, encoding, p, &[])?;
+    }
+    Ok(())
+}
+
+/// Writes points as 'vertex' element with the properties 'x', 'y', 'z' (float) and 'red',
+/// 'green', 'blue' (uchar), followed by optional extra float properties.
+pub struct PlyWriter {
+    writer: Option<BufWriter<File>>,
+    encoding: Encoding,
+    num_extra_properties: usize,
+    num_written: usize,
+    // Byte offset of the vertex count in the header.
+    count_position: u64,
+}
+
+impl PlyWriter {
+    /// Creates 'filename'. 'extra_properties' are the names of additional float properties of each
+    /// point, which are written with 'write_with_extra'.
+    pub fn new<P: AsRef<Path>>(
+        filename: P,
+        encoding: Encoding,
+        extra_properties: &[&str],
+    ) -> Result<Self> {
+        let filename = filename.as_ref();
+        let mut writer = BufWriter::new(
+            File::create(filename)
+                .chain_err(|| format!("Could not create {}.", filename.display()))?,
+        );
+
+        let (header, count_position) = vertex_header(
+            encoding,
+            &format!("{:1$}", 0, COUNT_PLACEHOLDER_LEN),
+            extra_properties,
+        )?;
+        writer.write_all(header.as_bytes())?;
+
+        Ok(
+            PlyWriter {
+                writer: Some(writer),
+                encoding: encoding,
+                num_extra_properties: extra_properties.len(),
+                num_written: 0,
+                count_position: count_position,
             }
+        )
+    }
+
+    pub fn write(&mut self, p: &Point) -> Result<()> {
+        self.write_with_extra(p, &[])
+    }
 
-            func(&point);
-            self.reader.consume(nread);
+    /// Writes 'p' and the values of the extra properties given to 'new', in the same order.
+    pub fn write_with_extra(&mut self, p: &Point, extra: &[f32]) -> Result<()> {
+        if extra.len() != self.num_extra_properties {
+            return Err(
+                ErrorKind::InvalidInput(
+                    format!(
+                        "Expected {} extra values, got {}.",
+                        self.num_extra_properties,
+                        extra.len()
+                    ),
+                )
+                        .into()
+            );
         }
+        write_vertex(self.writer.as_mut().unwrap(), self.encoding, p, extra)?;
+        self.num_written += 1;
+        Ok(())
+    }
+
+    pub fn num_written(&self) -> usize {
+        self.num_written
+    }
+
+    /// Fills in the number of points and closes the file.
+    pub fn finish(mut self) -> Result<()> {
+        self.write_count()
+    }
+
+    fn write_count(&mut self) -> Result<()> {
+        let writer = match self.writer.take() {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        let mut file = writer
+            .into_inner()
+            .chain_err(|| "Could not write PLY file.")?;
+        file.seek(SeekFrom::Start(self.count_position))?;
+        file.write_all(format!("{:<1$}", self.num_written, COUNT_PLACEHOLDER_LEN).as_bytes())?;
+        Ok(())
+    }
+}
+
+impl Drop for PlyWriter {
+    fn drop(&mut self) {
+        // Errors can only be reported by calling 'finish'.
+        let _ = self.write_count();
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::env;
+    use std::io::Cursor;
 
     fn points_from_file<P: AsRef<Path>>(path: P) -> Vec<Point> {
         let iterator = PlyIterator::new(path).unwrap();
         let mut points = Vec::new();
-        iterator.for_each(|p| { points.push(p.clone()); });
+        iterator
+            .for_each(|p| { points.push(p.clone()); })
+            .unwrap();
         points
     }
 
@@ -460,4 +1018,238 @@ mod tests {
         assert_eq!(points[0].r, 255);
         assert_eq!(points[7].r, 227);
     }
+
+    #[test]
+    fn test_truncated_file() {
+        let mut data = Vec::new();
+        File::open("src/test_data/xyz_f32_rgb_u8_le.ply")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        let len = data.len();
+        data.truncate(len - 5);
+        let path = env::temp_dir().join("ply_test_truncated_file.ply");
+        File::create(&path).unwrap().write_all(&data).unwrap();
+
+        let mut num_points = 0;
+        let result = PlyIterator::new(&path)
+            .unwrap()
+            .for_each(|_| num_points += 1);
+        assert!(result.is_err());
+        assert_eq!(7, num_points);
+    }
+
+    #[test]
+    fn test_pull_iteration() {
+        let iterator = PlyIterator::new("src/test_data/xyz_f32_rgb_u8_le.ply").unwrap();
+        let x: Vec<f32> = iterator
+            .into_iter()
+            .map(|p| p.unwrap().position.x)
+            .take(2)
+            .collect();
+        assert_eq!(vec![1., 4.], x);
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let mut data = Vec::new();
+        File::open("src/test_data/xyz_f32_rgb_u8_le.ply")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        let iterator = PlyIterator::from_reader(Box::new(Cursor::new(data)), "<memory>").unwrap();
+        let mut points = Vec::new();
+        iterator.for_each(|p| points.push(p.clone())).unwrap();
+        let expected = points_from_file("src/test_data/xyz_f32_rgb_u8_le.ply");
+        assert_eq!(expected.len(), points.len());
+        for (a, b) in expected.iter().zip(points.iter()) {
+            assert_eq!(a.position, b.position);
+            assert_eq!((a.r, a.g, a.b), (b.r, b.g, b.b));
+        }
+    }
+
+    // A unit square made of two triangles. The faces come before the vertices and the vertices have
+    // a list property in between their scalar properties.
+    fn square_mesh() -> Vec<u8> {
+        let mut data = b"ply
+format binary_little_endian 1.0
+element face 2
+property list uchar int vertex_indices
+element vertex 4
+property float x
+property float y
+property list uchar float normal
+property float z
+property uchar red
+property uchar green
+property uchar blue
+end_header
+"
+                .to_vec();
+        for face in &[[0, 1, 2], [0, 2, 3]] {
+            data.push(3);
+            for index in face {
+                data.write_i32::<LittleEndian>(*index).unwrap();
+            }
+        }
+        for (i, &(x, y)) in [(0., 0.), (1., 0.), (1., 1.), (0., 1.)].iter().enumerate() {
+            data.write_f32::<LittleEndian>(x).unwrap();
+            data.write_f32::<LittleEndian>(y).unwrap();
+            data.push(3);
+            for n in &[0., 0., 1.] {
+                data.write_f32::<LittleEndian>(*n).unwrap();
+            }
+            data.write_f32::<LittleEndian>(0.).unwrap();
+            data.extend(&[i as u8 * 10, 0, 0]);
+        }
+        data
+    }
+
+    #[test]
+    fn test_faces_before_vertices() {
+        let iterator = PlyIterator::from_reader(Box::new(Cursor::new(square_mesh())), "<memory>")
+            .unwrap();
+        let mut points = Vec::new();
+        iterator.for_each(|p| points.push(p.clone())).unwrap();
+        assert_eq!(4, points.len());
+        assert_eq!(Vector3f::new(1., 1., 0.), points[2].position);
+        assert_eq!(30, points[3].r);
+    }
+
+    #[test]
+    fn test_negative_count() {
+        let data = b"ply
+format binary_little_endian 1.0
+element vertex -1
+property float x
+property float y
+property float z
+end_header
+"
+                .to_vec();
+        assert!(PlyIterator::from_reader(Box::new(Cursor::new(data)), "<memory>").is_err());
+    }
+
+    #[test]
+    fn test_mesh() {
+        let mesh = Mesh::from_reader(Box::new(Cursor::new(square_mesh())), "<memory>").unwrap();
+        assert_eq!(4, mesh.vertices.len());
+        assert_eq!(vec![vec![0, 1, 2], vec![0, 2, 3]], mesh.faces);
+        assert_eq!(Vector3f::new(0., 1., 0.), mesh.vertices[3].position);
+    }
+
+    #[test]
+    fn test_mesh_with_invalid_index() {
+        let mut data = square_mesh();
+        // Replace the last index of the first face.
+        let offset = data.len() - 4 * 28 - 13 - 4;
+        data[offset] = 4;
+        assert!(Mesh::from_reader(Box::new(Cursor::new(data)), "<memory>").is_err());
+    }
+
+    #[test]
+    fn test_sample_points() {
+        let mesh = Mesh::from_reader(Box::new(Cursor::new(square_mesh())), "<memory>").unwrap();
+        let points = mesh.sample_points(0.125);
+        // Each triangle has an area of 0.5, i.e. 32 samples.
+        assert_eq!(4 + 64, points.len());
+        for p in &points {
+            assert!(p.position.x >= 0. && p.position.x <= 1.);
+            assert!(p.position.y >= 0. && p.position.y <= 1.);
+            assert_eq!(0., p.position.z);
+            assert!(p.r <= 30);
+        }
+    }
+
+    fn test_points() -> Vec<Point> {
+        (0..10)
+            .map(
+                |i| {
+                    Point {
+                        position: Vector3f::new(i as f32, 0.25 * i as f32, -1.5),
+                        r: i as u8,
+                        g: 100,
+                        b: 255 - i as u8,
+                    }
+                }
+            )
+            .collect()
+    }
+
+    #[test]
+    fn test_writer_round_trip() {
+        let path = env::temp_dir().join("ply_test_writer_round_trip.ply");
+        let points = test_points();
+        {
+            let mut writer = PlyWriter::new(&path, Encoding::BinaryLittleEndian, &[]).unwrap();
+            for p in &points {
+                writer.write(p).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        let read = points_from_file(&path);
+        assert_eq!(points.len(), read.len());
+        for (a, b) in points.iter().zip(read.iter()) {
+            assert_eq!(a.position, b.position);
+            assert_eq!((a.r, a.g, a.b), (b.r, b.g, b.b));
+        }
+    }
+
+    #[test]
+    fn test_writer_extra_properties() {
+        let path = env::temp_dir().join("ply_test_writer_extra_properties.ply");
+        let points = test_points();
+        {
+            let mut writer =
+                PlyWriter::new(&path, Encoding::BinaryLittleEndian, &["intensity", "time"])
+                    .unwrap();
+            assert!(writer.write(&points[0]).is_err());
+            for (i, p) in points.iter().enumerate() {
+                writer.write_with_extra(p, &[0.5, i as f32]).unwrap();
+            }
+            // The vertex count is filled in when the writer is dropped.
+        }
+        let read = points_from_file(&path);
+        assert_eq!(points.len(), read.len());
+        assert_eq!(points[9].position, read[9].position);
+        assert_eq!(points[9].b, read[9].b);
+    }
+
+    #[test]
+    fn test_writer_ascii() {
+        let path = env::temp_dir().join("ply_test_writer_ascii.ply");
+        let points = test_points();
+        {
+            let mut writer = PlyWriter::new(&path, Encoding::Ascii, &["intensity"]).unwrap();
+            writer.write_with_extra(&points[2], &[0.5]).unwrap();
+            writer.write_with_extra(&points[3], &[1.]).unwrap();
+            writer.finish().unwrap();
+        }
+        let mut content = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!("element vertex 2", lines[2].trim_end());
+        assert_eq!(
+            vec!["2 0.5 -1.5 2 100 253 0.5", "3 0.75 -1.5 3 100 252 1"],
+            lines[11..].to_vec()
+        );
+    }
+
+    #[test]
+    fn test_write_points_to_memory() {
+        let points = test_points();
+        let mut data = Vec::new();
+        write_points(&mut data, Encoding::BinaryLittleEndian, &points).unwrap();
+        let mut read = Vec::new();
+        PlyIterator::from_reader(Box::new(Cursor::new(data)), "memory")
+            .unwrap()
+            .for_each(|p| { read.push(p.clone()); })
+            .unwrap();
+        assert_eq!(points.len(), read.len());
+        assert_eq!(points[9].position, read[9].position);
+        assert_eq!(points[9].g, read[9].g);
+    }
 }
@@ -13,52 +13,454 @@
 // limitations under the License.
 
 use {InternalIterator, Point};
+use compression;
+use errors::*;
 use math::Vector3f;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::path::Path;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Which values of a line hold the position and the color.
+#[derive(Debug,Clone,PartialEq)]
+pub struct Columns {
+    pub position: [usize; 3],
+    pub color: Option<[usize; 3]>,
+}
+
+impl FromStr for Columns {
+    type Err = Error;
+
+    /// Parses a comma separated list of column names, e.g. "x,y,z,_,r,g,b". Valid names are 'x',
+    /// 'y', 'z', 'r', 'g', 'b' and '_' for columns that are ignored. 'x', 'y' and 'z' are
+    /// required, colors are optional.
+    fn from_str(spec: &str) -> Result<Self> {
+        let names: Vec<&str> = spec.split(',').map(|s| s.trim()).collect();
+        for name in &names {
+            match *name {
+                "x" | "y" | "z" | "r" | "g" | "b" | "_" => (),
+                other => {
+                    return Err(
+                        ErrorKind::InvalidInput(format!("Invalid column '{}'.", other)).into()
+                    )
+                }
+            }
+            if *name != "_" && names.iter().filter(|n| **n == *name).count() > 1 {
+                return Err(
+                    ErrorKind::InvalidInput(format!("Column '{}' is given twice.", name)).into()
+                );
+            }
+        }
+        let index = |name: &str| names.iter().position(|n| *n == name);
+        let position = match (index("x"), index("y"), index("z")) {
+            (Some(x), Some(y), Some(z)) => [x, y, z],
+            _ => {
+                return Err(
+                    ErrorKind::InvalidInput("Columns 'x', 'y' and 'z' are required.".to_string())
+                        .into()
+                )
+            }
+        };
+        let color = match (index("r"), index("g"), index("b")) {
+            (Some(r), Some(g), Some(b)) => Some([r, g, b]),
+            (None, None, None) => None,
+            _ => {
+                return Err(
+                    ErrorKind::InvalidInput("Either all or none of 'r', 'g' and 'b' are required."
+                                                .to_string())
+                            .into()
+                )
+            }
+        };
+        Ok(
+            Columns {
+                position: position,
+                color: color,
+            }
+        )
+    }
+}
+
+/// The layout of the lines of a delimited text file.
+#[derive(Debug,Clone,PartialEq)]
+pub struct TextFormat {
+    pub columns: Columns,
+    /// Values are separated by this character. If None, by any number of spaces, tabs or commas.
+    pub delimiter: Option<char>,
+    /// Number of lines at the beginning of the file that are ignored.
+    pub num_header_lines: usize,
+    /// If not empty, lines with a number of values that is not listed here are skipped, e.g. the
+    /// point counts in PTS and XYZ files.
+    pub num_values: Vec<usize>,
+    /// If true, lines with a single integer are skipped, i.e. the point counts in PTS and XYZ
+    /// files. Unlike 'num_values', this also works with custom columns.
+    pub skip_count_lines: bool,
+    /// Color of points on lines without color values.
+    pub default_color: (u8, u8, u8),
+}
+
+impl TextFormat {
+    /// x y z intensity r g b
+    pub fn pts() -> Self {
+        TextFormat {
+            columns: Columns {
+                position: [0, 1, 2],
+                color: Some([4, 5, 6]),
+            },
+            delimiter: None,
+            num_header_lines: 0,
+            num_values: vec![7],
+            skip_count_lines: true,
+            default_color: (255, 255, 255),
+        }
+    }
+
+    /// x y z, optionally followed by r g b.
+    pub fn xyz() -> Self {
+        TextFormat {
+            columns: Columns {
+                position: [0, 1, 2],
+                color: Some([3, 4, 5]),
+            },
+            delimiter: None,
+            num_header_lines: 0,
+            num_values: vec![3, 6],
+            skip_count_lines: true,
+            default_color: (255, 255, 255),
+        }
+    }
+}
+
+/// Counts the lines a 'PtsIterator' skipped. It is shared with the caller, since the iterator is
+/// consumed while reading.
+#[derive(Debug,Default)]
+pub struct SkippedLines {
+    // Lines with a number of values that is not in 'TextFormat::num_values'.
+    num_ignored: AtomicUsize,
+    // Lines that could not be parsed, only with 'skip_invalid_lines'.
+    num_invalid: AtomicUsize,
+}
+
+impl SkippedLines {
+    pub fn num_ignored(&self) -> usize {
+        self.num_ignored.load(Ordering::SeqCst)
+    }
+
+    pub fn num_invalid(&self) -> usize {
+        self.num_invalid.load(Ordering::SeqCst)
+    }
+}
 
-#[derive(Debug)]
 pub struct PtsIterator {
-    data: BufReader<File>,
+    data: BufReader<Box<Read + Send>>,
+    filename: PathBuf,
+    format: TextFormat,
+    skip_invalid_lines: bool,
+    line: String,
+    line_number: usize,
+    skipped_lines: Arc<SkippedLines>,
+    done: bool,
 }
 
 impl PtsIterator {
-    pub fn new(filename: &Path) -> Self {
-        let file = File::open(filename).unwrap();
-        PtsIterator { data: BufReader::new(file) }
+    /// Opens a text file, which can be compressed with gzip or zstd as indicated by a '.gz' or
+    /// '.zst' extension.
+    pub fn new(filename: &Path) -> Result<Self> {
+        let reader = compression::open(filename)?;
+        Ok(Self::from_reader(reader, filename))
     }
-}
 
-impl InternalIterator for PtsIterator {
-    fn size_hint(&self) -> Option<usize> {
-        None
+    /// Reads lines from 'reader', e.g. stdin. 'name' is only used in error messages.
+    pub fn from_reader<P: AsRef<Path>>(reader: Box<Read + Send>, name: P) -> Self {
+        PtsIterator {
+            data: BufReader::new(reader),
+            filename: name.as_ref().to_path_buf(),
+            format: TextFormat::pts(),
+            skip_invalid_lines: false,
+            line: String::new(),
+            line_number: 0,
+            skipped_lines: Arc::new(SkippedLines::default()),
+            done: false,
+        }
     }
 
-    fn for_each<F: FnMut(&Point)>(mut self, mut f: F) {
-        let mut line = String::new();
-        loop {
-            line.clear();
-            self.data.read_line(&mut line).unwrap();
-            if line.is_empty() {
-                break;
+    /// Sets the layout of the lines. Defaults to 'TextFormat::pts()'.
+    pub fn format(mut self, format: TextFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Instead of failing on the first line that cannot be parsed, skip such lines and count them
+    /// in 'skipped_lines'.
+    pub fn skip_invalid_lines(mut self) -> Self {
+        self.skip_invalid_lines = true;
+        self
+    }
+
+    /// Returns the number of skipped lines, which are counted while iterating.
+    pub fn skipped_lines(&self) -> Arc<SkippedLines> {
+        self.skipped_lines.clone()
+    }
+
+    fn parse_line(&self, parts: &[&str], line_number: usize) -> Result<Point> {
+        let columns = &self.format.columns;
+        let num_required = columns.position.iter().max().unwrap() + 1;
+        if parts.len() < num_required {
+            return Err(
+                ErrorKind::InvalidInput(
+                    format!(
+                        "{}:{}: Expected at least {} values.",
+                        self.filename.display(),
+                        line_number,
+                        num_required
+                    ),
+                )
+                        .into()
+            );
+        }
+        let parse = |index: usize| -> Result<f32> {
+            let value = parts[index];
+            match f32::from_str(value) {
+                // NaN or infinite positions cannot be placed in the octree.
+                Ok(number) if number.is_finite() => Ok(number),
+                _ => {
+                    Err(
+                        ErrorKind::InvalidInput(
+                            format!(
+                                "{}:{}: Could not parse '{}' as a finite number.",
+                                self.filename.display(),
+                                line_number,
+                                value
+                            ),
+                        )
+                                .into(),
+                    )
+                }
             }
+        };
+        let parse_color = |index: usize| -> Result<u8> {
+            let value = parts[index];
+            u8::from_str(value).chain_err(
+                || {
+                    ErrorKind::InvalidInput(
+                        format!(
+                            "{}:{}: Could not parse '{}' as a color.",
+                            self.filename.display(),
+                            line_number,
+                            value
+                        ),
+                    )
+                },
+            )
+        };
+        let position = Vector3f::new(
+            parse(columns.position[0])?,
+            parse(columns.position[1])?,
+            parse(columns.position[2])?,
+        );
+        let (r, g, b) = match columns.color {
+            // Lines without colors get the default color.
+            Some(c) if c.iter().all(|i| *i < parts.len()) => {
+                (parse_color(c[0])?, parse_color(c[1])?, parse_color(c[2])?)
+            }
+            _ => self.format.default_color,
+        };
+        Ok(
+            Point {
+                position: position,
+                r: r,
+                g: g,
+                b: b,
+            }
+        )
+    }
 
-            let parts: Vec<&str> = line.trim().split(|c| c == ' ' || c == ',').collect();
-            if parts.len() != 7 {
+    /// Returns the next point, or None at the end of the file.
+    fn read_point(&mut self) -> Result<Option<Point>> {
+        loop {
+            self.line.clear();
+            self.data
+                .read_line(&mut self.line)
+                .chain_err(|| format!("Could not read {}.", self.filename.display()))?;
+            if self.line.is_empty() {
+                return Ok(None);
+            }
+            self.line_number += 1;
+            if self.line_number <= self.format.num_header_lines || self.line.trim().is_empty() {
                 continue;
             }
-            let p = Point {
-                position: Vector3f::new(
-                    parts[0].parse::<f32>().unwrap(),
-                    parts[1].parse::<f32>().unwrap(),
-                    parts[2].parse::<f32>().unwrap(),
-                ),
-                r: parts[4].parse::<u8>().unwrap(),
-                g: parts[5].parse::<u8>().unwrap(),
-                b: parts[6].parse::<u8>().unwrap(),
+
+            let result = {
+                let line = self.line.trim();
+                let parts: Vec<&str> = match self.format.delimiter {
+                    Some(delimiter) => line.split(delimiter).map(|s| s.trim()).collect(),
+                    None => {
+                        line.split(|c| c == ' ' || c == '\t' || c == ',')
+                            .filter(|s| !s.is_empty())
+                            .collect()
+                    }
+                };
+                let is_count_line = self.format.skip_count_lines && parts.len() == 1 &&
+                                    u64::from_str(parts[0]).is_ok();
+                if is_count_line ||
+                   (!self.format.num_values.is_empty() &&
+                    !self.format.num_values.contains(&parts.len())) {
+                    self.skipped_lines.num_ignored.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+                self.parse_line(&parts, self.line_number)
             };
-            f(&p);
+            match result {
+                Ok(p) => return Ok(Some(p)),
+                Err(_) if self.skip_invalid_lines => {
+                    self.skipped_lines.num_invalid.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Pull-based iterator over the points of a PTS file. Iteration stops after the first error.
+pub struct PtsPoints(PtsIterator);
+
+impl Iterator for PtsPoints {
+    type Item = Result<Point>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.0.done {
+            return None;
+        }
+        match self.0.read_point() {
+            Ok(Some(p)) => Some(Ok(p)),
+            Ok(None) => {
+                self.0.done = true;
+                None
+            }
+            Err(err) => {
+                self.0.done = true;
+                Some(Err(err))
+            }
         }
     }
 }
+
+impl IntoIterator for PtsIterator {
+    type Item = Result<Point>;
+    type IntoIter = PtsPoints;
+
+    fn into_iter(self) -> Self::IntoIter {
+        PtsPoints(self)
+    }
+}
+
+impl InternalIterator for PtsIterator {
+    fn size_hint(&self) -> Option<usize> {
+        None
+    }
+
+    fn for_each<F: FnMut(&Point)>(self, mut f: F) -> Result<()> {
+        for point in self {
+            f(&point?);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn iterator_from_str(data: &str, format: TextFormat) -> PtsIterator {
+        PtsIterator::from_reader(Box::new(Cursor::new(data.to_string())), "test").format(format)
+    }
+
+    fn points_from_str(data: &str, format: TextFormat) -> Vec<Point> {
+        let mut points = Vec::new();
+        iterator_from_str(data, format)
+            .for_each(|p| points.push(p.clone()))
+            .unwrap();
+        points
+    }
+
+    #[test]
+    fn test_pts() {
+        let points = points_from_str(
+            "2\n1 2 3 0.5 10 20 30\n4,5,6,0.5,40,50,60\n",
+            TextFormat::pts(),
+        );
+        assert_eq!(2, points.len());
+        assert_eq!(Vector3f::new(4., 5., 6.), points[1].position);
+        assert_eq!((10, 20, 30), (points[0].r, points[0].g, points[0].b));
+    }
+
+    #[test]
+    fn test_pts_points_stop_at_error() {
+        let data = "1 2 3 0.5 10 20 30\n1 2 a 0.5 1 2 3\n4 5 6 0.5 1 2 3\n";
+        let mut points = iterator_from_str(data, TextFormat::pts()).into_iter();
+        assert_eq!(Vector3f::new(1., 2., 3.), points.next().unwrap().unwrap().position);
+        assert!(points.next().unwrap().is_err());
+        assert!(points.next().is_none());
+
+        let mut num_points = 0;
+        let result = iterator_from_str(data, TextFormat::pts()).for_each(|_| num_points += 1);
+        assert!(result.is_err());
+        assert_eq!(1, num_points);
+    }
+
+    #[test]
+    fn test_skipped_lines() {
+        let data = "2\n1 2 3 0.5 10 20 30\n1 2 a 0.5 1 2 3\n";
+        let iterator = iterator_from_str(data, TextFormat::pts()).skip_invalid_lines();
+        let skipped_lines = iterator.skipped_lines();
+        assert_eq!(1, iterator.into_iter().count());
+        assert_eq!(1, skipped_lines.num_ignored());
+        assert_eq!(1, skipped_lines.num_invalid());
+    }
+
+    #[test]
+    fn test_non_finite_positions_are_invalid() {
+        let data = "1 2 3\nnan 2 3\n1 inf 3\n";
+        let iterator = iterator_from_str(data, TextFormat::xyz()).skip_invalid_lines();
+        let skipped_lines = iterator.skipped_lines();
+        assert_eq!(1, iterator.into_iter().count());
+        assert_eq!(2, skipped_lines.num_invalid());
+    }
+
+    #[test]
+    fn test_xyz() {
+        let points = points_from_str("2\n1 2 3\n4 5 6 40 50 60\n", TextFormat::xyz());
+        assert_eq!(2, points.len());
+        assert_eq!(255, points[0].r);
+        assert_eq!((40, 50, 60), (points[1].r, points[1].g, points[1].b));
+    }
+
+    #[test]
+    fn test_custom_columns() {
+        let format = TextFormat {
+            columns: Columns::from_str("_,z,y,x").unwrap(),
+            delimiter: Some(';'),
+            num_header_lines: 1,
+            num_values: Vec::new(),
+            skip_count_lines: true,
+            default_color: (1, 2, 3),
+        };
+        let points = points_from_str("id;z;y;x\n1\n7; 3 ;2;1\n", format);
+        assert_eq!(1, points.len());
+        assert_eq!(Vector3f::new(1., 2., 3.), points[0].position);
+        assert_eq!((1, 2, 3), (points[0].r, points[0].g, points[0].b));
+    }
+
+    #[test]
+    fn test_invalid_columns() {
+        assert!(Columns::from_str("x,y").is_err());
+        assert!(Columns::from_str("x,y,z,r,g").is_err());
+        assert!(Columns::from_str("x,y,z,x").is_err());
+        assert_eq!(
+            Some([3, 4, 5]),
+            Columns::from_str("x,y,z,r,g,b").unwrap().color
+        );
+    }
+}
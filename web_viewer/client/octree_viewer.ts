@@ -68,6 +68,16 @@ function matrixToString(m: THREE.Matrix4): string {
   ].join(',');
 }
 
+// Appends the 'access_token' of the page URL, if any, to 'url'. The server requires it for all data
+// requests if it runs with --access_token_file.
+function withAccessToken(url: string): string {
+  const match = /[?&]access_token=([^&#]*)/.exec(window.location.search);
+  if (match === null) {
+    return url;
+  }
+  return url + (url.indexOf('?') === -1 ? '?' : '&') + 'access_token=' + match[1];
+}
+
 class NodeRenderData {
   constructor(
       public min: THREE.Vector3,
@@ -89,7 +99,7 @@ class NodeLoader {
     }
     const headers = new Headers();
     headers.append('Content-Type', 'application/json; charset=UTF-8');
-    const request = new Request('/nodes_data', {
+    const request = new Request(withAccessToken('/nodes_data'), {
       method: 'POST',
       body: '[' + query.join(',') + ']',
       headers: headers,
@@ -301,7 +311,8 @@ export class OctreeViewer {
   public frustumChanged(matrix: THREE.Matrix4, width: number, height: number) {
     // ThreeJS is column major.
     const request = new Request(
-        `/visible_nodes?use_lod=${this.useLod ? 1 : 0}&width=${width}&height=${height}&matrix=${matrixToString(matrix)}`,
+        withAccessToken(
+            `/visible_nodes?use_lod=${this.useLod ? 1 : 0}&width=${width}&height=${height}&matrix=${matrixToString(matrix)}`),
         {
           method: 'GET',
           credentials: 'same-origin',
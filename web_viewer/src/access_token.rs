@@ -0,0 +1,170 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use {HttpError, invalid_input};
+use iron::{self, IronError, IronResult, Plugin, Request, Response};
+use point_viewer::errors::*;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::Arc;
+use urlencoded::UrlEncodedQuery;
+
+/// A secret that clients need to send to access the data, either in an
+/// 'Authorization: Bearer <token>' header or as 'access_token' query parameter. The latter is for
+/// clients that cannot set headers, e.g. browsers opening a WebSocket.
+pub struct AccessToken {
+    token: String,
+}
+
+impl AccessToken {
+    pub fn new<S: Into<String>>(token: S) -> Result<Self> {
+        let token = token.into();
+        if token.is_empty() || token.contains(char::is_whitespace) {
+            return Err(
+                invalid_input("The access token must not be empty or contain whitespace."),
+            );
+        }
+        Ok(AccessToken { token: token })
+    }
+
+    /// Reads the token from the first line of 'path', so that it does not show up in the list of
+    /// processes like a command line argument would.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let mut content = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut content))
+            .chain_err(|| format!("Could not read access token from {}.", path.display()))?;
+        Self::new(content.lines().next().unwrap_or("").trim())
+    }
+
+    /// Compares in constant time, so that the token cannot be guessed from response times.
+    pub fn matches(&self, candidate: &str) -> bool {
+        let token = self.token.as_bytes();
+        let candidate = candidate.as_bytes();
+        if token.len() != candidate.len() {
+            return false;
+        }
+        token
+            .iter()
+            .zip(candidate)
+            .fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+    }
+
+    /// Checks the value of the 'Authorization' header or, if there is none, of the 'access_token'
+    /// query parameter.
+    pub fn verify(
+        &self,
+        authorization: Option<&[u8]>,
+        query_token: Option<&str>,
+    ) -> ::std::result::Result<(), HttpError> {
+        let candidate = match authorization {
+            Some(value) => {
+                let value = String::from_utf8_lossy(value);
+                if !value.starts_with("Bearer ") {
+                    return Err(HttpError::unauthorized("Expected a bearer token."));
+                }
+                value["Bearer ".len()..].trim().to_string()
+            }
+            None => query_token.unwrap_or("").to_string(),
+        };
+        if candidate.is_empty() {
+            return Err(HttpError::unauthorized("An access token is required."));
+        }
+        if !self.matches(&candidate) {
+            return Err(HttpError::unauthorized("Invalid access token."));
+        }
+        Ok(())
+    }
+
+    fn check(&self, req: &mut Request) -> ::std::result::Result<(), HttpError> {
+        let authorization = req.headers
+            .get_raw("Authorization")
+            .and_then(|values| values.first())
+            .cloned();
+        let query_token = req.get_ref::<UrlEncodedQuery>()
+            .ok()
+            .and_then(|query| query.get("access_token"))
+            .and_then(|values| values.first())
+            .cloned();
+        self.verify(
+            authorization.as_ref().map(|value| value.as_slice()),
+            query_token.as_ref().map(|value| value.as_str()),
+        )
+    }
+}
+
+/// Serves requests with 'handler' if they carry the access token.
+struct Guarded<H> {
+    handler: H,
+    access_token: Arc<AccessToken>,
+}
+
+impl<H: iron::Handler> iron::Handler for Guarded<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        if let Err(err) = self.access_token.check(req) {
+            let mut err = IronError::from(err);
+            err.response
+                .headers
+                .set_raw("WWW-Authenticate", vec![b"Bearer".to_vec()]);
+            return Err(err);
+        }
+        self.handler.handle(req)
+    }
+}
+
+/// Returns 'handler', which requires 'access_token' if there is one.
+pub fn guarded<H: iron::Handler>(
+    access_token: &Option<Arc<AccessToken>>,
+    handler: H,
+) -> Box<iron::Handler> {
+    match *access_token {
+        Some(ref access_token) => {
+            Box::new(
+                Guarded {
+                    handler: handler,
+                    access_token: access_token.clone(),
+                },
+            )
+        }
+        None => Box::new(handler),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let access_token = AccessToken::new("secret").unwrap();
+        assert!(access_token.matches("secret"));
+        assert!(!access_token.matches("secreT"));
+        assert!(!access_token.matches("secret2"));
+        assert!(!access_token.matches(""));
+        assert!(AccessToken::new("").is_err());
+        assert!(AccessToken::new("a b").is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let access_token = AccessToken::new("secret").unwrap();
+        assert!(access_token.verify(Some(b"Bearer secret"), None).is_ok());
+        assert!(access_token.verify(None, Some("secret")).is_ok());
+        // The header takes precedence.
+        assert!(access_token.verify(Some(b"Bearer wrong"), Some("secret")).is_err());
+        assert!(access_token.verify(Some(b"Basic c2VjcmV0"), None).is_err());
+        assert!(access_token.verify(None, None).is_err());
+    }
+}
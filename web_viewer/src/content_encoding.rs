@@ -0,0 +1,132 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use brotli2::write::BrotliEncoder;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use iron::Request;
+use std::io::{self, Write};
+use std::str;
+
+// Quality 5 compresses node data nearly as well as the maximum of 11, at a fraction of the cost.
+const BROTLI_QUALITY: u32 = 5;
+
+/// Compression applied to a response body, chosen from the 'Accept-Encoding' header of the
+/// request.
+#[derive(Debug,Clone,Copy,PartialEq,Eq,Hash)]
+pub enum ContentEncoding {
+    Identity,
+    Gzip,
+    Brotli,
+}
+
+impl ContentEncoding {
+    /// The name used in the 'Accept-Encoding' and 'Content-Encoding' headers.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ContentEncoding::Identity => "identity",
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Brotli => "br",
+        }
+    }
+
+    /// Picks the encoding with the highest quality value in 'accept_encoding', preferring brotli
+    /// over gzip if both are equally acceptable. Unknown or malformed entries are ignored.
+    pub fn negotiate(accept_encoding: &str) -> Self {
+        let mut wildcard_quality = None;
+        let mut qualities = Vec::new();
+        for item in accept_encoding.split(',') {
+            let mut parts = item.split(';');
+            let name = parts.next().unwrap().trim().to_lowercase();
+            if name.is_empty() {
+                continue;
+            }
+            let mut quality = 1.;
+            for parameter in parts {
+                let parameter = parameter.trim();
+                if parameter.starts_with("q=") {
+                    quality = parameter[2..].parse::<f32>().unwrap_or(0.);
+                }
+            }
+            if name == "*" {
+                wildcard_quality = Some(quality);
+            } else {
+                qualities.push((name, quality));
+            }
+        }
+
+        let mut best = ContentEncoding::Identity;
+        let mut best_quality = 0.;
+        for encoding in &[ContentEncoding::Brotli, ContentEncoding::Gzip] {
+            let quality = qualities
+                .iter()
+                .find(|&&(ref name, _)| name == encoding.name())
+                .map(|&(_, quality)| quality)
+                .or(wildcard_quality)
+                .unwrap_or(0.);
+            if quality > best_quality {
+                best = *encoding;
+                best_quality = quality;
+            }
+        }
+        best
+    }
+
+    /// Negotiates the encoding for the response to 'req'. Without 'Accept-Encoding' header, the
+    /// body is sent as is.
+    pub fn for_request(req: &Request) -> Self {
+        let raw = match req.headers.get_raw("Accept-Encoding") {
+            Some(raw) => raw,
+            None => return ContentEncoding::Identity,
+        };
+        let values: Vec<&str> = raw.iter()
+            .filter_map(|value| str::from_utf8(value).ok())
+            .collect();
+        Self::negotiate(&values.join(","))
+    }
+
+    pub fn encode(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match *self {
+            ContentEncoding::Identity => Ok(data.to_vec()),
+            ContentEncoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::Default);
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            ContentEncoding::Brotli => {
+                let mut encoder = BrotliEncoder::new(Vec::new(), BROTLI_QUALITY);
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(ContentEncoding::Identity, ContentEncoding::negotiate(""));
+        assert_eq!(ContentEncoding::Identity, ContentEncoding::negotiate("deflate"));
+        assert_eq!(ContentEncoding::Gzip, ContentEncoding::negotiate("gzip, deflate"));
+        assert_eq!(ContentEncoding::Brotli, ContentEncoding::negotiate("gzip, deflate, br"));
+        assert_eq!(ContentEncoding::Gzip, ContentEncoding::negotiate("br;q=0.5, GZIP"));
+        assert_eq!(ContentEncoding::Identity, ContentEncoding::negotiate("gzip;q=0"));
+        assert_eq!(ContentEncoding::Brotli, ContentEncoding::negotiate("*"));
+        assert_eq!(ContentEncoding::Gzip, ContentEncoding::negotiate("br;q=0, *;q=0.1"));
+        assert_eq!(ContentEncoding::Identity, ContentEncoding::negotiate("gzip;q=abc"));
+    }
+}
@@ -0,0 +1,700 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+extern crate brotli2;
+extern crate byteorder;
+extern crate flate2;
+extern crate iron;
+extern crate json;
+extern crate openssl;
+extern crate point_viewer;
+extern crate router;
+extern crate time;
+extern crate urlencoded;
+extern crate ws;
+
+use access_token::guarded;
+use iron::headers::{CacheControl, CacheDirective, ETag, EntityTag, IfNoneMatch};
+use iron::mime::Mime;
+use iron::prelude::*;
+use point_viewer::errors::*;
+use point_viewer::math::Matrix4f;
+use point_viewer::octree;
+use router::Router;
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
+use urlencoded::{QueryMap, UrlEncodedQuery};
+
+mod access_token;
+mod content_encoding;
+mod metadata;
+mod metrics;
+mod node_cache;
+mod points;
+mod streaming;
+mod tls;
+
+pub use access_token::AccessToken;
+pub use content_encoding::ContentEncoding;
+pub use metrics::Metrics;
+pub use node_cache::{CacheStats, EncodedNode, NodeCache};
+pub use streaming::StreamingServer;
+pub use tls::TlsServer;
+
+/// An error that is sent to the client with 'status' and a JSON body of the form
+/// '{"error": message}'.
+#[derive(Debug)]
+pub struct HttpError {
+    pub status: iron::status::Status,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn bad_request<S: Into<String>>(message: S) -> Self {
+        HttpError {
+            status: iron::status::BadRequest,
+            message: message.into(),
+        }
+    }
+
+    pub fn unauthorized<S: Into<String>>(message: S) -> Self {
+        HttpError {
+            status: iron::status::Unauthorized,
+            message: message.into(),
+        }
+    }
+
+    pub fn not_found<S: Into<String>>(message: S) -> Self {
+        HttpError {
+            status: iron::status::NotFound,
+            message: message.into(),
+        }
+    }
+
+    pub fn internal<S: Into<String>>(message: S) -> Self {
+        HttpError {
+            status: iron::status::InternalServerError,
+            message: message.into(),
+        }
+    }
+}
+
+impl HttpError {
+    /// For errors reading node 'id': a missing node is not found, everything else means the data
+    /// on disk is broken.
+    fn from_node_error(id: &octree::NodeId, err: Error) -> Self {
+        match *err.kind() {
+            ErrorKind::NodeNotFound => HttpError::not_found(format!("Node {} does not exist.", id)),
+            _ => HttpError::internal(format!("Could not read node {}: {}", id, err)),
+        }
+    }
+}
+
+impl From<Error> for HttpError {
+    /// Invalid input is the fault of the client, a missing node is not found and everything else
+    /// is an error of the server.
+    fn from(err: Error) -> Self {
+        let status = match *err.kind() {
+            ErrorKind::InvalidInput(_) => iron::status::BadRequest,
+            ErrorKind::NodeNotFound => iron::status::NotFound,
+            _ => iron::status::InternalServerError,
+        };
+        HttpError {
+            status: status,
+            message: err.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "{}: {}", self.status, self.message)
+    }
+}
+
+impl error::Error for HttpError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl From<HttpError> for IronError {
+    fn from(err: HttpError) -> Self {
+        let mut body = json::JsonValue::new_object();
+        body["error"] = err.message.clone().into();
+        let content_type = "application/json".parse::<Mime>().unwrap();
+        let response = (content_type, err.status, body.dump());
+        IronError::new(err, response)
+    }
+}
+
+fn invalid_input<S: Into<String>>(message: S) -> Error {
+    ErrorKind::InvalidInput(message.into()).into()
+}
+
+/// The octrees served by one instance, by their id. The id is part of the URL of the node
+/// endpoints, e.g. '/octrees/<id>/visible_nodes'.
+#[derive(Default)]
+pub struct Octrees {
+    // Ordered by id, so that '/octrees' lists them in a stable order.
+    octrees: BTreeMap<String, Arc<RwLock<octree::Octree>>>,
+    // The first octree added, which is served by the endpoints without octree id.
+    default_id: Option<String>,
+}
+
+impl Octrees {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds 'octree' as 'id', which may only contain alphanumeric characters, '-', '_' and '.'.
+    pub fn insert(&mut self, id: &str, octree: octree::Octree) -> Result<()> {
+        let is_valid_char = |c: char| match c {
+            'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '_' | '.' => true,
+            _ => false,
+        };
+        if id.is_empty() || !id.chars().all(is_valid_char) {
+            return Err(invalid_input(format!("Invalid octree id '{}'.", id)));
+        }
+        if self.octrees.contains_key(id) {
+            return Err(invalid_input(format!("Octree id '{}' is used twice.", id)));
+        }
+        self.octrees
+            .insert(id.to_string(), Arc::new(RwLock::new(octree)));
+        if self.default_id.is_none() {
+            self.default_id = Some(id.to_string());
+        }
+        Ok(())
+    }
+
+    /// Loads the octree in 'directory' and adds it with the name of the directory as id. Relative
+    /// paths like '.' are resolved first.
+    pub fn insert_directory(&mut self, directory: &Path) -> Result<()> {
+        let octree = octree::Octree::new(directory)
+            .chain_err(|| format!("Could not load octree {}.", directory.display()))?;
+        let directory = directory
+            .canonicalize()
+            .chain_err(|| format!("Could not resolve {}.", directory.display()))?;
+        let id = match directory.file_name().and_then(|n| n.to_str()) {
+            Some(id) => id.to_string(),
+            None => {
+                return Err(
+                    invalid_input(format!("Cannot derive an id for {}.", directory.display())),
+                )
+            }
+        };
+        self.insert(&id, octree)
+    }
+
+    /// Adds all octrees in the subdirectories of 'root', i.e. the directories containing a
+    /// 'meta.pb'. Returns the number of octrees found.
+    pub fn insert_root_directory(&mut self, root: &Path) -> Result<usize> {
+        let mut directories = Vec::new();
+        for entry in root.read_dir()
+                .chain_err(|| format!("Could not read {}.", root.display()))? {
+            let path = entry?.path();
+            if path.join("meta.pb").is_file() {
+                directories.push(path);
+            }
+        }
+        directories.sort();
+        for directory in &directories {
+            self.insert_directory(directory)?;
+        }
+        Ok(directories.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.octrees.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.octrees.is_empty()
+    }
+
+    pub fn get(&self, id: &str) -> Option<Arc<RwLock<octree::Octree>>> {
+        self.octrees.get(id).cloned()
+    }
+
+    /// Returns the id and the octree for the 'octree_id' parameter of the route, or the default
+    /// octree for routes without it.
+    fn for_request(
+        &self,
+        req: &Request,
+    ) -> ::std::result::Result<(String, Arc<RwLock<octree::Octree>>), HttpError> {
+        self.find(
+            req.extensions
+                .get::<Router>()
+                .and_then(|params| params.find("octree_id")),
+        )
+    }
+
+    /// Returns the id and the octree for 'id', or the default octree if 'id' is None.
+    fn find(
+        &self,
+        id: Option<&str>,
+    ) -> ::std::result::Result<(String, Arc<RwLock<octree::Octree>>), HttpError> {
+        let id = match id {
+            Some(id) => id.to_string(),
+            None => {
+                match self.default_id {
+                    Some(ref id) => id.clone(),
+                    None => return Err(HttpError::not_found("No octrees are served.")),
+                }
+            }
+        };
+        match self.get(&id) {
+            Some(octree) => Ok((id, octree)),
+            None => Err(HttpError::not_found(format!("Octree '{}' does not exist.", id))),
+        }
+    }
+}
+
+/// Lists the served octrees with their extent.
+struct ListOctrees {
+    octrees: Arc<Octrees>,
+}
+
+impl iron::Handler for ListOctrees {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let mut reply = json::JsonValue::new_array();
+        for (id, octree) in &self.octrees.octrees {
+            let octree = octree.read().unwrap();
+            let mut entry = json::JsonValue::new_object();
+            entry["id"] = id.as_str().into();
+            entry["bounding_cube"] = metadata::bounding_cube_to_json(octree.bounding_cube());
+            entry["resolution"] = octree.resolution().into();
+            reply.push(entry).unwrap();
+        }
+        let content_type = "application/json".parse::<Mime>().unwrap();
+        Ok(Response::with((content_type, iron::status::Ok, reply.dump())))
+    }
+}
+
+/// Returns the first value of the query parameter 'name'.
+fn query_value<'a>(query: &'a QueryMap, name: &str) -> Result<&'a str> {
+    match query.get(name).and_then(|values| values.first()) {
+        Some(value) => Ok(value.as_str()),
+        None => Err(invalid_input(format!("Missing parameter '{}'.", name))),
+    }
+}
+
+fn parse_query_value<T: FromStr>(query: &QueryMap, name: &str) -> Result<T> {
+    let value = query_value(query, name)?;
+    value
+        .parse::<T>()
+        .map_err(|_| invalid_input(format!("Invalid value '{}' for '{}'.", value, name)))
+}
+
+/// Parses the query parameter 'name', which must be 'len' comma separated finite numbers.
+fn parse_numbers(query: &QueryMap, name: &str, len: usize) -> Result<Vec<f32>> {
+    let numbers = query_value(query, name)?
+        .split(',')
+        .map(|s| s.trim().parse::<f32>())
+        .collect::<::std::result::Result<Vec<f32>, _>>()
+        .unwrap_or_default();
+    if numbers.len() != len || numbers.iter().any(|v| !v.is_finite()) {
+        return Err(
+            invalid_input(format!("'{}' must be {} comma separated finite numbers.", name, len)),
+        );
+    }
+    Ok(numbers)
+}
+
+/// Parses a node id like 'r0173', which would panic in 'NodeId::from_str' if malformed.
+fn parse_node_id(name: &str) -> Result<octree::NodeId> {
+    // The index of a node has 3 bits per level and needs to fit into an usize.
+    let max_level = 21;
+    if !name.starts_with('r') || name.len() > max_level + 1 ||
+       !name[1..].chars().all(|c| c >= '0' && c <= '7') {
+        return Err(invalid_input(format!("Invalid node id '{}'.", name)));
+    }
+    Ok(octree::NodeId::from_str(name))
+}
+
+fn parse_level_of_detail(value: i64) -> Result<i32> {
+    if value < 1 || value > i32::max_value() as i64 {
+        return Err(invalid_input(format!("Invalid level of detail {}.", value)));
+    }
+    Ok(value as i32)
+}
+
+/// Returns the matrix with the 16 entries 'e' in column major order.
+fn matrix_from_column_major(e: &[f32]) -> Matrix4f {
+    Matrix4f::new(
+        e[0],
+        e[1],
+        e[2],
+        e[3],
+        e[4],
+        e[5],
+        e[6],
+        e[7],
+        e[8],
+        e[9],
+        e[10],
+        e[11],
+        e[12],
+        e[13],
+        e[14],
+        e[15],
+    )
+}
+
+struct VisibleNodes {
+    octrees: Arc<Octrees>,
+}
+
+fn visible_nodes(octree: &octree::Octree, query: &QueryMap) -> Result<Vec<octree::VisibleNode>> {
+    let width: i32 = parse_query_value(query, "width")?;
+    let height: i32 = parse_query_value(query, "height")?;
+    if width <= 0 || height <= 0 {
+        return Err(invalid_input("'width' and 'height' must be positive."));
+    }
+    let matrix = matrix_from_column_major(&parse_numbers(query, "matrix", 16)?);
+    let use_lod = match query_value(query, "use_lod")? {
+        "1" => octree::UseLod::Yes,
+        "0" => octree::UseLod::No,
+        other => {
+            return Err(invalid_input(format!("Invalid value '{}' for 'use_lod'.", other)))
+        }
+    };
+
+    Ok(octree.get_visible_nodes(&matrix, width, height, use_lod))
+}
+
+impl iron::Handler for VisibleNodes {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let (_, octree) = self.octrees.for_request(req)?;
+        let visible_nodes = {
+            let query = req.get_ref::<UrlEncodedQuery>()
+                .map_err(|_| HttpError::bad_request("Missing query parameters."))?;
+            visible_nodes(&octree.read().unwrap(), query)
+                .map_err(HttpError::from)?
+        };
+        let mut reply = String::from("[");
+        let visible_nodes_string = visible_nodes
+            .iter()
+            .map(|n| format!("[\"{}\", {}]", n.id, n.level_of_detail))
+            .collect::<Vec<_>>()
+            .join(",");
+        reply.push_str(&visible_nodes_string);
+        reply.push(']');
+        let content_type = "application/json".parse::<Mime>().unwrap();
+        Ok(Response::with((content_type, iron::status::Ok, reply)))
+    }
+}
+
+#[derive(Debug)]
+struct NodeToLoad {
+    id: octree::NodeId,
+    level_of_detail: i32,
+}
+
+/// Parses the body of a '/nodes_data' request, a JSON list of [node id, level of detail] pairs.
+fn parse_nodes_to_load(content: &str) -> Result<Vec<NodeToLoad>> {
+    let data = json::parse(content)
+        .map_err(|err| invalid_input(format!("Invalid JSON: {}", err)))?;
+    if !data.is_array() {
+        return Err(invalid_input("Expected a list of nodes."));
+    }
+    let mut nodes_to_load = Vec::new();
+    for e in data.members() {
+        let (id, level_of_detail) = match (e[0].as_str(), e[1].as_i64()) {
+            (Some(id), Some(level_of_detail)) if e.len() == 2 => (id, level_of_detail),
+            _ => {
+                return Err(
+                    invalid_input(format!("Expected [node id, level of detail], got {}.", e)),
+                )
+            }
+        };
+        nodes_to_load.push(
+            NodeToLoad {
+                id: parse_node_id(id)?,
+                level_of_detail: parse_level_of_detail(level_of_detail)?,
+            }
+        );
+    }
+    Ok(nodes_to_load)
+}
+
+struct NodesData {
+    octrees: Arc<Octrees>,
+    cache: Arc<NodeCache>,
+    metrics: Arc<Metrics>,
+}
+
+impl iron::Handler for NodesData {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let start = time::precise_time_ns();
+        let (octree_id, octree) = self.octrees.for_request(req)?;
+        let mut content = String::new();
+        req.body
+            .read_to_string(&mut content)
+            .map_err(|err| HttpError::bad_request(format!("Could not read body: {}", err)))?;
+        let nodes_to_load = parse_nodes_to_load(&content).map_err(HttpError::from)?;
+
+        let encoding = ContentEncoding::for_request(req);
+        let mut reply_blob = Vec::<u8>::new();
+        let mut num_nodes_fetched = 0;
+        let mut num_points = 0;
+        let octree = octree.read().unwrap();
+        for node in nodes_to_load {
+            let encoded_node = self.cache
+                .get(&octree_id, &octree, &node.id, node.level_of_detail)?;
+            reply_blob.extend_from_slice(&encoded_node.data);
+            num_nodes_fetched += 1;
+            num_points += encoded_node.num_points;
+        }
+
+        let body = encode_body(encoding, &reply_blob)?;
+        self.metrics
+            .record_nodes(num_nodes_fetched, num_points as u64);
+
+        let duration_ms = (time::precise_time_ns() - start) as f32 / 1000000.;
+        let cache_stats = self.cache.stats();
+        println!(
+            "Got {} nodes with {} points, sent {} of {} bytes as {} ({}ms, cache: {} hits, {} \
+             misses).",
+            num_nodes_fetched,
+            num_points,
+            body.len(),
+            reply_blob.len(),
+            encoding.name(),
+            duration_ms,
+            cache_stats.num_hits,
+            cache_stats.num_misses
+        );
+        Ok(binary_response(encoding, body))
+    }
+}
+
+fn encode_body(
+    encoding: ContentEncoding,
+    data: &[u8],
+) -> ::std::result::Result<Vec<u8>, HttpError> {
+    encoding
+        .encode(data)
+        .map_err(|err| HttpError::internal(format!("Could not compress reply: {}", err)))
+}
+
+/// An 'application/octet-stream' response with 'body', which is compressed with 'encoding'.
+fn binary_response(encoding: ContentEncoding, body: Vec<u8>) -> Response {
+    let content_type = "application/octet-stream".parse::<Mime>().unwrap();
+    let mut response = Response::with((content_type, iron::status::Ok, body));
+    if encoding != ContentEncoding::Identity {
+        response
+            .headers
+            .set_raw("Content-Encoding", vec![encoding.name().as_bytes().to_vec()]);
+    }
+    response
+}
+
+/// Identifies the data of 'node', which is node 'id' at 'level_of_detail', sent with 'encoding'.
+/// It changes whenever the octree is rebuilt.
+fn entity_tag(
+    octree: &octree::Octree,
+    node: &EncodedNode,
+    id: &octree::NodeId,
+    level_of_detail: i32,
+    encoding: ContentEncoding,
+) -> EntityTag {
+    EntityTag::strong(
+        format!(
+            "{:x}-{}-{}-{}-{:x}-{:x}-{}",
+            octree.build_id(),
+            id,
+            level_of_detail,
+            node.num_points,
+            node.position_checksum.unwrap_or(0),
+            node.color_checksum.unwrap_or(0),
+            encoding.name()
+        ),
+    )
+}
+
+/// Serves a single node in the same format as '/nodes_data', so that it can be cached by
+/// browsers and proxies.
+struct NodeById {
+    octrees: Arc<Octrees>,
+    cache: Arc<NodeCache>,
+    metrics: Arc<Metrics>,
+}
+
+impl iron::Handler for NodeById {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let (octree_id, octree) = self.octrees.for_request(req)?;
+        let id = {
+            let name = req.extensions
+                .get::<Router>()
+                .and_then(|params| params.find("node_id"))
+                .unwrap_or("");
+            parse_node_id(name).map_err(HttpError::from)?
+        };
+        let level_of_detail = match req.get_ref::<UrlEncodedQuery>() {
+            Ok(query) if query.contains_key("lod") => {
+                parse_query_value(query, "lod")
+                    .and_then(parse_level_of_detail)
+                    .map_err(HttpError::from)?
+            }
+            _ => 1,
+        };
+
+        let octree = octree.read().unwrap();
+        // The tag is computed from the cached node, so that it always matches the data sent.
+        let node = self.cache
+            .get(&octree_id, &octree, &id, level_of_detail)?;
+        let encoding = ContentEncoding::for_request(req);
+        let etag = entity_tag(&octree, &node, &id, level_of_detail, encoding);
+        let is_not_modified = match req.headers.get::<IfNoneMatch>() {
+            Some(&IfNoneMatch::Any) => true,
+            Some(&IfNoneMatch::Items(ref tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+            None => false,
+        };
+
+        let mut response = if is_not_modified {
+            Response::with(iron::status::NotModified)
+        } else {
+            let compressed = self.cache
+                .get_compressed(&octree_id, &octree, &id, level_of_detail, &node, encoding)?;
+            self.metrics.record_nodes(1, node.num_points as u64);
+            println!(
+                "Got node {} with {} points, sent {} of {} bytes as {}.",
+                id,
+                node.num_points,
+                compressed.data.len(),
+                node.data.len(),
+                encoding.name()
+            );
+            binary_response(encoding, compressed.data.clone())
+        };
+        response
+            .headers
+            .set_raw("Vary", vec![b"Accept-Encoding".to_vec()]);
+        response.headers.set(ETag(etag));
+        // Caches may keep the node, but need to check that the ETag is still valid, since the
+        // octree can be rebuilt under the same URL.
+        response
+            .headers
+            .set(CacheControl(vec![CacheDirective::Public, CacheDirective::NoCache]));
+        Ok(response)
+    }
+}
+
+/// Registers handlers that require the access token and are recorded in the metrics.
+struct Routes<'a> {
+    router: &'a mut Router,
+    access_token: Option<Arc<AccessToken>>,
+    metrics: Arc<Metrics>,
+}
+
+impl<'a> Routes<'a> {
+    fn wrap<H: iron::Handler>(
+        &self,
+        route: &str,
+        handler: H,
+    ) -> metrics::Instrumented<Box<iron::Handler>> {
+        metrics::Instrumented {
+            route: route.to_string(),
+            handler: guarded(&self.access_token, handler),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    fn get<H: iron::Handler>(&mut self, route: &str, handler: H) {
+        let handler = self.wrap(route, handler);
+        self.router.get(route, handler);
+    }
+
+    fn post<H: iron::Handler>(&mut self, route: &str, handler: H) {
+        let handler = self.wrap(route, handler);
+        self.router.post(route, handler);
+    }
+}
+
+/// Adds the routes that serve the data of 'octrees' to 'router'. The node endpoints are available
+/// per octree under '/octrees/<id>/' and for the default octree without prefix. Node data is
+/// cached in 'cache'. If 'access_token' is given, all routes require it. Requests are recorded in
+/// 'metrics', which are served under '/metrics'.
+pub fn add_routes(
+    router: &mut Router,
+    octrees: Arc<Octrees>,
+    cache: Arc<NodeCache>,
+    access_token: Option<Arc<AccessToken>>,
+    metrics: Arc<Metrics>,
+) {
+    router.get(
+        "/metrics",
+        guarded(
+            &access_token,
+            metrics::MetricsHandler {
+                metrics: metrics.clone(),
+                cache: cache.clone(),
+            },
+        ),
+    );
+    let mut routes = Routes {
+        router: router,
+        access_token: access_token,
+        metrics: metrics.clone(),
+    };
+    let meta = metadata::Meta::new(octrees.clone());
+    routes.get("/octrees", ListOctrees { octrees: octrees.clone() });
+    for prefix in &["/octrees/:octree_id", ""] {
+        routes.get(&format!("{}/meta", prefix), meta.clone());
+        routes.get(
+            &format!("{}/hierarchy", prefix),
+            metadata::Hierarchy { octrees: octrees.clone() },
+        );
+        routes.get(
+            &format!("{}/visible_nodes", prefix),
+            VisibleNodes { octrees: octrees.clone() },
+        );
+        routes.post(
+            &format!("{}/nodes_data", prefix),
+            NodesData {
+                octrees: octrees.clone(),
+                cache: cache.clone(),
+                metrics: metrics.clone(),
+            },
+        );
+        routes.get(
+            &format!("{}/nodes/:node_id", prefix),
+            NodeById {
+                octrees: octrees.clone(),
+                cache: cache.clone(),
+                metrics: metrics.clone(),
+            },
+        );
+        for &(name, kind) in &[
+            ("box", points::Kind::Box),
+            ("sphere", points::Kind::Sphere),
+            ("pick", points::Kind::Pick),
+        ] {
+            routes.get(
+                &format!("{}/points/{}", prefix, name),
+                points::Points {
+                    octrees: octrees.clone(),
+                    kind: kind,
+                    metrics: metrics.clone(),
+                },
+            );
+        }
+    }
+}
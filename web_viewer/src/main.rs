@@ -12,26 +12,20 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-extern crate byteorder;
-extern crate point_viewer;
-extern crate router;
-extern crate time;
-extern crate urlencoded;
 #[macro_use]
 extern crate clap;
 extern crate iron;
-extern crate json;
+extern crate point_viewer;
+extern crate router;
+extern crate web_viewer;
 
-use byteorder::{LittleEndian, WriteBytesExt};
 use iron::mime::Mime;
 use iron::prelude::*;
-use point_viewer::math::{CuboidLike, Matrix4f};
-use point_viewer::octree;
 use router::Router;
-use std::io::Read;
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
-use urlencoded::UrlEncodedQuery;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use web_viewer::{AccessToken, Metrics, NodeCache, Octrees, StreamingServer, TlsServer};
 
 const INDEX_HTML: &'static str = include_str!("../client/index.html");
 const APP_BUNDLE: &'static str = include_str!("../target/app_bundle.js");
@@ -53,204 +47,147 @@ fn app_bundle_source_map(_: &mut Request) -> IronResult<Response> {
     Ok(Response::with((content_type, iron::status::Ok, APP_BUNDLE_MAP)))
 }
 
-struct VisibleNodes {
-    octree: Arc<RwLock<octree::Octree>>,
-}
-
-impl iron::Handler for VisibleNodes {
-    fn handle(&self, req: &mut Request) -> IronResult<Response> {
-        // TODO(hrapp): This should not crash on error, but return a valid http response.
-        let query = req.get_ref::<UrlEncodedQuery>().unwrap();
-        let width: i32 = query.get("width").unwrap()[0].parse().unwrap();
-        let height: i32 = query.get("height").unwrap()[0].parse().unwrap();
-        let matrix = {
-            // Entries are column major.
-            let e: Vec<f32> = query.get("matrix").unwrap()[0]
-                .split(',')
-                .map(|s| s.parse::<f32>().unwrap())
-                .collect();
-            Matrix4f::new(
-                e[0],
-                e[1],
-                e[2],
-                e[3],
-                e[4],
-                e[5],
-                e[6],
-                e[7],
-                e[8],
-                e[9],
-                e[10],
-                e[11],
-                e[12],
-                e[13],
-                e[14],
-                e[15],
-            )
-        };
-        let use_lod = {
-            let lod: i32 = query.get("use_lod").unwrap()[0].parse().unwrap();
-            if lod == 1 {
-                octree::UseLod::Yes
-            } else {
-                octree::UseLod::No
-            }
-        };
-
-        let visible_nodes = {
-            let octree = self.octree.read().unwrap();
-            octree.get_visible_nodes(&matrix, width, height, use_lod)
-        };
-        let mut reply = String::from("[");
-        let visible_nodes_string = visible_nodes
-            .iter()
-            .map(|n| format!("[\"{}\", {}]", n.id, n.level_of_detail))
-            .collect::<Vec<_>>()
-            .join(",");
-        reply.push_str(&visible_nodes_string);
-        reply.push(']');
-        let content_type = "application/json".parse::<Mime>().unwrap();
-        Ok(Response::with((content_type, iron::status::Ok, reply)))
-    }
-}
-
-// Javascript requires its arrays to be padded to 4 bytes.
-fn pad(input: &mut Vec<u8>) {
-    let pad = input.len() % 4;
-    if pad == 0 {
-        return;
-    }
-    for _ in 0..(4 - pad) {
-        input.push(0);
-    }
-}
-
-#[derive(Debug)]
-struct NodeToLoad {
-    id: octree::NodeId,
-    level_of_detail: i32,
-}
-
-struct NodesData {
-    octree: Arc<RwLock<octree::Octree>>,
-}
-
-impl iron::Handler for NodesData {
-    fn handle(&self, req: &mut Request) -> IronResult<Response> {
-        let start = time::precise_time_ns();
-        let mut content = String::new();
-        // TODO(hrapp): This should not crash on error, but return a valid http response.
-        req.body.read_to_string(&mut content).unwrap();
-        let data = json::parse(&content).unwrap();
-        let nodes_to_load = data.members()
-            .map(
-                |e| {
-                    NodeToLoad {
-                        id: octree::NodeId::from_str(e[0].as_str().unwrap()),
-                        level_of_detail: e[1].as_i32().unwrap(),
-                    }
-                }
-            );
-
-        // So this is godawful: We need to get data to the GPU without JavaScript herp-derping with
-        // it - because that will stall interaction. The straight forward approach would be to ship
-        // json with base64 encoded values - unfortunately base64 decoding in JavaScript yields a
-        // string which cannot be used as a buffer. So we would need to manually convert this into
-        // an Array with is very slow.
-        // The alternative is to binary encode the whole request and parse it on the client side,
-        // which requires careful constructing on the server and parsing on the client.
-        let mut reply_blob = Vec::<u8>::new();
-
-        let mut num_nodes_fetched = 0;
-        let mut num_points = 0;
-        let octree = self.octree.read().unwrap();
-        for node in nodes_to_load {
-            let mut node_data = octree
-                .get_node_data(&node.id, node.level_of_detail)
-                .unwrap();
-
-            // Write the bounding box information.
-            let min = node_data.meta.bounding_cube.min();
-            reply_blob.write_f32::<LittleEndian>(min.x).unwrap();
-            reply_blob.write_f32::<LittleEndian>(min.y).unwrap();
-            reply_blob.write_f32::<LittleEndian>(min.z).unwrap();
-            reply_blob
-                .write_f32::<LittleEndian>(node_data.meta.bounding_cube.edge_length())
-                .unwrap();
-
-            // Number of points.
-            reply_blob
-                .write_u32::<LittleEndian>(node_data.meta.num_points as u32)
-                .unwrap();
-
-            // Position encoding.
-            let bytes_per_coordinate = node_data.meta.position_encoding.bytes_per_coordinate();
-            reply_blob.write_u8(bytes_per_coordinate as u8).unwrap();
-            assert!(
-                bytes_per_coordinate * node_data.meta.num_points as usize * 3 ==
-                node_data.position.len()
-            );
-            assert!(node_data.meta.num_points as usize * 3 == node_data.color.len());
-            pad(&mut reply_blob);
-
-
-            reply_blob.append(&mut node_data.position);
-            pad(&mut reply_blob);
-
-            reply_blob.append(&mut node_data.color);
-            pad(&mut reply_blob);
-
-            num_nodes_fetched += 1;
-            num_points += node_data.meta.num_points;
-        }
-
-        let duration_ms = (time::precise_time_ns() - start) as f32 / 1000000.;
-        println!(
-            "Got {} nodes with {} points ({}ms).",
-            num_nodes_fetched,
-            num_points,
-            duration_ms
-        );
-
-        let content_type = "application/octet-stream".parse::<Mime>().unwrap();
-        Ok(Response::with((content_type, iron::status::Ok, reply_blob)))
-    }
-}
-
 fn main() {
     let matches = clap::App::new("web_viewer")
         .args(
             &[
+                clap::Arg::with_name("bind")
+                    .help("Address to listen on, e.g. 127.0.0.1 to only accept local connections.")
+                    .long("bind")
+                    .default_value("0.0.0.0"),
                 clap::Arg::with_name("port")
                     .help("Port to listen on for connections.")
                     .long("port")
                     .takes_value(true),
+                clap::Arg::with_name("websocket_port")
+                    .help("Port to listen on for WebSocket connections that stream nodes.")
+                    .long("websocket_port")
+                    .default_value("5434"),
+                clap::Arg::with_name("tls_certificate")
+                    .help("PEM file with the certificate chain. Serves HTTPS and WSS if given.")
+                    .long("tls_certificate")
+                    .takes_value(true)
+                    .requires("tls_key"),
+                clap::Arg::with_name("tls_key")
+                    .help("PEM file with the private key of the certificate.")
+                    .long("tls_key")
+                    .takes_value(true)
+                    .requires("tls_certificate"),
+                clap::Arg::with_name("access_token_file")
+                    .help(
+                        "File with a token that clients need to send to access the data, either \
+                           as 'Authorization: Bearer <token>' header or as 'access_token' query \
+                           parameter."
+                    )
+                    .long("access_token_file")
+                    .takes_value(true),
+                clap::Arg::with_name("cache_size_mb")
+                    .help("Memory budget for caching node data in MiB.")
+                    .long("cache_size_mb")
+                    .default_value("256"),
+                clap::Arg::with_name("octrees_root")
+                    .help(
+                        "Serve all octrees in the subdirectories of this directory, in addition \
+                           to the ones given as arguments."
+                    )
+                    .long("octrees_root")
+                    .takes_value(true),
                 clap::Arg::with_name("octree_directory")
-                    .help("Input directory of the octree directory to serve.")
+                    .help(
+                        "Octree directories to serve. The name of the directory is the id of the \
+                           octree, the first one is also served without id."
+                    )
                     .index(1)
-                    .required(true),
+                    .multiple(true)
+                    .required_unless("octrees_root"),
             ]
         )
         .get_matches();
 
+    let bind = matches.value_of("bind").unwrap();
     let port = value_t!(matches, "port", u16).unwrap_or(5433);
-    let octree_directory = PathBuf::from(matches.value_of("octree_directory").unwrap());
+    let websocket_port = value_t!(matches, "websocket_port", u16).unwrap_or_else(|e| e.exit());
+    let cache_size_mb = value_t!(matches, "cache_size_mb", usize).unwrap_or_else(|e| e.exit());
+
+    let mut octrees = Octrees::new();
+    if let Some(directories) = matches.values_of("octree_directory") {
+        for directory in directories {
+            if let Err(err) = octrees.insert_directory(Path::new(directory)) {
+                panic!("Could not load octree: {}", err);
+            }
+        }
+    }
+    if let Some(root) = matches.value_of("octrees_root") {
+        if let Err(err) = octrees.insert_root_directory(Path::new(root)) {
+            panic!("Could not load octrees: {}", err);
+        }
+    }
+    if octrees.is_empty() {
+        panic!("No octrees found.");
+    }
 
-    let otree = {
-        let otree = match octree::Octree::new(octree_directory) {
-            Ok(otree) => otree,
-            Err(err) => panic!("Could not load octree: {}", err),
-        };
-        Arc::new(RwLock::new(otree))
+    let access_token = matches
+        .value_of("access_token_file")
+        .map(
+            |path| match AccessToken::from_file(Path::new(path)) {
+                Ok(access_token) => Arc::new(access_token),
+                Err(err) => panic!("{}", err),
+            },
+        );
+    let tls_files = match (matches.value_of("tls_certificate"), matches.value_of("tls_key")) {
+        (Some(certificate), Some(key)) => Some((Path::new(certificate), Path::new(key))),
+        _ => None,
     };
 
+    let octrees = Arc::new(octrees);
+    let cache = Arc::new(NodeCache::new(cache_size_mb * 1024 * 1024));
+    let metrics = Arc::new(Metrics::new());
     let mut router = Router::new();
     router.get("/", index);
     router.get("/app_bundle.js", app_bundle);
     router.get("/app_bundle.js.map", app_bundle_source_map);
-    router.get("/visible_nodes", VisibleNodes { octree: otree.clone() });
-    router.post("/nodes_data", NodesData { octree: otree.clone() });
-
-    println!("Listening on port {}.", port);
-    Iron::new(router).http(("0.0.0.0", port)).unwrap();
+    // The static assets contain no data, so they do not need the access token.
+    web_viewer::add_routes(
+        &mut router,
+        octrees.clone(),
+        cache.clone(),
+        access_token.clone(),
+        metrics.clone(),
+    );
+
+    // With TLS, both servers only listen locally and get their connections through TLS servers.
+    let (local_bind, local_port, local_websocket_port) = match tls_files {
+        Some(_) => ("127.0.0.1", 0, 0),
+        None => (bind, port, websocket_port),
+    };
+    let streaming_server = StreamingServer::start(
+        (local_bind, local_websocket_port),
+        octrees,
+        cache,
+        access_token,
+        metrics,
+    )
+            .unwrap_or_else(|err| panic!("Could not listen for WebSocket connections: {}", err));
+    let listening = Iron::new(router)
+        .http((local_bind, local_port))
+        .unwrap_or_else(|err| panic!("Could not listen for HTTP connections: {}", err));
+    let _tls_servers = tls_files.map(
+        |(certificate, key)| {
+            let start = |port: u16, backend: SocketAddr| {
+                TlsServer::start((bind, port), backend, certificate, key)
+                    .unwrap_or_else(|err| panic!("Could not listen for TLS connections: {}", err))
+            };
+            (start(port, listening.socket), start(websocket_port, streaming_server.address()))
+        },
+    );
+
+    println!(
+        "Listening on {}:{}, streaming on port {}{}.",
+        bind,
+        port,
+        websocket_port,
+        if tls_files.is_some() { " with TLS" } else { "" }
+    );
+    // Waits for the HTTP server, which runs forever, while the other servers are still alive.
+    drop(listening);
 }
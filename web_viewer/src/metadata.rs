@@ -0,0 +1,174 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Handlers that describe the served octrees, so that clients can frame the scene and traverse
+//! the nodes on their own.
+
+use {HttpError, Octrees, invalid_input, parse_node_id, parse_query_value, query_value};
+use iron::{self, IronResult, Plugin, Request, Response};
+use iron::mime::Mime;
+use json;
+use point_viewer::errors;
+use point_viewer::math::{CuboidLike, Cube};
+use point_viewer::octree::{ChildIndex, NodeId, NodeMeta, Octree};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use urlencoded::{QueryMap, UrlEncodedQuery};
+
+// Every level multiplies the number of nodes in the reply by up to 8.
+const MAX_HIERARCHY_DEPTH: usize = 8;
+
+pub fn bounding_cube_to_json(bounding_cube: &Cube) -> json::JsonValue {
+    let min = bounding_cube.min();
+    let mut reply = json::JsonValue::new_object();
+    reply["min"] = vec![min.x, min.y, min.z].into();
+    reply["edge_length"] = bounding_cube.edge_length().into();
+    reply
+}
+
+fn json_response(reply: json::JsonValue) -> Response {
+    let content_type = "application/json".parse::<Mime>().unwrap();
+    Response::with((content_type, iron::status::Ok, reply.dump()))
+}
+
+fn read_node_meta(octree: &Octree, id: &NodeId) -> Result<NodeMeta, HttpError> {
+    NodeMeta::from_disk(octree.directory(), id).map_err(|err| HttpError::from_node_error(id, err))
+}
+
+#[derive(Clone,Copy)]
+struct Totals {
+    num_points: u64,
+    num_nodes: u64,
+}
+
+/// Serves the meta data of an octree together with the total number of points.
+#[derive(Clone)]
+pub struct Meta {
+    octrees: Arc<Octrees>,
+    // Counting the points requires reading the meta data of every node, so it is only done once
+    // per octree. Shared by all routes.
+    totals: Arc<Mutex<HashMap<String, Totals>>>,
+}
+
+impl Meta {
+    pub fn new(octrees: Arc<Octrees>) -> Self {
+        Meta {
+            octrees: octrees,
+            totals: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn totals(&self, octree_id: &str, octree: &Octree) -> Result<Totals, HttpError> {
+        if let Some(totals) = self.totals.lock().unwrap().get(octree_id) {
+            return Ok(*totals);
+        }
+        let mut totals = Totals {
+            num_points: 0,
+            num_nodes: 0,
+        };
+        for id in octree.node_ids() {
+            totals.num_points += read_node_meta(octree, &id)?.num_points as u64;
+            totals.num_nodes += 1;
+        }
+        self.totals
+            .lock()
+            .unwrap()
+            .insert(octree_id.to_string(), totals);
+        Ok(totals)
+    }
+}
+
+impl iron::Handler for Meta {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let (octree_id, octree) = self.octrees.for_request(req)?;
+        let octree = octree.read().unwrap();
+        let totals = self.totals(&octree_id, &octree)?;
+
+        let mut reply = json::JsonValue::new_object();
+        reply["id"] = octree_id.as_str().into();
+        reply["version"] = octree.version().into();
+        reply["bounding_cube"] = bounding_cube_to_json(octree.bounding_cube());
+        reply["resolution"] = octree.resolution().into();
+        // JSON numbers cannot represent all 64 bit integers.
+        reply["build_id"] = format!("{:x}", octree.build_id()).into();
+        reply["num_points"] = totals.num_points.into();
+        reply["num_nodes"] = totals.num_nodes.into();
+        Ok(json_response(reply))
+    }
+}
+
+/// Serves the ids and number of points of the nodes below 'root', up to 'depth' levels deeper.
+pub struct Hierarchy {
+    pub octrees: Arc<Octrees>,
+}
+
+fn parse_hierarchy_query(query: &QueryMap) -> errors::Result<(NodeId, usize)> {
+    let root = match query.get("root") {
+        Some(_) => parse_node_id(query_value(query, "root")?)?,
+        None => NodeId::from_str("r"),
+    };
+    let depth = match query.get("depth") {
+        Some(_) => parse_query_value(query, "depth")?,
+        None => 1,
+    };
+    if depth > MAX_HIERARCHY_DEPTH {
+        return Err(
+            invalid_input(format!("'depth' must not be larger than {}.", MAX_HIERARCHY_DEPTH)),
+        );
+    }
+    Ok((root, depth))
+}
+
+impl iron::Handler for Hierarchy {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let (_, octree) = self.octrees.for_request(req)?;
+        let query = req.get_ref::<UrlEncodedQuery>()
+            .ok()
+            .cloned()
+            .unwrap_or_default();
+        let (root, depth) = parse_hierarchy_query(&query).map_err(HttpError::from)?;
+
+        let octree = octree.read().unwrap();
+        if !octree.has_node(&root) {
+            return Err(HttpError::not_found(format!("Node {} does not exist.", root)).into());
+        }
+
+        // Breadth first, so parents always come before their children.
+        let mut nodes = json::JsonValue::new_array();
+        let mut open = VecDeque::new();
+        open.push_back(root);
+        while let Some(id) = open.pop_front() {
+            let children: Vec<NodeId> = (0..8)
+                .map(|index| id.get_child_id(ChildIndex::from_u8(index)))
+                .filter(|child| octree.has_node(child))
+                .collect();
+            let mut node = json::JsonValue::new_object();
+            node["id"] = id.to_string().into();
+            // The counts are known from the file sizes, reading every node would be too slow
+            // for deep hierarchies.
+            node["num_points"] = octree.num_points(&id).unwrap_or(0).into();
+            // Lets clients know where to continue with another request.
+            node["has_children"] = (!children.is_empty()).into();
+            nodes.push(node).unwrap();
+            if id.level() < root.level() + depth {
+                open.extend(children);
+            }
+        }
+
+        let mut reply = json::JsonValue::new_object();
+        reply["root"] = root.to_string().into();
+        reply["nodes"] = nodes;
+        Ok(json_response(reply))
+    }
+}
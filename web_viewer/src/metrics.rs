@@ -0,0 +1,340 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Counts what the server does and exposes it in the text format of Prometheus, see
+//! https://prometheus.io/docs/instrumenting/exposition_formats/.
+
+use NodeCache;
+use iron::{self, IronResult, Request, Response};
+use iron::headers::ContentLength;
+use iron::mime::Mime;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use time;
+
+// Upper bounds of the latency histogram buckets in seconds, the defaults of the Prometheus client
+// libraries.
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 5., 10.];
+
+// Errors while streaming nodes are counted under this route.
+pub const STREAMING_ROUTE: &'static str = "websocket";
+
+#[derive(Default)]
+struct RouteStats {
+    num_requests_by_status: BTreeMap<u16, u64>,
+    num_errors: u64,
+    // The number of requests that took at most the corresponding entry of 'LATENCY_BUCKETS'.
+    latency_buckets: [u64; 11],
+    latency_count: u64,
+    latency_sum_seconds: f64,
+}
+
+#[derive(Default)]
+struct Counters {
+    routes: BTreeMap<String, RouteStats>,
+    num_nodes: u64,
+    num_points: u64,
+    num_bytes: u64,
+}
+
+/// Collects the metrics of all routes and of the streaming server.
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a request to 'route', which is the pattern it was routed by, so that the number of
+    /// label values stays small.
+    pub fn record_request(&self, route: &str, status: u16, seconds: f64, num_bytes: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.num_bytes += num_bytes;
+        let stats = counters
+            .routes
+            .entry(route.to_string())
+            .or_insert_with(RouteStats::default);
+        *stats.num_requests_by_status.entry(status).or_insert(0) += 1;
+        if status >= 400 {
+            stats.num_errors += 1;
+        }
+        for (bucket, upper_bound) in stats.latency_buckets.iter_mut().zip(&LATENCY_BUCKETS) {
+            if seconds <= *upper_bound {
+                *bucket += 1;
+            }
+        }
+        stats.latency_count += 1;
+        stats.latency_sum_seconds += seconds;
+    }
+
+    /// Records an error that is not a reply to an HTTP request.
+    pub fn record_error(&self, route: &str) {
+        let mut counters = self.counters.lock().unwrap();
+        counters
+            .routes
+            .entry(route.to_string())
+            .or_insert_with(RouteStats::default)
+            .num_errors += 1;
+    }
+
+    /// Records nodes and their points that were sent to a client.
+    pub fn record_nodes(&self, num_nodes: u64, num_points: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.num_nodes += num_nodes;
+        counters.num_points += num_points;
+    }
+
+    /// Records points sent in reply to a point query.
+    pub fn record_points(&self, num_points: u64) {
+        self.counters.lock().unwrap().num_points += num_points;
+    }
+
+    /// Records bytes that were sent outside of HTTP replies, i.e. over the WebSocket.
+    pub fn record_bytes(&self, num_bytes: u64) {
+        self.counters.lock().unwrap().num_bytes += num_bytes;
+    }
+
+    /// Returns all metrics, including the ones of 'cache', in the Prometheus text format.
+    pub fn to_prometheus(&self, cache: &NodeCache) -> String {
+        let mut text = String::new();
+        let counters = self.counters.lock().unwrap();
+
+        write_header(
+            &mut text,
+            "web_viewer_requests_total",
+            "counter",
+            "Number of HTTP requests by route and status code.",
+        );
+        for (route, stats) in &counters.routes {
+            for (status, num_requests) in &stats.num_requests_by_status {
+                writeln!(
+                    text,
+                    "web_viewer_requests_total{{route=\"{}\",status=\"{}\"}} {}",
+                    escape(route),
+                    status,
+                    num_requests
+                )
+                        .unwrap();
+            }
+        }
+
+        write_header(
+            &mut text,
+            "web_viewer_errors_total",
+            "counter",
+            "Number of requests answered with an error status and of errors while streaming.",
+        );
+        for (route, stats) in &counters.routes {
+            writeln!(
+                text,
+                "web_viewer_errors_total{{route=\"{}\"}} {}",
+                escape(route),
+                stats.num_errors
+            )
+                    .unwrap();
+        }
+
+        write_header(
+            &mut text,
+            "web_viewer_request_duration_seconds",
+            "histogram",
+            "Time to handle HTTP requests by route.",
+        );
+        for (route, stats) in &counters.routes {
+            if stats.latency_count == 0 {
+                continue;
+            }
+            let route = escape(route);
+            for (num_requests, upper_bound) in stats.latency_buckets.iter().zip(&LATENCY_BUCKETS) {
+                writeln!(
+                    text,
+                    "web_viewer_request_duration_seconds_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    route,
+                    upper_bound,
+                    num_requests
+                )
+                        .unwrap();
+            }
+            writeln!(
+                text,
+                "web_viewer_request_duration_seconds_bucket{{route=\"{}\",le=\"+Inf\"}} {}",
+                route,
+                stats.latency_count
+            )
+                    .unwrap();
+            writeln!(
+                text,
+                "web_viewer_request_duration_seconds_sum{{route=\"{}\"}} {}",
+                route,
+                stats.latency_sum_seconds
+            )
+                    .unwrap();
+            writeln!(
+                text,
+                "web_viewer_request_duration_seconds_count{{route=\"{}\"}} {}",
+                route,
+                stats.latency_count
+            )
+                    .unwrap();
+        }
+
+        let values = [
+            (
+                "web_viewer_nodes_served_total",
+                "counter",
+                "Number of nodes sent.",
+                counters.num_nodes,
+            ),
+            (
+                "web_viewer_points_served_total",
+                "counter",
+                "Number of points sent in nodes and in reply to point queries.",
+                counters.num_points,
+            ),
+            (
+                "web_viewer_sent_bytes_total",
+                "counter",
+                "Number of bytes sent in HTTP bodies and WebSocket messages.",
+                counters.num_bytes,
+            ),
+        ];
+        let cache_stats = cache.stats();
+        let cache_values = [
+            (
+                "web_viewer_cache_hits_total",
+                "counter",
+                "Number of nodes found in the cache.",
+                cache_stats.num_hits,
+            ),
+            (
+                "web_viewer_cache_misses_total",
+                "counter",
+                "Number of nodes read from disk.",
+                cache_stats.num_misses,
+            ),
+            (
+                "web_viewer_cache_bytes",
+                "gauge",
+                "Size of the cached nodes in bytes.",
+                cache_stats.num_bytes as u64,
+            ),
+            (
+                "web_viewer_cache_entries",
+                "gauge",
+                "Number of cached nodes.",
+                cache_stats.num_entries as u64,
+            ),
+        ];
+        for &(name, kind, help, value) in values.iter().chain(&cache_values) {
+            write_header(&mut text, name, kind, help);
+            writeln!(text, "{} {}", name, value).unwrap();
+        }
+        text
+    }
+}
+
+fn write_header(text: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(text, "# HELP {} {}", name, help).unwrap();
+    writeln!(text, "# TYPE {} {}", name, kind).unwrap();
+}
+
+fn escape(label_value: &str) -> String {
+    label_value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves the metrics in the Prometheus text format.
+pub struct MetricsHandler {
+    pub metrics: Arc<Metrics>,
+    pub cache: Arc<NodeCache>,
+}
+
+impl iron::Handler for MetricsHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let content_type = "text/plain; version=0.0.4".parse::<Mime>().unwrap();
+        let text = self.metrics.to_prometheus(&self.cache);
+        Ok(Response::with((content_type, iron::status::Ok, text)))
+    }
+}
+
+/// Serves requests with 'handler' and records them in 'metrics' under 'route'.
+pub struct Instrumented<H> {
+    pub route: String,
+    pub handler: H,
+    pub metrics: Arc<Metrics>,
+}
+
+impl<H: iron::Handler> iron::Handler for Instrumented<H> {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let start = time::precise_time_ns();
+        let result = self.handler.handle(req);
+        let seconds = (time::precise_time_ns() - start) as f64 / 1e9;
+        let (status, num_bytes) = {
+            let response = match result {
+                Ok(ref response) => response,
+                Err(ref err) => &err.response,
+            };
+            let num_bytes = response
+                .headers
+                .get::<ContentLength>()
+                .map_or(0, |&ContentLength(num_bytes)| num_bytes);
+            // Iron sends responses without status as 404.
+            (response.status.unwrap_or(iron::status::NotFound).to_u16(), num_bytes)
+        };
+        self.metrics
+            .record_request(&self.route, status, seconds, num_bytes);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_prometheus() {
+        let metrics = Metrics::new();
+        metrics.record_request("/meta", 200, 0.02, 100);
+        metrics.record_request("/meta", 404, 0.2, 10);
+        metrics.record_nodes(2, 30);
+        let text = metrics.to_prometheus(&NodeCache::new(1024));
+        for line in &[
+            "web_viewer_requests_total{route=\"/meta\",status=\"200\"} 1",
+            "web_viewer_requests_total{route=\"/meta\",status=\"404\"} 1",
+            "web_viewer_errors_total{route=\"/meta\"} 1",
+            "web_viewer_request_duration_seconds_bucket{route=\"/meta\",le=\"0.01\"} 0",
+            "web_viewer_request_duration_seconds_bucket{route=\"/meta\",le=\"0.025\"} 1",
+            "web_viewer_request_duration_seconds_bucket{route=\"/meta\",le=\"0.25\"} 2",
+            "web_viewer_request_duration_seconds_bucket{route=\"/meta\",le=\"+Inf\"} 2",
+            "web_viewer_request_duration_seconds_count{route=\"/meta\"} 2",
+            "web_viewer_nodes_served_total 2",
+            "web_viewer_points_served_total 30",
+            "web_viewer_sent_bytes_total 110",
+            "web_viewer_cache_hits_total 0",
+        ] {
+            assert!(text.lines().any(|l| l == *line), "'{}' not in:\n{}", line, text);
+        }
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!("a\\\"b\\\\c\\n", escape("a\"b\\c\n"));
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use HttpError;
+use content_encoding::ContentEncoding;
+use byteorder::{LittleEndian, WriteBytesExt};
+use point_viewer::cache::LruCache;
+use point_viewer::math::CuboidLike;
+use point_viewer::octree;
+use std::sync::{Arc, Mutex};
+
+// Javascript requires its arrays to be padded to 4 bytes.
+pub fn pad(input: &mut Vec<u8>) {
+    let pad = input.len() % 4;
+    if pad == 0 {
+        return;
+    }
+    for _ in 0..(4 - pad) {
+        input.push(0);
+    }
+}
+
+/// A node in the binary format the client expects. The reply to a '/nodes_data' request is the
+/// concatenation of the requested nodes.
+pub struct EncodedNode {
+    pub num_points: i64,
+    // From the node meta read together with the points, so they identify 'data'.
+    pub position_checksum: Option<u64>,
+    pub color_checksum: Option<u64>,
+    pub data: Vec<u8>,
+}
+
+/// Reads the node 'id' at 'level_of_detail' from 'octree' and encodes it.
+fn encode_node(
+    octree: &octree::Octree,
+    id: &octree::NodeId,
+    level_of_detail: i32,
+) -> ::std::result::Result<EncodedNode, HttpError> {
+    let mut node_data = octree
+        .get_node_data(id, level_of_detail)
+        .map_err(|err| HttpError::from_node_error(id, err))?;
+
+    // So this is godawful: We need to get data to the GPU without JavaScript herp-derping with
+    // it - because that will stall interaction. The straight forward approach would be to ship
+    // json with base64 encoded values - unfortunately base64 decoding in JavaScript yields a
+    // string which cannot be used as a buffer. So we would need to manually convert this into
+    // an Array with is very slow.
+    // The alternative is to binary encode the whole request and parse it on the client side,
+    // which requires careful constructing on the server and parsing on the client.
+    // Every node is padded to 4 bytes, so nodes can be encoded independently and concatenated.
+    let mut blob = Vec::<u8>::new();
+
+    // Write the bounding box information.
+    let min = node_data.meta.bounding_cube.min();
+    blob.write_f32::<LittleEndian>(min.x).unwrap();
+    blob.write_f32::<LittleEndian>(min.y).unwrap();
+    blob.write_f32::<LittleEndian>(min.z).unwrap();
+    blob.write_f32::<LittleEndian>(node_data.meta.bounding_cube.edge_length())
+        .unwrap();
+
+    // Number of points.
+    blob.write_u32::<LittleEndian>(node_data.meta.num_points as u32)
+        .unwrap();
+
+    // Position encoding.
+    let bytes_per_coordinate = node_data.meta.position_encoding.bytes_per_coordinate();
+    blob.write_u8(bytes_per_coordinate as u8).unwrap();
+    if bytes_per_coordinate * node_data.meta.num_points as usize * 3 != node_data.position.len() ||
+       node_data.meta.num_points as usize * 3 != node_data.color.len() {
+        return Err(HttpError::internal(format!("Node {} has inconsistent sizes.", id)));
+    }
+    pad(&mut blob);
+
+    blob.append(&mut node_data.position);
+    pad(&mut blob);
+
+    blob.append(&mut node_data.color);
+    pad(&mut blob);
+
+    Ok(
+        EncodedNode {
+            num_points: node_data.meta.num_points,
+            position_checksum: node_data.meta.position_checksum,
+            color_checksum: node_data.meta.color_checksum,
+            data: blob,
+        }
+    )
+}
+
+#[derive(Debug)]
+pub struct CacheStats {
+    pub num_hits: u64,
+    pub num_misses: u64,
+    pub num_bytes: usize,
+    pub num_entries: usize,
+}
+
+// Octree id, build id, node id, level of detail and the compression of the data. The build id
+// keeps nodes of an octree that was rebuilt apart from the old ones.
+type NodeKey = (String, u64, octree::NodeId, i32, ContentEncoding);
+
+/// Keeps recently requested encoded nodes of all served octrees in memory, up to a budget in
+/// bytes. Compressed variants of a node share the budget with the uncompressed one.
+pub struct NodeCache {
+    cache: Mutex<LruCache<NodeKey, Arc<EncodedNode>>>,
+}
+
+impl NodeCache {
+    pub fn new(max_bytes: usize) -> Self {
+        NodeCache { cache: Mutex::new(LruCache::new(max_bytes)) }
+    }
+
+    /// Returns the encoded node, reading it from 'octree' if it is not in the cache.
+    pub fn get(
+        &self,
+        octree_id: &str,
+        octree: &octree::Octree,
+        id: &octree::NodeId,
+        level_of_detail: i32,
+    ) -> ::std::result::Result<Arc<EncodedNode>, HttpError> {
+        let key = (
+            octree_id.to_string(),
+            octree.build_id(),
+            *id,
+            level_of_detail,
+            ContentEncoding::Identity,
+        );
+        if let Some(node) = self.cache.lock().unwrap().get(&key) {
+            return Ok(node.clone());
+        }
+        // Reading happens without holding the lock, so that requests for cached nodes are not
+        // blocked by the disk. Two requests might read the same node at the same time, which is
+        // harmless.
+        let node = Arc::new(encode_node(octree, id, level_of_detail)?);
+        self.insert(key, node.clone());
+        Ok(node)
+    }
+
+    /// Returns 'node', which was returned by 'get' for the same arguments, compressed with
+    /// 'encoding'. It is only compressed if it is not in the cache.
+    pub fn get_compressed(
+        &self,
+        octree_id: &str,
+        octree: &octree::Octree,
+        id: &octree::NodeId,
+        level_of_detail: i32,
+        node: &Arc<EncodedNode>,
+        encoding: ContentEncoding,
+    ) -> ::std::result::Result<Arc<EncodedNode>, HttpError> {
+        if encoding == ContentEncoding::Identity {
+            return Ok(node.clone());
+        }
+        let key = (octree_id.to_string(), octree.build_id(), *id, level_of_detail, encoding);
+        // Not counted, the statistics are about nodes read from disk.
+        if let Some(compressed) = self.cache.lock().unwrap().get_uncounted(&key) {
+            // Only valid for the same data, i.e. if the node was not read again meanwhile.
+            if compressed.position_checksum == node.position_checksum &&
+               compressed.color_checksum == node.color_checksum {
+                return Ok(compressed.clone());
+            }
+        }
+        let data = encoding
+            .encode(&node.data)
+            .map_err(|err| HttpError::internal(format!("Could not compress node: {}", err)))?;
+        let compressed = Arc::new(
+            EncodedNode {
+                num_points: node.num_points,
+                position_checksum: node.position_checksum,
+                color_checksum: node.color_checksum,
+                data: data,
+            }
+        );
+        self.insert(key, compressed.clone());
+        Ok(compressed)
+    }
+
+    fn insert(&self, key: NodeKey, node: Arc<EncodedNode>) {
+        let num_bytes = node.data.len();
+        self.cache.lock().unwrap().insert(key, node, num_bytes);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let cache = self.cache.lock().unwrap();
+        CacheStats {
+            num_hits: cache.num_hits(),
+            num_misses: cache.num_misses(),
+            num_bytes: cache.num_bytes(),
+            num_entries: cache.len(),
+        }
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Handlers that return the points in a region, e.g. for measurement tools.
+
+use {HttpError, Metrics, Octrees, invalid_input, parse_numbers, parse_query_value, query_value};
+use iron::{self, IronResult, Plugin, Request, Response};
+use iron::mime::Mime;
+use json;
+use point_viewer::Point;
+use point_viewer::errors::*;
+use point_viewer::math::{Cuboid, Vector3f};
+use point_viewer::octree::{Octree, QueryResult};
+use point_viewer::ply;
+use std::sync::Arc;
+use urlencoded::{QueryMap, UrlEncodedQuery};
+
+const DEFAULT_MAX_POINTS: usize = 10000;
+// Bounds the memory used by a single request.
+const MAX_MAX_POINTS: usize = 1000000;
+
+#[derive(Debug,Clone,Copy)]
+pub enum Kind {
+    // Points inside the box from 'min' to 'max'.
+    Box,
+    // Points at most 'radius' away from 'center'.
+    Sphere,
+    // The closest point at most 'radius' away from the ray from 'origin' in 'direction'.
+    Pick,
+}
+
+#[derive(Debug,Clone,Copy,PartialEq)]
+enum Format {
+    Json,
+    Ply,
+}
+
+fn parse_vector(query: &QueryMap, name: &str) -> Result<Vector3f> {
+    let v = parse_numbers(query, name, 3)?;
+    Ok(Vector3f::new(v[0], v[1], v[2]))
+}
+
+fn parse_radius(query: &QueryMap) -> Result<f32> {
+    let radius: f32 = parse_query_value(query, "radius")?;
+    if !radius.is_finite() || radius < 0. {
+        return Err(invalid_input("'radius' must not be negative."));
+    }
+    Ok(radius)
+}
+
+fn parse_max_points(query: &QueryMap) -> Result<usize> {
+    if !query.contains_key("max_points") {
+        return Ok(DEFAULT_MAX_POINTS);
+    }
+    let max_points: usize = parse_query_value(query, "max_points")?;
+    if max_points == 0 || max_points > MAX_MAX_POINTS {
+        return Err(
+            invalid_input(format!("'max_points' must be between 1 and {}.", MAX_MAX_POINTS)),
+        );
+    }
+    Ok(max_points)
+}
+
+fn parse_format(query: &QueryMap) -> Result<Format> {
+    if !query.contains_key("format") {
+        return Ok(Format::Json);
+    }
+    match query_value(query, "format")? {
+        "json" => Ok(Format::Json),
+        "ply" => Ok(Format::Ply),
+        other => Err(invalid_input(format!("Unknown format '{}'.", other))),
+    }
+}
+
+fn run_query(kind: Kind, octree: &Octree, query: &QueryMap) -> Result<QueryResult> {
+    match kind {
+        Kind::Box => {
+            let min = parse_vector(query, "min")?;
+            let max = parse_vector(query, "max")?;
+            if min.x > max.x || min.y > max.y || min.z > max.z {
+                return Err(invalid_input("'min' must not be larger than 'max'."));
+            }
+            octree.points_in_box(&Cuboid::from_min_max(min, max), parse_max_points(query)?)
+        }
+        Kind::Sphere => {
+            let center = parse_vector(query, "center")?;
+            octree.points_in_sphere(&center, parse_radius(query)?, parse_max_points(query)?)
+        }
+        Kind::Pick => {
+            let origin = parse_vector(query, "origin")?;
+            let direction = parse_vector(query, "direction")?;
+            let point = octree.pick(&origin, &direction, parse_radius(query)?)?;
+            Ok(
+                QueryResult {
+                    points: point.into_iter().collect(),
+                    is_truncated: false,
+                }
+            )
+        }
+    }
+}
+
+fn point_to_json(p: &Point) -> json::JsonValue {
+    let mut reply = json::JsonValue::new_object();
+    reply["position"] = vec![p.position.x, p.position.y, p.position.z].into();
+    reply["color"] = vec![p.r, p.g, p.b].into();
+    reply
+}
+
+/// Serves the points found by a query of 'kind' as JSON or, with 'format=ply', as binary PLY.
+pub struct Points {
+    pub octrees: Arc<Octrees>,
+    pub kind: Kind,
+    pub metrics: Arc<Metrics>,
+}
+
+impl iron::Handler for Points {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let (_, octree) = self.octrees.for_request(req)?;
+        let query = req.get_ref::<UrlEncodedQuery>()
+            .ok()
+            .cloned()
+            .unwrap_or_default();
+        let format = parse_format(&query).map_err(HttpError::from)?;
+        let result = {
+            let octree = octree.read().unwrap();
+            run_query(self.kind, &octree, &query).map_err(HttpError::from)?
+        };
+        self.metrics.record_points(result.points.len() as u64);
+
+        let mut response = match format {
+            Format::Json => {
+                let mut reply = json::JsonValue::new_object();
+                match self.kind {
+                    Kind::Pick => {
+                        reply["point"] = match result.points.first() {
+                            Some(p) => point_to_json(p),
+                            None => json::JsonValue::Null,
+                        };
+                    }
+                    Kind::Box | Kind::Sphere => {
+                        let points: Vec<_> = result.points.iter().map(point_to_json).collect();
+                        reply["points"] = points.into();
+                        reply["is_truncated"] = result.is_truncated.into();
+                    }
+                }
+                let content_type = "application/json".parse::<Mime>().unwrap();
+                Response::with((content_type, iron::status::Ok, reply.dump()))
+            }
+            Format::Ply => {
+                let mut data = Vec::new();
+                ply::write_points(&mut data, ply::Encoding::BinaryLittleEndian, &result.points)
+                    .map_err(|err| HttpError::internal(format!("Could not write PLY: {}", err)))?;
+                let content_type = "application/octet-stream".parse::<Mime>().unwrap();
+                Response::with((content_type, iron::status::Ok, data))
+            }
+        };
+        // PLY has no place for it, so this is also sent as header.
+        let is_truncated = result.is_truncated.to_string().into_bytes();
+        response
+            .headers
+            .set_raw("X-Points-Truncated", vec![is_truncated]);
+        Ok(response)
+    }
+}
@@ -0,0 +1,479 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Streams node data over a WebSocket, so that clients do not need a round trip for every camera
+//! move.
+//!
+//! The client connects to '/' for the default octree or to '/octrees/<id>', with
+//! '?access_token=<token>' if the server requires one, and sends the camera as text message
+//! whenever it changes:
+//!
+//!   {"width": 800, "height": 600, "matrix": [16 numbers, column major], "use_lod": true}
+//!
+//! For every camera, the server replies with the visible nodes as text message
+//!
+//!   {"type": "visible", "generation": 1, "nodes": [["r", 1], ["r4", 2], ...]}
+//!
+//! and then sends the nodes the client does not have yet as binary messages, largest on screen
+//! first. Each consists of the level of detail and the length of the node id as little endian
+//! u32, the id padded to 4 bytes, followed by the node in the format of '/nodes_data'. Once all
+//! nodes are sent, '{"type": "done", "generation": 1}' follows. A new camera cancels the nodes
+//! that are not sent yet. The client can drop all nodes not in the latest visible list, the server
+//! sends them again once they become visible again. Errors are reported as
+//! '{"type": "error", "error": <message>}'.
+//!
+//! The client acknowledges every binary message with '{"type": "received"}'. The server only has
+//! a few unacknowledged nodes in flight, so that the nodes do not pile up in the send queue and a
+//! new camera cancels everything that is not sent yet.
+
+use {AccessToken, HttpError, Metrics, NodeCache, Octrees, invalid_input, matrix_from_column_major};
+use byteorder::{LittleEndian, WriteBytesExt};
+use json;
+use metrics::STREAMING_ROUTE;
+use node_cache::pad;
+use point_viewer::errors::*;
+use point_viewer::math::Matrix4f;
+use point_viewer::octree::{self, NodeId, Octree};
+use std::collections::HashSet;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::str;
+use std::sync::{Arc, Condvar, Mutex, RwLock, mpsc};
+use std::thread::{self, JoinHandle};
+use ws;
+
+// The number of nodes sent before waiting for the client to acknowledge them.
+const MAX_NODES_IN_FLIGHT: usize = 4;
+
+#[derive(Debug,Clone)]
+struct Camera {
+    matrix: Matrix4f,
+    width: i32,
+    height: i32,
+    use_lod: bool,
+}
+
+impl Camera {
+    fn from_json(message: &str) -> Result<Self> {
+        let camera = json::parse(message)
+            .map_err(|_| invalid_input("The camera is not valid JSON."))?;
+        let width = camera["width"].as_i32().unwrap_or(0);
+        let height = camera["height"].as_i32().unwrap_or(0);
+        if width <= 0 || height <= 0 {
+            return Err(invalid_input("'width' and 'height' must be positive."));
+        }
+        let entries: Vec<f32> = camera["matrix"]
+            .members()
+            .filter_map(|entry| entry.as_f32())
+            .filter(|entry| entry.is_finite())
+            .collect();
+        if !camera["matrix"].is_array() || camera["matrix"].len() != 16 || entries.len() != 16 {
+            return Err(invalid_input("'matrix' must have 16 finite entries."));
+        }
+        let use_lod = match camera["use_lod"].as_bool() {
+            Some(use_lod) => use_lod,
+            None => return Err(invalid_input("'use_lod' must be true or false.")),
+        };
+        Ok(
+            Camera {
+                matrix: matrix_from_column_major(&entries),
+                width: width,
+                height: height,
+                use_lod: use_lod,
+            }
+        )
+    }
+}
+
+/// Shared between a connection and the thread streaming its nodes.
+struct State {
+    camera: Option<Camera>,
+    // Incremented for every camera.
+    generation: u64,
+    // Nodes sent, but not yet acknowledged by the client.
+    nodes_in_flight: usize,
+    is_closed: bool,
+}
+
+impl State {
+    fn new() -> Self {
+        State {
+            camera: None,
+            generation: 0,
+            nodes_in_flight: 0,
+            is_closed: false,
+        }
+    }
+}
+
+type SharedState = Arc<(Mutex<State>, Condvar)>;
+
+/// Waits until fewer than MAX_NODES_IN_FLIGHT nodes are unacknowledged. Returns false if the
+/// camera changed or the connection was closed in the meantime.
+fn wait_for_acknowledgements(state: &SharedState, generation: u64) -> bool {
+    let &(ref lock, ref has_changed) = &**state;
+    let mut state = lock.lock().unwrap();
+    while !state.is_closed && state.generation == generation &&
+          state.nodes_in_flight >= MAX_NODES_IN_FLIGHT {
+        state = has_changed.wait(state).unwrap();
+    }
+    !state.is_closed && state.generation == generation
+}
+
+fn is_acknowledgement(message: &str) -> bool {
+    json::parse(message)
+        .map(|message| message["type"].as_str() == Some("received"))
+        .unwrap_or(false)
+}
+
+fn error_message(message: &str) -> String {
+    let mut reply = json::JsonValue::new_object();
+    reply["type"] = "error".into();
+    reply["error"] = message.into();
+    reply.dump()
+}
+
+/// Decodes a value of the query string like the HTTP server does, i.e. '+' is a space and '%XX'
+/// is the byte XX. Malformed escapes are kept as they are.
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = if bytes[i] == b'%' && i + 2 < bytes.len() {
+            str::from_utf8(&bytes[i + 1..i + 3])
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        } else {
+            None
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn encode_message(id: &NodeId, level_of_detail: i32, node: &[u8]) -> Vec<u8> {
+    let id = id.to_string();
+    let mut message = Vec::with_capacity(node.len() + id.len() + 12);
+    message
+        .write_u32::<LittleEndian>(level_of_detail as u32)
+        .unwrap();
+    message.write_u32::<LittleEndian>(id.len() as u32).unwrap();
+    message.extend_from_slice(id.as_bytes());
+    pad(&mut message);
+    message.extend_from_slice(node);
+    message
+}
+
+/// Streams the visible nodes of 'octree' for the latest camera in 'state' to 'out' until the
+/// connection is closed.
+fn stream_nodes(
+    out: ws::Sender,
+    octree_id: String,
+    octree: Arc<RwLock<Octree>>,
+    cache: Arc<NodeCache>,
+    metrics: Arc<Metrics>,
+    state: SharedState,
+) -> ws::Result<()> {
+    // The nodes the client has, by id and level of detail.
+    let mut sent = HashSet::<(NodeId, i32)>::new();
+    let mut current_generation = 0;
+    loop {
+        let camera = {
+            let &(ref lock, ref has_changed) = &*state;
+            let mut state = lock.lock().unwrap();
+            while !state.is_closed && state.generation == current_generation {
+                state = has_changed.wait(state).unwrap();
+            }
+            if state.is_closed {
+                return Ok(());
+            }
+            current_generation = state.generation;
+            state.camera.clone().unwrap()
+        };
+
+        let visible = octree
+            .read()
+            .unwrap()
+            .get_visible_nodes(
+                &camera.matrix,
+                camera.width,
+                camera.height,
+                if camera.use_lod {
+                    octree::UseLod::Yes
+                } else {
+                    octree::UseLod::No
+                },
+            );
+        let visible_keys: HashSet<_> = visible
+            .iter()
+            .map(|node| (node.id, node.level_of_detail))
+            .collect();
+        // The client drops the nodes that are not in the visible list.
+        sent.retain(|key| visible_keys.contains(key));
+
+        let mut reply = json::JsonValue::new_object();
+        reply["type"] = "visible".into();
+        reply["generation"] = current_generation.into();
+        reply["nodes"] = json::JsonValue::new_array();
+        for node in &visible {
+            let entry = vec![
+                json::JsonValue::from(node.id.to_string()),
+                node.level_of_detail.into(),
+            ];
+            reply["nodes"].push(entry).unwrap();
+        }
+        let reply = reply.dump();
+        metrics.record_bytes(reply.len() as u64);
+        out.send(reply)?;
+
+        let mut is_cancelled = false;
+        // 'get_visible_nodes' returns the nodes with the most pixels first.
+        for node in &visible {
+            let key = (node.id, node.level_of_detail);
+            if sent.contains(&key) {
+                continue;
+            }
+            if !wait_for_acknowledgements(&state, current_generation) {
+                is_cancelled = true;
+                break;
+            }
+            let encoded = {
+                let octree = octree.read().unwrap();
+                cache.get(&octree_id, &octree, &node.id, node.level_of_detail)
+            };
+            match encoded {
+                Ok(encoded) => {
+                    let message = encode_message(&node.id, node.level_of_detail, &encoded.data);
+                    metrics.record_nodes(1, encoded.num_points as u64);
+                    metrics.record_bytes(message.len() as u64);
+                    // Counted before sending, otherwise the acknowledgement could arrive first.
+                    state.0.lock().unwrap().nodes_in_flight += 1;
+                    out.send(message)?;
+                    sent.insert(key);
+                }
+                Err(err) => {
+                    metrics.record_error(STREAMING_ROUTE);
+                    out.send(error_message(&err.message))?
+                }
+            }
+        }
+        if !is_cancelled {
+            let mut reply = json::JsonValue::new_object();
+            reply["type"] = "done".into();
+            reply["generation"] = current_generation.into();
+            let reply = reply.dump();
+            metrics.record_bytes(reply.len() as u64);
+            out.send(reply)?;
+        }
+    }
+}
+
+struct Connection {
+    out: ws::Sender,
+    octrees: Arc<Octrees>,
+    cache: Arc<NodeCache>,
+    access_token: Option<Arc<AccessToken>>,
+    metrics: Arc<Metrics>,
+    // Set once the octree is known from the request path.
+    octree: Option<(String, Arc<RwLock<Octree>>)>,
+    state: SharedState,
+}
+
+impl Connection {
+    fn close_state(&self) {
+        let &(ref lock, ref has_changed) = &*self.state;
+        lock.lock().unwrap().is_closed = true;
+        has_changed.notify_all();
+    }
+}
+
+impl ws::Handler for Connection {
+    fn on_request(&mut self, req: &ws::Request) -> ws::Result<ws::Response> {
+        let mut parts = req.resource().splitn(2, '?');
+        let resource = parts.next().unwrap().trim_end_matches('/');
+        if let Some(ref access_token) = self.access_token {
+            let query_token = parts
+                .next()
+                .unwrap_or("")
+                .split('&')
+                .find(|parameter| parameter.starts_with("access_token="))
+                .map(|parameter| percent_decode(&parameter["access_token=".len()..]));
+            let authorization = req.header("Authorization").map(|value| value.as_slice());
+            let query_token = query_token.as_ref().map(|token| token.as_str());
+            if let Err(err) = access_token.verify(authorization, query_token) {
+                return Ok(ws::Response::new(401, "Unauthorized", err.message.into_bytes()));
+            }
+        }
+        let id = if resource.is_empty() {
+            None
+        } else if resource.starts_with("/octrees/") {
+            Some(&resource["/octrees/".len()..])
+        } else {
+            return Ok(ws::Response::new(404, "Not Found", b"Unknown path.".to_vec()));
+        };
+        match self.octrees.find(id) {
+            Ok(octree) => self.octree = Some(octree),
+            Err(err) => return Ok(ws::Response::new(404, "Not Found", err.message.into_bytes())),
+        }
+        ws::Response::from_request(req)
+    }
+
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        let (octree_id, octree) = self.octree.clone().unwrap();
+        let out = self.out.clone();
+        let cache = self.cache.clone();
+        let metrics = self.metrics.clone();
+        let state = self.state.clone();
+        thread::spawn(
+            move || if let Err(err) =
+                stream_nodes(out.clone(), octree_id, octree, cache, metrics, state) {
+                println!("Streaming nodes failed: {}", err);
+                // Otherwise the client would wait for nodes that never arrive.
+                let _ = out.close(ws::CloseCode::Error);
+            },
+        );
+        Ok(())
+    }
+
+    fn on_message(&mut self, message: ws::Message) -> ws::Result<()> {
+        let camera = match message {
+            ws::Message::Text(ref text) if is_acknowledgement(text) => {
+                let &(ref lock, ref has_changed) = &*self.state;
+                let mut state = lock.lock().unwrap();
+                // Ignores acknowledgements for nodes that were never sent.
+                state.nodes_in_flight = state.nodes_in_flight.saturating_sub(1);
+                has_changed.notify_all();
+                return Ok(());
+            }
+            ws::Message::Text(text) => Camera::from_json(&text).map_err(HttpError::from),
+            ws::Message::Binary(_) => Err(HttpError::bad_request("Expected a camera as text.")),
+        };
+        match camera {
+            Ok(camera) => {
+                let &(ref lock, ref has_changed) = &*self.state;
+                let mut state = lock.lock().unwrap();
+                state.camera = Some(camera);
+                state.generation += 1;
+                has_changed.notify_all();
+                Ok(())
+            }
+            Err(err) => {
+                self.metrics.record_error(STREAMING_ROUTE);
+                self.out.send(error_message(&err.message))
+            }
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Stops the streaming thread.
+        self.close_state();
+    }
+}
+
+/// A WebSocket server for streaming nodes, which runs on its own thread until it is dropped.
+pub struct StreamingServer {
+    address: SocketAddr,
+    sender: ws::Sender,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StreamingServer {
+    /// Listens on 'address' for connections, which can use port 0 to pick a free port. If
+    /// 'access_token' is given, clients need to send it when connecting. The nodes sent are
+    /// recorded in 'metrics'.
+    pub fn start<A: ToSocketAddrs>(
+        address: A,
+        octrees: Arc<Octrees>,
+        cache: Arc<NodeCache>,
+        access_token: Option<Arc<AccessToken>>,
+        metrics: Arc<Metrics>,
+    ) -> ws::Result<Self> {
+        // Resolved here, so that 'address' does not need to be sent to the thread.
+        let addresses: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+        let (started, has_started) = mpsc::channel();
+        let thread = thread::spawn(
+            move || {
+                let socket = ws::WebSocket::new(
+                    move |out| {
+                        Connection {
+                            out: out,
+                            octrees: octrees.clone(),
+                            cache: cache.clone(),
+                            access_token: access_token.clone(),
+                            metrics: metrics.clone(),
+                            octree: None,
+                            state: Arc::new((Mutex::new(State::new()), Condvar::new())),
+                        }
+                    },
+                )
+                        .and_then(|socket| socket.bind(&addresses[..]));
+                let socket = match socket.and_then(
+                    |socket| {
+                        let address = socket.local_addr()?;
+                        Ok((socket, address))
+                    },
+                ) {
+                    Ok((socket, address)) => {
+                        started
+                            .send(Ok((address, socket.broadcaster())))
+                            .unwrap();
+                        socket
+                    }
+                    Err(err) => {
+                        started.send(Err(err.to_string())).unwrap();
+                        return;
+                    }
+                };
+                if let Err(err) = socket.run() {
+                    println!("WebSocket server failed: {}", err);
+                }
+            },
+        );
+        let (address, sender) = has_started
+            .recv()
+            .unwrap()
+            .map_err(|message| ws::Error::new(ws::ErrorKind::Internal, message))?;
+        Ok(
+            StreamingServer {
+                address: address,
+                sender: sender,
+                thread: Some(thread),
+            }
+        )
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for StreamingServer {
+    fn drop(&mut self) {
+        let _ = self.sender.shutdown();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,285 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Terminates TLS in front of a server that only speaks plain TCP, i.e. the HTTP and the
+//! WebSocket server. Iron can serve HTTPS itself, but only with openssl 0.7, which cannot be
+//! linked together with the openssl 0.9 that the build already depends on.
+
+use openssl::ssl::{Ssl, SslContext, SslContextBuilder, SslMethod, SslStream};
+use openssl::x509::X509_FILETYPE_PEM;
+use point_viewer::errors::*;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Every connection takes two threads, further connections are closed right away.
+const MAX_CONNECTIONS: usize = 256;
+
+// Clients that do not finish the handshake in time are disconnected, so that they do not hold on
+// to a connection slot.
+const HANDSHAKE_TIMEOUT_SECS: u64 = 10;
+
+// Connections without data in either direction for this long are closed for the same reason.
+const IDLE_TIMEOUT_SECS: u64 = 120;
+
+const BUFFER_SIZE: usize = 64 * 1024;
+
+// OpenSSL does not allow reading and writing the same session from different threads at the same
+// time, so both directions take turns.
+type SharedStream = Arc<Mutex<SslStream<TcpStream>>>;
+
+// When data was last forwarded in either direction.
+type LastActivity = Arc<Mutex<Instant>>;
+
+/// Counts an accepted connection until it is dropped.
+struct OpenConnection {
+    num_open: Arc<AtomicUsize>,
+}
+
+impl OpenConnection {
+    fn new(num_open: Arc<AtomicUsize>) -> Self {
+        num_open.fetch_add(1, Ordering::SeqCst);
+        OpenConnection { num_open: num_open }
+    }
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        self.num_open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Copies decrypted data from the client to the backend until the client closes the connection
+/// or the connection was idle for 'IDLE_TIMEOUT_SECS'. Waits for data on the underlying 'socket',
+/// which has the idle timeout as read timeout, without holding the session, so that the other
+/// direction can write in the meantime.
+fn client_to_backend(
+    client: &SharedStream,
+    socket: &TcpStream,
+    mut backend: &TcpStream,
+    last_activity: &LastActivity,
+) -> io::Result<()> {
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        match socket.peek(&mut [0]) {
+            Ok(0) => return Ok(()),
+            Ok(_) => (),
+            // Unix reports the timeout as 'WouldBlock', Windows as 'TimedOut'.
+            Err(ref err) if err.kind() == io::ErrorKind::WouldBlock ||
+                            err.kind() == io::ErrorKind::TimedOut => {
+                // The backend might still be sending, e.g. nodes over the WebSocket.
+                if last_activity.lock().unwrap().elapsed() >=
+                   Duration::from_secs(IDLE_TIMEOUT_SECS) {
+                    return Ok(());
+                }
+                continue;
+            }
+            Err(err) => return Err(err),
+        }
+        // A record can hold more data than a single read returns.
+        loop {
+            let (num_bytes, num_pending) = {
+                let mut client = client.lock().unwrap();
+                let num_bytes = client.read(&mut buffer)?;
+                (num_bytes, client.ssl().pending())
+            };
+            if num_bytes == 0 {
+                return Ok(());
+            }
+            backend.write_all(&buffer[..num_bytes])?;
+            *last_activity.lock().unwrap() = Instant::now();
+            if num_pending == 0 {
+                break;
+            }
+        }
+    }
+}
+
+/// Copies data from the backend to the client until the backend closes the connection.
+fn backend_to_client(
+    mut backend: &TcpStream,
+    client: &SharedStream,
+    last_activity: &LastActivity,
+) -> io::Result<()> {
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        let num_bytes = backend.read(&mut buffer)?;
+        if num_bytes == 0 {
+            return Ok(());
+        }
+        {
+            let mut client = client.lock().unwrap();
+            client.write_all(&buffer[..num_bytes])?;
+            client.flush()?;
+        }
+        *last_activity.lock().unwrap() = Instant::now();
+    }
+}
+
+/// Wakes up both directions, which are blocked reading, once either side is done.
+fn close(socket: &TcpStream, backend: &TcpStream) {
+    let _ = socket.shutdown(Shutdown::Both);
+    let _ = backend.shutdown(Shutdown::Both);
+}
+
+/// Copies data in both directions, each on its own thread, until either side closes the
+/// connection.
+fn proxy(client: SslStream<TcpStream>, backend: TcpStream) -> io::Result<()> {
+    let socket = client.get_ref().try_clone()?;
+    let client = Arc::new(Mutex::new(client));
+    let backend = Arc::new(backend);
+    let last_activity = Arc::new(Mutex::new(Instant::now()));
+    let thread = {
+        let socket = socket.try_clone()?;
+        let client = client.clone();
+        let backend = backend.clone();
+        let last_activity = last_activity.clone();
+        thread::spawn(
+            move || {
+                let result = backend_to_client(&backend, &client, &last_activity);
+                // The client direction might hold the session while it waits for the rest of a
+                // record, the notification is only sent if it does not.
+                if let Ok(mut client) = client.try_lock() {
+                    let _ = client.shutdown();
+                }
+                close(&socket, &backend);
+                result
+            },
+        )
+    };
+    let result = client_to_backend(&client, &socket, &backend, &last_activity);
+    close(&socket, &backend);
+    let other_result = thread
+        .join()
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "Forwarding panicked.")));
+    result.and(other_result)
+}
+
+fn handle_connection(context: &SslContext, stream: TcpStream, backend: SocketAddr) -> Result<()> {
+    let timeout = Some(Duration::from_secs(HANDSHAKE_TIMEOUT_SECS));
+    stream
+        .set_read_timeout(timeout)
+        .and_then(|_| stream.set_write_timeout(timeout))
+        .chain_err(|| "Could not set handshake timeout.")?;
+    let ssl = Ssl::new(context).chain_err(|| "Could not create TLS session.")?;
+    let client = ssl.accept(stream)
+        .map_err(|err| format!("TLS handshake failed: {}", err))?;
+    // Only waiting for the client times out, sending data to it blocks as long as it takes.
+    client
+        .get_ref()
+        .set_read_timeout(Some(Duration::from_secs(IDLE_TIMEOUT_SECS)))
+        .and_then(|_| client.get_ref().set_write_timeout(None))
+        .chain_err(|| "Could not set idle timeout.")?;
+    let backend = TcpStream::connect(backend)
+        .chain_err(|| format!("Could not connect to {}.", backend))?;
+    proxy(client, backend).chain_err(|| "Forwarding failed.")
+}
+
+fn load_context(certificate: &Path, key: &Path) -> Result<SslContext> {
+    let mut builder = SslContextBuilder::new(SslMethod::tls())
+        .chain_err(|| "Could not create TLS context.")?;
+    builder
+        .set_certificate_chain_file(certificate)
+        .chain_err(|| format!("Could not load certificate {}.", certificate.display()))?;
+    builder
+        .set_private_key_file(key, X509_FILETYPE_PEM)
+        .chain_err(|| format!("Could not load private key {}.", key.display()))?;
+    builder
+        .check_private_key()
+        .chain_err(|| "The private key does not match the certificate.")?;
+    Ok(builder.build())
+}
+
+/// Accepts TLS connections and forwards their decrypted content to a local server on its own
+/// thread until it is dropped. At most 'MAX_CONNECTIONS' are forwarded at the same time.
+pub struct TlsServer {
+    address: SocketAddr,
+    is_stopped: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TlsServer {
+    /// Listens on 'address', which can use port 0 to pick a free port, and forwards every
+    /// connection to 'backend'. 'certificate' and 'key' are PEM files.
+    pub fn start<A: ToSocketAddrs>(
+        address: A,
+        backend: SocketAddr,
+        certificate: &Path,
+        key: &Path,
+    ) -> Result<Self> {
+        let context = Arc::new(load_context(certificate, key)?);
+        let listener = TcpListener::bind(address)
+            .chain_err(|| "Could not listen for TLS connections.")?;
+        let address = listener.local_addr().chain_err(|| "Could not get listening address.")?;
+        let is_stopped = Arc::new(AtomicBool::new(false));
+        let num_open = Arc::new(AtomicUsize::new(0));
+        let thread = {
+            let is_stopped = is_stopped.clone();
+            thread::spawn(
+                move || for stream in listener.incoming() {
+                    if is_stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let stream = match stream {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            println!("Could not accept TLS connection: {}", err);
+                            continue;
+                        }
+                    };
+                    if num_open.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+                        println!("Closing TLS connection, {} are open already.", MAX_CONNECTIONS);
+                        continue;
+                    }
+                    let connection = OpenConnection::new(num_open.clone());
+                    let context = context.clone();
+                    thread::spawn(
+                        move || {
+                            if let Err(err) = handle_connection(&context, stream, backend) {
+                                println!("TLS connection failed: {}", err);
+                            }
+                            drop(connection);
+                        },
+                    );
+                },
+            )
+        };
+        Ok(
+            TlsServer {
+                address: address,
+                is_stopped: is_stopped,
+                thread: Some(thread),
+            }
+        )
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for TlsServer {
+    fn drop(&mut self) {
+        self.is_stopped.store(true, Ordering::SeqCst);
+        // Wakes up the thread waiting for connections, so that it sees the flag.
+        let _ = TcpStream::connect(self.address);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,114 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks the access token and serving over TLS.
+
+extern crate iron;
+extern crate json;
+extern crate openssl;
+extern crate point_viewer;
+extern crate router;
+extern crate web_viewer;
+extern crate ws;
+
+mod common;
+
+use common::{StreamingClient, TestServer, assert_error, write_test_octree};
+use point_viewer::octree::Octree;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use web_viewer::{AccessToken, Metrics, NodeCache, Octrees, StreamingServer};
+
+fn test_octrees(name: &str) -> Octrees {
+    let mut octrees = Octrees::new();
+    octrees
+        .insert(name, Octree::new(write_test_octree(name)).unwrap())
+        .unwrap();
+    octrees
+}
+
+#[test]
+fn test_access_token() {
+    let name = "web_viewer_test_access_token";
+    let server = TestServer::with_options(test_octrees(name), Some("secret"), false);
+
+    let response = server.send("GET", "/meta", &[], "");
+    assert_eq!(401, response.status);
+    assert_eq!(Some("Bearer"), response.header("WWW-Authenticate"));
+    assert_error(401, server.get("/octrees"));
+    assert_error(401, server.get("/meta?access_token=wrong"));
+    let response = server.send("GET", "/meta", &[("Authorization", "Bearer wrong")], "");
+    assert_eq!(401, response.status);
+    let response = server.send("GET", "/meta", &[("Authorization", "Basic secret")], "");
+    assert_eq!(401, response.status);
+
+    let response = server.send("GET", "/meta", &[("Authorization", "Bearer secret")], "");
+    assert_eq!(200, response.status);
+    assert_eq!(200, server.get("/meta?access_token=secret").0);
+    let (status, body) = server.get(&format!("/octrees/{}/nodes/r?access_token=secret", name));
+    assert_eq!(200, status, "{}", body);
+}
+
+#[test]
+fn test_streaming_access_token() {
+    let name = "web_viewer_test_streaming_access_token";
+    let server = StreamingServer::start(
+        ("127.0.0.1", 0),
+        Arc::new(test_octrees(name)),
+        Arc::new(NodeCache::new(1024 * 1024)),
+        Some(Arc::new(AccessToken::new("secret").unwrap())),
+        Arc::new(Metrics::new()),
+    )
+            .unwrap();
+    assert!(StreamingClient::connect(&format!("ws://{}/", server.address())).is_err());
+    let url = format!("ws://{}/?access_token=wrong", server.address());
+    assert!(StreamingClient::connect(&url).is_err());
+    let url = format!("ws://{}/octrees/{}?access_token=secret", server.address(), name);
+    assert!(StreamingClient::connect(&url).is_ok());
+}
+
+#[test]
+fn test_streaming_access_token_is_decoded() {
+    let name = "web_viewer_test_streaming_access_token_is_decoded";
+    let server = StreamingServer::start(
+        ("127.0.0.1", 0),
+        Arc::new(test_octrees(name)),
+        Arc::new(NodeCache::new(1024 * 1024)),
+        Some(Arc::new(AccessToken::new("a+b/c=").unwrap())),
+        Arc::new(Metrics::new()),
+    )
+            .unwrap();
+    let url = format!("ws://{}/?access_token=a%2Bb%2Fc%3D", server.address());
+    assert!(StreamingClient::connect(&url).is_ok());
+    let url = format!("ws://{}/?access_token=a+b/c=", server.address());
+    assert!(StreamingClient::connect(&url).is_err());
+}
+
+#[test]
+fn test_tls() {
+    let name = "web_viewer_test_tls";
+    let server = TestServer::with_options(test_octrees(name), Some("secret"), true);
+    assert_error(401, server.get("/meta"));
+    let (status, body) = server.get("/meta?access_token=secret");
+    assert_eq!(200, status, "{}", body);
+    assert_eq!(name, json::parse(&body).unwrap()["id"].as_str().unwrap());
+
+    // Plain HTTP is not answered.
+    let mut stream = TcpStream::connect(server.address()).unwrap();
+    write!(stream, "GET /meta?access_token=secret HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    assert!(!response.starts_with(b"HTTP/"));
+}
@@ -0,0 +1,381 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Helpers shared by the integration tests.
+
+// Not every test uses every helper.
+#![allow(dead_code)]
+
+use iron::{self, Iron};
+use json;
+use openssl::ssl::{SSL_VERIFY_NONE, Ssl, SslContextBuilder, SslMethod};
+use point_viewer::Point;
+use point_viewer::math::Vector3f;
+use point_viewer::octree::{Octree, testing};
+use router::Router;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use web_viewer::{self, AccessToken, Metrics, NodeCache, Octrees, TlsServer};
+use ws;
+
+/// Writes an octree with only a root node holding a single point into the temporary directory
+/// 'name'.
+pub fn write_test_octree(name: &str) -> PathBuf {
+    testing::write_test_octree(
+        name,
+        &[("r", vec![testing::red_point(Vector3f::new(0.5, 0.5, 0.5))])],
+    )
+}
+
+/// Serves the test octree with an additional node 'r3' holding the points (0.1, 0.6, 0.6) and
+/// (0.1, 0.6, 0.7).
+pub fn server_with_child_node(name: &str) -> TestServer {
+    let child_points = [0.6, 0.7]
+        .iter()
+        .map(
+            |z| {
+                Point {
+                    position: Vector3f::new(0.1, 0.6, *z),
+                    r: 0,
+                    g: 255,
+                    b: 0,
+                }
+            }
+        )
+        .collect();
+    let directory = testing::write_test_octree(
+        name,
+        &[
+            ("r", vec![testing::red_point(Vector3f::new(0.5, 0.5, 0.5))]),
+            ("r3", child_points),
+        ],
+    );
+    let mut octrees = Octrees::new();
+    octrees
+        .insert(name, Octree::new(&directory).unwrap())
+        .unwrap();
+    TestServer::new(octrees)
+}
+
+/// Returns the self-signed certificate for 'localhost' and its key.
+pub fn test_certificate() -> (PathBuf, PathBuf) {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+    (directory.join("test_certificate.pem"), directory.join("test_key.pem"))
+}
+
+fn exchange<S: Read + Write>(stream: &mut S, request: &[u8]) -> Vec<u8> {
+    stream.write_all(request).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    response
+}
+
+pub struct TestServer {
+    listening: iron::Listening,
+    // Set if the server is only reachable through TLS.
+    tls_server: Option<TlsServer>,
+    pub cache: Arc<NodeCache>,
+}
+
+impl TestServer {
+    /// Serves 'octrees' on a free local port.
+    pub fn new(octrees: Octrees) -> Self {
+        Self::with_options(octrees, None, false)
+    }
+
+    /// Like 'new', but requires 'access_token' if given and serves over TLS with the test
+    /// certificate if 'use_tls' is set.
+    pub fn with_options(octrees: Octrees, access_token: Option<&str>, use_tls: bool) -> Self {
+        let cache = Arc::new(NodeCache::new(1024 * 1024));
+        let access_token = access_token.map(|token| Arc::new(AccessToken::new(token).unwrap()));
+        let mut router = Router::new();
+        web_viewer::add_routes(
+            &mut router,
+            Arc::new(octrees),
+            cache.clone(),
+            access_token,
+            Arc::new(Metrics::new()),
+        );
+        let listening = Iron::new(router).http(("127.0.0.1", 0)).unwrap();
+        let tls_server = if use_tls {
+            let (certificate, key) = test_certificate();
+            Some(TlsServer::start(("127.0.0.1", 0), listening.socket, &certificate, &key).unwrap())
+        } else {
+            None
+        };
+        TestServer {
+            listening: listening,
+            tls_server: tls_server,
+            cache: cache,
+        }
+    }
+
+    /// Serves a single test octree with id 'name'.
+    pub fn with_test_octree(name: &str) -> Self {
+        let mut octrees = Octrees::new();
+        octrees
+            .insert(name, Octree::new(write_test_octree(name)).unwrap())
+            .unwrap();
+        Self::new(octrees)
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        match self.tls_server {
+            Some(ref tls_server) => tls_server.address(),
+            None => self.listening.socket,
+        }
+    }
+
+    /// Sends a request with the additional 'headers' and returns the parsed response.
+    pub fn send(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> HttpResponse {
+        let mut request = Vec::new();
+        write!(
+            request,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n",
+            method,
+            path
+        )
+                .unwrap();
+        for &(name, value) in headers {
+            write!(request, "{}: {}\r\n", name, value).unwrap();
+        }
+        write!(request, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+
+        let mut stream = TcpStream::connect(self.address()).unwrap();
+        let response = if self.tls_server.is_some() {
+            let mut context = SslContextBuilder::new(SslMethod::tls()).unwrap();
+            // The test certificate is self-signed.
+            context.set_verify(SSL_VERIFY_NONE);
+            let mut stream = Ssl::new(&context.build())
+                .unwrap()
+                .connect(stream)
+                .unwrap();
+            exchange(&mut stream, &request)
+        } else {
+            exchange(&mut stream, &request)
+        };
+        HttpResponse::parse(&response)
+    }
+
+    /// Returns the status code and the body of the response.
+    pub fn request(&self, method: &str, path: &str, body: &str) -> (u16, String) {
+        let response = self.send(method, path, &[], body);
+        (response.status, String::from_utf8_lossy(&response.body).into_owned())
+    }
+
+    pub fn get(&self, path: &str) -> (u16, String) {
+        self.request("GET", path, "")
+    }
+
+    pub fn post(&self, path: &str, body: &str) -> (u16, String) {
+        self.request("POST", path, body)
+    }
+}
+
+pub struct HttpResponse {
+    pub status: u16,
+    // Header names are lowercased.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn parse(response: &[u8]) -> Self {
+        let header_end = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .expect("Response without header.");
+        let head = String::from_utf8_lossy(&response[..header_end]).into_owned();
+        let mut lines = head.split("\r\n");
+        // The status line looks like 'HTTP/1.1 400 Bad Request'.
+        let status = lines
+            .next()
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|s| s.parse().ok())
+            .expect("Invalid status line.");
+        let headers = lines
+            .filter_map(
+                |line| {
+                    let index = match line.find(':') {
+                        Some(index) => index,
+                        None => return None,
+                    };
+                    Some(
+                        (
+                            line[..index].trim().to_lowercase(),
+                            line[index + 1..].trim().to_string(),
+                        )
+                    )
+                }
+            )
+            .collect();
+        HttpResponse {
+            status: status,
+            headers: headers,
+            body: response[header_end + 4..].to_vec(),
+        }
+    }
+
+    /// Returns the value of the header 'name', which is case insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_lowercase();
+        self.headers
+            .iter()
+            .find(|&&(ref key, _)| *key == name)
+            .map(|&(_, ref value)| value.as_str())
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        // Otherwise dropping 'listening' waits for the server to finish, which it never does.
+        self.listening.close().unwrap();
+    }
+}
+
+pub fn assert_error(expected_status: u16, (status, body): (u16, String)) {
+    assert_eq!(expected_status, status, "{}", body);
+    let error = json::parse(&body).unwrap();
+    assert!(error["error"].is_string(), "{}", body);
+}
+
+pub const IDENTITY: &'static str = "1,0,0,0,0,1,0,0,0,0,1,0,0,0,0,1";
+
+struct StreamingClientHandler {
+    out: ws::Sender,
+    opened: mpsc::Sender<ws::Result<ws::Sender>>,
+    messages: mpsc::Sender<ws::Message>,
+}
+
+impl ws::Handler for StreamingClientHandler {
+    fn on_open(&mut self, _: ws::Handshake) -> ws::Result<()> {
+        let _ = self.opened.send(Ok(self.out.clone()));
+        Ok(())
+    }
+
+    fn on_message(&mut self, message: ws::Message) -> ws::Result<()> {
+        let _ = self.messages.send(message);
+        Ok(())
+    }
+
+    fn on_error(&mut self, err: ws::Error) {
+        let _ = self.opened.send(Err(err));
+    }
+}
+
+/// A client for the node streaming WebSocket that collects the received messages.
+pub struct StreamingClient {
+    out: ws::Sender,
+    messages: Receiver<ws::Message>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StreamingClient {
+    pub fn connect(url: &str) -> ws::Result<Self> {
+        let (opened, has_opened) = mpsc::channel();
+        let (messages, received) = mpsc::channel();
+        let url = url.to_string();
+        let thread = thread::spawn(
+            move || {
+                let result = ws::connect(
+                    url,
+                    |out| {
+                        StreamingClientHandler {
+                            out: out,
+                            opened: opened.clone(),
+                            messages: messages.clone(),
+                        }
+                    },
+                );
+                if let Err(err) = result {
+                    let _ = opened.send(Err(err));
+                }
+            },
+        );
+        let out = match has_opened.recv() {
+            Ok(out) => out?,
+            Err(_) => {
+                return Err(ws::Error::new(ws::ErrorKind::Internal, "Could not connect."));
+            }
+        };
+        Ok(
+            StreamingClient {
+                out: out,
+                messages: received,
+                thread: Some(thread),
+            }
+        )
+    }
+
+    pub fn send_camera(&self, width: i32, height: i32, matrix: &str, use_lod: bool) {
+        self.send(
+            &format!(
+                "{{\"width\": {}, \"height\": {}, \"matrix\": [{}], \"use_lod\": {}}}",
+                width,
+                height,
+                matrix,
+                use_lod
+            ),
+        );
+    }
+
+    pub fn send(&self, message: &str) {
+        self.out.send(message).unwrap();
+    }
+
+    /// Tells the server that a node was received, so that it sends more.
+    pub fn acknowledge(&self) {
+        self.send("{\"type\": \"received\"}");
+    }
+
+    pub fn receive(&self) -> ws::Message {
+        self.messages
+            .recv_timeout(Duration::from_secs(10))
+            .expect("No message received.")
+    }
+
+    pub fn receive_text(&self) -> json::JsonValue {
+        match self.receive() {
+            ws::Message::Text(text) => json::parse(&text).unwrap(),
+            ws::Message::Binary(_) => panic!("Expected a text message."),
+        }
+    }
+
+    pub fn receive_binary(&self) -> Vec<u8> {
+        match self.receive() {
+            ws::Message::Binary(data) => data,
+            ws::Message::Text(text) => panic!("Expected a binary message, got '{}'.", text),
+        }
+    }
+}
+
+impl Drop for StreamingClient {
+    fn drop(&mut self) {
+        let _ = self.out.close(ws::CloseCode::Normal);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks that node data is compressed as requested by the 'Accept-Encoding' header.
+
+extern crate brotli2;
+extern crate flate2;
+extern crate iron;
+extern crate json;
+extern crate openssl;
+extern crate point_viewer;
+extern crate router;
+extern crate web_viewer;
+extern crate ws;
+
+mod common;
+
+use brotli2::read::BrotliDecoder;
+use common::TestServer;
+use flate2::read::GzDecoder;
+use std::io::Read;
+
+const NODES: &'static str = "[[\"r\", 1]]";
+
+fn gunzip(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    GzDecoder::new(data)
+        .unwrap()
+        .read_to_end(&mut decoded)
+        .unwrap();
+    decoded
+}
+
+fn unbrotli(data: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    BrotliDecoder::new(data)
+        .read_to_end(&mut decoded)
+        .unwrap();
+    decoded
+}
+
+#[test]
+fn test_nodes_data_compression() {
+    let server = TestServer::with_test_octree("web_viewer_test_nodes_data_compression");
+    let identity = server.send("POST", "/nodes_data", &[], NODES);
+    assert_eq!(200, identity.status);
+    assert_eq!(None, identity.header("content-encoding"));
+
+    let gzip = server.send("POST", "/nodes_data", &[("Accept-Encoding", "gzip, deflate")], NODES);
+    assert_eq!(200, gzip.status);
+    assert_eq!(Some("gzip"), gzip.header("content-encoding"));
+    assert_eq!(identity.body, gunzip(&gzip.body));
+
+    let brotli = server.send(
+        "POST",
+        "/nodes_data",
+        &[("Accept-Encoding", "gzip, deflate, br")],
+        NODES,
+    );
+    assert_eq!(200, brotli.status);
+    assert_eq!(Some("br"), brotli.header("content-encoding"));
+    assert_eq!(identity.body, unbrotli(&brotli.body));
+
+    let refused = server.send("POST", "/nodes_data", &[("Accept-Encoding", "gzip;q=0")], NODES);
+    assert_eq!(None, refused.header("content-encoding"));
+    assert_eq!(identity.body, refused.body);
+}
+
+#[test]
+fn test_node_compression() {
+    let server = TestServer::with_test_octree("web_viewer_test_node_compression");
+    let identity = server.send("GET", "/nodes/r", &[], "");
+    assert_eq!(200, identity.status);
+    assert_eq!(Some("Accept-Encoding"), identity.header("vary"));
+
+    let gzip = server.send("GET", "/nodes/r", &[("Accept-Encoding", "gzip")], "");
+    assert_eq!(200, gzip.status);
+    assert_eq!(Some("gzip"), gzip.header("content-encoding"));
+    assert_eq!(identity.body, gunzip(&gzip.body));
+
+    // Each encoding is a different representation and needs its own tag.
+    let gzip_etag = gzip.header("etag").unwrap();
+    assert!(identity.header("etag").unwrap() != gzip_etag);
+    let response = server.send(
+        "GET",
+        "/nodes/r",
+        &[("Accept-Encoding", "gzip"), ("If-None-Match", gzip_etag)],
+        "",
+    );
+    assert_eq!(304, response.status);
+    let response = server.send(
+        "GET",
+        "/nodes/r",
+        &[("Accept-Encoding", "br"), ("If-None-Match", gzip_etag)],
+        "",
+    );
+    assert_eq!(200, response.status);
+    assert_eq!(identity.body, unbrotli(&response.body));
+}
+
+#[test]
+fn test_compressed_node_is_cached() {
+    let server = TestServer::with_test_octree("web_viewer_test_compressed_node_is_cached");
+    let first = server.send("GET", "/nodes/r", &[("Accept-Encoding", "br")], "");
+    let second = server.send("GET", "/nodes/r", &[("Accept-Encoding", "br")], "");
+    assert_eq!(200, second.status);
+    assert_eq!(first.body, second.body);
+
+    // The node is read once. Looking up its compressed variant is not counted.
+    let (status, metrics) = server.get("/metrics");
+    assert_eq!(200, status);
+    assert!(metrics.lines().any(|l| l == "web_viewer_cache_misses_total 1"), "{}", metrics);
+    assert!(metrics.lines().any(|l| l == "web_viewer_cache_hits_total 1"), "{}", metrics);
+    // The node and its compressed variant.
+    assert_eq!(2, server.cache.stats().num_entries);
+}
@@ -0,0 +1,79 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Sends malformed requests to a locally started server and checks that they are answered with
+//! an error instead of crashing the handler.
+
+extern crate iron;
+extern crate json;
+extern crate openssl;
+extern crate point_viewer;
+extern crate router;
+extern crate web_viewer;
+extern crate ws;
+
+mod common;
+
+use common::{IDENTITY, TestServer, assert_error};
+
+#[test]
+fn test_visible_nodes() {
+    let server = TestServer::with_test_octree("web_viewer_test_visible_nodes");
+    let (status, body) = server.get(
+        &format!("/visible_nodes?width=100&height=100&matrix={}&use_lod=1", IDENTITY),
+    );
+    assert_eq!(200, status, "{}", body);
+    assert_eq!("r", json::parse(&body).unwrap()[0][0].as_str().unwrap());
+}
+
+#[test]
+fn test_visible_nodes_bad_requests() {
+    let server = TestServer::with_test_octree("web_viewer_test_visible_nodes_bad_requests");
+    assert_error(400, server.get("/visible_nodes"));
+    assert_error(
+        400,
+        server.get(&format!("/visible_nodes?height=100&matrix={}&use_lod=1", IDENTITY)),
+    );
+    assert_error(
+        400,
+        server.get(&format!("/visible_nodes?width=abc&height=100&matrix={}&use_lod=1", IDENTITY)),
+    );
+    assert_error(
+        400,
+        server.get("/visible_nodes?width=100&height=100&matrix=1,0,0&use_lod=1"),
+    );
+    assert_error(
+        400,
+        server.get(&format!("/visible_nodes?width=100&height=100&matrix={}&use_lod=2", IDENTITY)),
+    );
+}
+
+#[test]
+fn test_nodes_data() {
+    let server = TestServer::with_test_octree("web_viewer_test_nodes_data");
+    let (status, body) = server.post("/nodes_data", "[[\"r\", 1]]");
+    assert_eq!(200, status, "{}", body);
+}
+
+#[test]
+fn test_nodes_data_bad_requests() {
+    let server = TestServer::with_test_octree("web_viewer_test_nodes_data_bad_requests");
+    assert_error(400, server.post("/nodes_data", "not json"));
+    assert_error(400, server.post("/nodes_data", "{\"r\": 1}"));
+    assert_error(400, server.post("/nodes_data", "[[\"x12\", 1]]"));
+    assert_error(400, server.post("/nodes_data", "[[\"r19\", 1]]"));
+    assert_error(400, server.post("/nodes_data", "[[\"r\"]]"));
+    assert_error(400, server.post("/nodes_data", "[[\"r\", 0]]"));
+    assert_error(404, server.post("/nodes_data", "[[\"r\", 1], [\"r7\", 1]]"));
+}
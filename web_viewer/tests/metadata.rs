@@ -0,0 +1,80 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks the endpoints describing the octree and its nodes.
+
+extern crate iron;
+extern crate json;
+extern crate openssl;
+extern crate point_viewer;
+extern crate router;
+extern crate web_viewer;
+extern crate ws;
+
+mod common;
+
+use common::{assert_error, server_with_child_node};
+
+#[test]
+fn test_meta() {
+    let name = "web_viewer_test_meta";
+    let server = server_with_child_node(name);
+    for path in &["/meta".to_string(), format!("/octrees/{}/meta", name)] {
+        let (status, body) = server.get(path);
+        assert_eq!(200, status, "{}", body);
+        let meta = json::parse(&body).unwrap();
+        assert_eq!(name, meta["id"].as_str().unwrap());
+        assert_eq!(1., meta["bounding_cube"]["edge_length"].as_f32().unwrap());
+        assert_eq!(0.001, meta["resolution"].as_f64().unwrap());
+        assert_eq!("1", meta["build_id"].as_str().unwrap());
+        assert_eq!(3, meta["num_points"].as_u64().unwrap());
+        assert_eq!(2, meta["num_nodes"].as_u64().unwrap());
+    }
+    assert_error(404, server.get("/octrees/unknown/meta"));
+}
+
+#[test]
+fn test_hierarchy() {
+    let server = server_with_child_node("web_viewer_test_hierarchy");
+    let (status, body) = server.get("/hierarchy?root=r&depth=1");
+    assert_eq!(200, status, "{}", body);
+    let hierarchy = json::parse(&body).unwrap();
+    assert_eq!("r", hierarchy["root"].as_str().unwrap());
+    let nodes = &hierarchy["nodes"];
+    assert_eq!(2, nodes.len());
+    assert_eq!("r", nodes[0]["id"].as_str().unwrap());
+    assert_eq!(1, nodes[0]["num_points"].as_u64().unwrap());
+    assert!(nodes[0]["has_children"].as_bool().unwrap());
+    assert_eq!("r3", nodes[1]["id"].as_str().unwrap());
+    assert_eq!(2, nodes[1]["num_points"].as_u64().unwrap());
+    assert!(!nodes[1]["has_children"].as_bool().unwrap());
+
+    // Only the root, but clients can see that there is more.
+    let (_, body) = server.get("/hierarchy?depth=0");
+    let hierarchy = json::parse(&body).unwrap();
+    assert_eq!(1, hierarchy["nodes"].len());
+    assert!(hierarchy["nodes"][0]["has_children"].as_bool().unwrap());
+
+    let (_, body) = server.get("/hierarchy?root=r3");
+    assert_eq!("r3", json::parse(&body).unwrap()["nodes"][0]["id"].as_str().unwrap());
+}
+
+#[test]
+fn test_hierarchy_bad_requests() {
+    let server = server_with_child_node("web_viewer_test_hierarchy_bad_requests");
+    assert_error(404, server.get("/hierarchy?root=r7"));
+    assert_error(400, server.get("/hierarchy?root=x"));
+    assert_error(400, server.get("/hierarchy?depth=-1"));
+    assert_error(400, server.get("/hierarchy?depth=100"));
+}
@@ -0,0 +1,68 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks that requests show up in '/metrics'.
+
+extern crate iron;
+extern crate json;
+extern crate openssl;
+extern crate point_viewer;
+extern crate router;
+extern crate web_viewer;
+extern crate ws;
+
+mod common;
+
+use common::TestServer;
+
+fn assert_metric(metrics: &str, line: &str) {
+    assert!(metrics.lines().any(|l| l == line), "'{}' not in:\n{}", line, metrics);
+}
+
+#[test]
+fn test_metrics() {
+    let server = TestServer::with_test_octree("web_viewer_test_metrics");
+    assert_eq!(200, server.get("/meta").0);
+    assert_eq!(200, server.get("/nodes/r").0);
+    assert_eq!(200, server.post("/nodes_data", "[[\"r\", 1]]").0);
+    assert_eq!(400, server.get("/nodes/x").0);
+    assert_eq!(200, server.get("/points/box?min=0,0,0&max=1,1,1").0);
+
+    let response = server.send("GET", "/metrics", &[], "");
+    assert_eq!(200, response.status);
+    assert!(response.header("content-type").unwrap().starts_with("text/plain"));
+    let metrics = String::from_utf8(response.body).unwrap();
+    assert_metric(&metrics, "web_viewer_requests_total{route=\"/meta\",status=\"200\"} 1");
+    assert_metric(
+        &metrics,
+        "web_viewer_requests_total{route=\"/nodes/:node_id\",status=\"200\"} 1",
+    );
+    assert_metric(
+        &metrics,
+        "web_viewer_requests_total{route=\"/nodes/:node_id\",status=\"400\"} 1",
+    );
+    assert_metric(&metrics, "web_viewer_errors_total{route=\"/nodes/:node_id\"} 1");
+    assert_metric(&metrics, "web_viewer_errors_total{route=\"/nodes_data\"} 0");
+    assert_metric(
+        &metrics,
+        "web_viewer_request_duration_seconds_count{route=\"/nodes_data\"} 1",
+    );
+    assert_metric(&metrics, "web_viewer_nodes_served_total 2");
+    // The root node twice and once as result of the query.
+    assert_metric(&metrics, "web_viewer_points_served_total 3");
+    // The root node is only read once.
+    assert_metric(&metrics, "web_viewer_cache_misses_total 1");
+    assert_metric(&metrics, "web_viewer_cache_hits_total 1");
+    assert!(!metrics.contains("route=\"/metrics\""));
+}
@@ -0,0 +1,50 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests that node data is served from the cache.
+
+extern crate iron;
+extern crate json;
+extern crate openssl;
+extern crate point_viewer;
+extern crate router;
+extern crate web_viewer;
+extern crate ws;
+
+mod common;
+
+use common::TestServer;
+
+#[test]
+fn test_nodes_are_cached() {
+    let server = TestServer::with_test_octree("web_viewer_test_nodes_are_cached");
+    let (status, first) = server.post("/nodes_data", "[[\"r\", 1]]");
+    assert_eq!(200, status);
+    let (status, second) = server.post("/nodes_data", "[[\"r\", 1]]");
+    assert_eq!(200, status);
+    assert_eq!(first, second);
+
+    let stats = server.cache.stats();
+    assert_eq!(1, stats.num_misses);
+    assert_eq!(1, stats.num_hits);
+    assert_eq!(1, stats.num_entries);
+
+    // Other levels of detail are different entries.
+    let (status, _) = server.post("/nodes_data", "[[\"r\", 2], [\"r\", 1]]");
+    assert_eq!(200, status);
+    let stats = server.cache.stats();
+    assert_eq!(2, stats.num_misses);
+    assert_eq!(2, stats.num_hits);
+    assert_eq!(2, stats.num_entries);
+}
@@ -0,0 +1,74 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks that single nodes can be fetched and revalidated with their ETag.
+
+extern crate iron;
+extern crate json;
+extern crate openssl;
+extern crate point_viewer;
+extern crate router;
+extern crate web_viewer;
+extern crate ws;
+
+mod common;
+
+use common::{TestServer, assert_error};
+
+#[test]
+fn test_node_has_etag_and_cache_control() {
+    let server = TestServer::with_test_octree("web_viewer_test_node_etag");
+    let response = server.send("GET", "/nodes/r?lod=1", &[], "");
+    assert_eq!(200, response.status);
+    // Same encoding as '/nodes_data' for a single node.
+    let nodes_data = server.send("POST", "/nodes_data", &[], "[[\"r\", 1]]");
+    assert_eq!(nodes_data.body, response.body);
+    assert_eq!(Some("public, no-cache"), response.header("cache-control"));
+    let etag = response.header("etag").expect("No ETag.").to_string();
+
+    let response = server.send("GET", "/nodes/r?lod=1", &[("If-None-Match", &etag)], "");
+    assert_eq!(304, response.status);
+    assert_eq!(Some(etag.as_str()), response.header("etag"));
+    assert!(response.body.is_empty());
+
+    // The level of detail is part of the tag, the default is 1.
+    let response = server.send("GET", "/nodes/r?lod=2", &[("If-None-Match", &etag)], "");
+    assert_eq!(200, response.status);
+    assert!(Some(etag.as_str()) != response.header("etag"));
+    let response = server.send("GET", "/nodes/r", &[("If-None-Match", &etag)], "");
+    assert_eq!(304, response.status);
+
+    let response = server.send("GET", "/nodes/r", &[("If-None-Match", "\"other\"")], "");
+    assert_eq!(200, response.status);
+    let response = server.send("GET", "/nodes/r", &[("If-None-Match", "*")], "");
+    assert_eq!(304, response.status);
+}
+
+#[test]
+fn test_node_per_octree() {
+    let name = "web_viewer_test_node_per_octree";
+    let server = TestServer::with_test_octree(name);
+    let (status, body) = server.get(&format!("/octrees/{}/nodes/r?lod=1", name));
+    assert_eq!(200, status, "{}", body);
+    assert_error(404, server.get("/octrees/unknown/nodes/r?lod=1"));
+}
+
+#[test]
+fn test_node_bad_requests() {
+    let server = TestServer::with_test_octree("web_viewer_test_node_bad_requests");
+    assert_error(404, server.get("/nodes/r7?lod=1"));
+    assert_error(400, server.get("/nodes/x12?lod=1"));
+    assert_error(400, server.get("/nodes/r?lod=0"));
+    assert_error(400, server.get("/nodes/r?lod=abc"));
+}
@@ -0,0 +1,105 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Tests serving more than one octree from one server.
+
+extern crate iron;
+extern crate json;
+extern crate openssl;
+extern crate point_viewer;
+extern crate router;
+extern crate web_viewer;
+extern crate ws;
+
+mod common;
+
+use common::{IDENTITY, TestServer, assert_error, write_test_octree};
+use point_viewer::octree::Octree;
+use std::env;
+use std::fs;
+use web_viewer::Octrees;
+
+fn two_octrees(name: &str) -> Octrees {
+    let mut octrees = Octrees::new();
+    for id in &["b", "a"] {
+        let directory = write_test_octree(&format!("{}_{}", name, id));
+        octrees.insert(id, Octree::new(directory).unwrap()).unwrap();
+    }
+    octrees
+}
+
+#[test]
+fn test_list_octrees() {
+    let server = TestServer::new(two_octrees("web_viewer_test_list_octrees"));
+    let (status, body) = server.get("/octrees");
+    assert_eq!(200, status, "{}", body);
+    let octrees = json::parse(&body).unwrap();
+    assert_eq!(2, octrees.len());
+    assert_eq!("a", octrees[0]["id"].as_str().unwrap());
+    assert_eq!("b", octrees[1]["id"].as_str().unwrap());
+    assert_eq!(1., octrees[1]["bounding_cube"]["edge_length"].as_f32().unwrap());
+    assert_eq!(0., octrees[1]["bounding_cube"]["min"][2].as_f32().unwrap());
+}
+
+#[test]
+fn test_node_endpoints_per_octree() {
+    let server = TestServer::new(two_octrees("web_viewer_test_node_endpoints_per_octree"));
+    let query = format!("width=100&height=100&matrix={}&use_lod=0", IDENTITY);
+    let (status, body) = server.get(&format!("/octrees/a/visible_nodes?{}", query));
+    assert_eq!(200, status, "{}", body);
+    let (status, body) = server.post("/octrees/a/nodes_data", "[[\"r\", 1]]");
+    assert_eq!(200, status, "{}", body);
+    // The first octree added is served without id.
+    let (status, body) = server.get(&format!("/visible_nodes?{}", query));
+    assert_eq!(200, status, "{}", body);
+
+    assert_error(404, server.get(&format!("/octrees/c/visible_nodes?{}", query)));
+    assert_error(404, server.post("/octrees/c/nodes_data", "[[\"r\", 1]]"));
+}
+
+#[test]
+fn test_insert() {
+    let directory = write_test_octree("web_viewer_test_insert");
+    let mut octrees = Octrees::new();
+    assert!(octrees.insert("a/b", Octree::new(&directory).unwrap()).is_err());
+    octrees.insert("a", Octree::new(&directory).unwrap()).unwrap();
+    assert!(octrees.insert("a", Octree::new(&directory).unwrap()).is_err());
+    assert_eq!(1, octrees.len());
+}
+
+#[test]
+fn test_insert_directory() {
+    let directory = write_test_octree("web_viewer_test_insert_directory");
+    fs::create_dir_all(directory.join("subdirectory")).unwrap();
+    let mut octrees = Octrees::new();
+    octrees
+        .insert_directory(&directory.join("subdirectory/.."))
+        .unwrap();
+    assert!(octrees.get("web_viewer_test_insert_directory").is_some());
+}
+
+#[test]
+fn test_insert_root_directory() {
+    let root = env::temp_dir().join("web_viewer_test_insert_root_directory");
+    let _ = fs::remove_dir_all(&root);
+    write_test_octree("web_viewer_test_insert_root_directory/site_1");
+    write_test_octree("web_viewer_test_insert_root_directory/site_2");
+    fs::create_dir_all(root.join("not_an_octree")).unwrap();
+
+    let mut octrees = Octrees::new();
+    assert_eq!(2, octrees.insert_root_directory(&root).unwrap());
+    assert!(octrees.get("site_1").is_some());
+    assert!(octrees.get("site_2").is_some());
+    assert!(octrees.get("not_an_octree").is_none());
+}
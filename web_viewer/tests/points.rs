@@ -0,0 +1,104 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Checks the point query endpoints.
+
+extern crate iron;
+extern crate json;
+extern crate openssl;
+extern crate point_viewer;
+extern crate router;
+extern crate web_viewer;
+extern crate ws;
+
+mod common;
+
+use common::{assert_error, server_with_child_node};
+use point_viewer::InternalIterator;
+use point_viewer::ply::PlyIterator;
+use std::io::Cursor;
+
+fn assert_near(expected: [f32; 3], actual: &json::JsonValue) {
+    for i in 0..3 {
+        let value = actual[i].as_f32().unwrap();
+        assert!((expected[i] - value).abs() < 0.01, "{} != {}", expected[i], value);
+    }
+}
+
+#[test]
+fn test_points_in_box() {
+    let server = server_with_child_node("web_viewer_test_points_in_box");
+    let (status, body) = server.get("/points/box?min=0,0.55,0.55&max=0.2,0.65,0.65");
+    assert_eq!(200, status, "{}", body);
+    let reply = json::parse(&body).unwrap();
+    assert_eq!(1, reply["points"].len());
+    assert_near([0.1, 0.6, 0.6], &reply["points"][0]["position"]);
+    assert_eq!(255, reply["points"][0]["color"][1].as_u8().unwrap());
+    assert!(!reply["is_truncated"].as_bool().unwrap());
+
+    let response = server.send("GET", "/points/box?min=0,0,0&max=1,1,1&max_points=2", &[], "");
+    assert_eq!(200, response.status);
+    let reply = json::parse(&String::from_utf8(response.body.clone()).unwrap()).unwrap();
+    assert_eq!(2, reply["points"].len());
+    assert!(reply["is_truncated"].as_bool().unwrap());
+    assert_eq!(Some("true"), response.header("x-points-truncated"));
+}
+
+#[test]
+fn test_points_in_sphere() {
+    let server = server_with_child_node("web_viewer_test_points_in_sphere");
+    let (status, body) = server.get("/points/sphere?center=0.1,0.6,0.65&radius=0.06");
+    assert_eq!(200, status, "{}", body);
+    assert_eq!(2, json::parse(&body).unwrap()["points"].len());
+}
+
+#[test]
+fn test_pick() {
+    let name = "web_viewer_test_pick";
+    let server = server_with_child_node(name);
+    let (status, body) = server.get(
+        &format!("/octrees/{}/points/pick?origin=0.1,0.6,0&direction=0,0,1&radius=0.01", name),
+    );
+    assert_eq!(200, status, "{}", body);
+    assert_near([0.1, 0.6, 0.6], &json::parse(&body).unwrap()["point"]["position"]);
+
+    let (status, body) = server.get("/points/pick?origin=0.1,0.6,0&direction=0,0,-1&radius=0.01");
+    assert_eq!(200, status, "{}", body);
+    assert!(json::parse(&body).unwrap()["point"].is_null());
+}
+
+#[test]
+fn test_points_as_ply() {
+    let server = server_with_child_node("web_viewer_test_points_as_ply");
+    let response = server.send("GET", "/points/box?min=0,0,0&max=1,1,1&format=ply", &[], "");
+    assert_eq!(200, response.status);
+    let mut num_points = 0;
+    PlyIterator::from_reader(Box::new(Cursor::new(response.body)), "response")
+        .unwrap()
+        .for_each(|_| num_points += 1)
+        .unwrap();
+    assert_eq!(3, num_points);
+}
+
+#[test]
+fn test_points_bad_requests() {
+    let server = server_with_child_node("web_viewer_test_points_bad_requests");
+    assert_error(400, server.get("/points/box?min=1,1,1&max=0,0,0"));
+    assert_error(400, server.get("/points/box?min=0,0&max=1,1,1"));
+    assert_error(400, server.get("/points/box?min=0,0,0&max=1,1,1&max_points=0"));
+    assert_error(400, server.get("/points/box?min=0,0,0&max=1,1,1&format=xml"));
+    assert_error(400, server.get("/points/sphere?center=0,0,0"));
+    assert_error(400, server.get("/points/sphere?center=0,0,0&radius=-1"));
+    assert_error(400, server.get("/points/pick?origin=0,0,0&direction=0,0,0&radius=1"));
+}
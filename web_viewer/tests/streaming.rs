@@ -0,0 +1,165 @@
+// Copyright 2016 The Cartographer Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//      http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Streams nodes over a WebSocket with the test client.
+
+extern crate iron;
+extern crate json;
+extern crate openssl;
+extern crate point_viewer;
+extern crate router;
+extern crate web_viewer;
+extern crate ws;
+
+mod common;
+
+use common::{IDENTITY, StreamingClient, write_test_octree};
+use point_viewer::math::{CuboidLike, Vector3f};
+use point_viewer::octree::{NodeId, Octree, testing};
+use std::path::Path;
+use std::sync::Arc;
+use web_viewer::{Metrics, NodeCache, Octrees, StreamingServer};
+
+// Moves the octree out of the view.
+const FAR_AWAY: &'static str = "1,0,0,0,0,1,0,0,0,0,1,0,100,0,0,1";
+
+fn serve(name: &str, directory: &Path) -> StreamingServer {
+    let mut octrees = Octrees::new();
+    octrees
+        .insert(name, Octree::new(directory).unwrap())
+        .unwrap();
+    StreamingServer::start(
+        ("127.0.0.1", 0),
+        Arc::new(octrees),
+        Arc::new(NodeCache::new(1024 * 1024)),
+        None,
+        Arc::new(Metrics::new()),
+    )
+            .unwrap()
+}
+
+fn start_server(name: &str) -> (StreamingServer, Vec<u8>) {
+    let directory = write_test_octree(name);
+    let root = NodeCache::new(1024 * 1024)
+        .get(name, &Octree::new(&directory).unwrap(), &NodeId::from_str("r"), 1)
+        .unwrap();
+    (serve(name, &directory), root.data.clone())
+}
+
+fn assert_message(client: &StreamingClient, message_type: &str, generation: u64) {
+    let message = client.receive_text();
+    assert_eq!(message_type, message["type"].as_str().unwrap(), "{}", message);
+    assert_eq!(generation, message["generation"].as_u64().unwrap());
+}
+
+fn assert_root_node(client: &StreamingClient, expected: &[u8]) {
+    let data = client.receive_binary();
+    // Level of detail 1, id length 1, "r" padded to 4 bytes.
+    assert_eq!(&[1, 0, 0, 0, 1, 0, 0, 0, b'r', 0, 0, 0], &data[..12]);
+    assert_eq!(expected, &data[12..]);
+    client.acknowledge();
+}
+
+#[test]
+fn test_streaming() {
+    let name = "web_viewer_test_streaming";
+    let (server, root) = start_server(name);
+    let client = StreamingClient::connect(&format!("ws://{}/", server.address())).unwrap();
+
+    client.send_camera(100, 100, IDENTITY, false);
+    let visible = client.receive_text();
+    assert_eq!("visible", visible["type"].as_str().unwrap());
+    assert_eq!(1, visible["generation"].as_u64().unwrap());
+    assert_eq!(1, visible["nodes"].len());
+    assert_eq!("r", visible["nodes"][0][0].as_str().unwrap());
+    assert_root_node(&client, &root);
+    assert_message(&client, "done", 1);
+
+    // The client already has all visible nodes.
+    client.send_camera(100, 100, IDENTITY, false);
+    assert_message(&client, "visible", 2);
+    assert_message(&client, "done", 2);
+
+    // Nodes that are no longer visible are sent again once they are.
+    client.send_camera(100, 100, FAR_AWAY, false);
+    let visible = client.receive_text();
+    assert_eq!(0, visible["nodes"].len());
+    assert_message(&client, "done", 3);
+    client.send_camera(100, 100, IDENTITY, false);
+    assert_message(&client, "visible", 4);
+    assert_root_node(&client, &root);
+    assert_message(&client, "done", 4);
+
+    let client = StreamingClient::connect(&format!("ws://{}/octrees/{}", server.address(), name))
+        .unwrap();
+    client.send_camera(100, 100, IDENTITY, true);
+    assert_message(&client, "visible", 1);
+}
+
+#[test]
+fn test_streaming_bad_requests() {
+    let (server, _) = start_server("web_viewer_test_streaming_bad_requests");
+    let client = StreamingClient::connect(&format!("ws://{}/", server.address())).unwrap();
+    for camera in &[
+        "not json",
+        "{}",
+        "{\"width\": 100, \"height\": 100, \"matrix\": [1, 0, 0], \"use_lod\": true}",
+    ] {
+        client.send(camera);
+        let error = client.receive_text();
+        assert_eq!("error", error["type"].as_str().unwrap());
+        assert!(error["error"].is_string());
+    }
+    client.send_camera(0, 100, IDENTITY, true);
+    assert_eq!("error", client.receive_text()["type"].as_str().unwrap());
+
+    let url = format!("ws://{}/octrees/unknown", server.address());
+    assert!(StreamingClient::connect(&url).is_err());
+}
+
+#[test]
+fn test_new_camera_cancels_unsent_nodes() {
+    let name = "web_viewer_test_new_camera_cancels_unsent_nodes";
+    // The root node and its 8 children, each holding a point at its center.
+    let ids: Vec<String> = (0..8).map(|index| format!("r{}", index)).collect();
+    let mut nodes = vec![("r", vec![testing::red_point(Vector3f::new(0.5, 0.5, 0.5))])];
+    for id in &ids {
+        let cube = testing::test_node(id).bounding_cube;
+        nodes.push((id.as_str(), vec![testing::red_point((cube.min() + cube.max()) / 2.)]));
+    }
+    let server = serve(name, &testing::write_test_octree(name, &nodes));
+    let client = StreamingClient::connect(&format!("ws://{}/", server.address())).unwrap();
+
+    client.send_camera(100, 100, IDENTITY, false);
+    assert_eq!(9, client.receive_text()["nodes"].len());
+    // The server waits for acknowledgements after 4 nodes.
+    for _ in 0..4 {
+        client.receive_binary();
+    }
+    client.send_camera(100, 100, FAR_AWAY, false);
+    // The other nodes of the first camera are never sent.
+    assert_message(&client, "visible", 2);
+    assert_message(&client, "done", 2);
+
+    for _ in 0..4 {
+        client.acknowledge();
+    }
+    client.send_camera(100, 100, IDENTITY, false);
+    assert_message(&client, "visible", 3);
+    for _ in 0..9 {
+        client.receive_binary();
+        client.acknowledge();
+    }
+    assert_message(&client, "done", 3);
+}